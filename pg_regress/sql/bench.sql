@@ -0,0 +1,39 @@
+-- BENCHMARKS
+-- Run after setup.sql, with \timing on, to compare the predicates on hip_table.
+-- The MOC/BMOC/regions are constants so they are only deserialized/built once per scan (see src/cache.rs).
+-- Each cached query is followed by the same query taking its argument from a column of bench_shapes (a single row),
+-- which disables the cache : the difference of the execution times is the speedup.
+
+CREATE TEMPORARY TABLE bench_shapes AS SELECT
+    mgx_bmoc_cone_coverage_approx(8, 0.01814144, 3.94648893, 5.64323) AS bmoc,
+    mgx_moc_from_cone(0.01814144, 3.94648893, 5.64323, 8, 2, 'All') AS moc;
+
+\timing on
+
+-- BMOC contains
+EXPLAIN ANALYZE SELECT count(*) FROM hip_table
+WHERE mgx_bmoc_contains_bool(mgx_bmoc_cone_coverage_approx(8, 0.01814144, 3.94648893, 5.64323), raicrs, deicrs);
+EXPLAIN ANALYZE SELECT count(*) FROM hip_table, bench_shapes s
+WHERE mgx_bmoc_contains_bool(s.bmoc, raicrs, deicrs);
+
+-- MOC contains
+EXPLAIN ANALYZE SELECT count(*) FROM hip_table
+WHERE mgx_is_in_moc(mgx_moc_from_cone(0.01814144, 3.94648893, 5.64323, 8, 2, 'All'), raicrs, deicrs);
+EXPLAIN ANALYZE SELECT count(*) FROM hip_table, bench_shapes s
+WHERE mgx_is_in_moc(s.moc, raicrs, deicrs);
+
+-- MOC contains, array variant
+EXPLAIN ANALYZE SELECT mgx_is_in_moc(mgx_moc_from_cone(0.01814144, 3.94648893, 5.64323, 8, 2, 'All'), array_agg(raicrs), array_agg(deicrs))
+FROM hip_table;
+
+-- Skyregion predicates
+EXPLAIN ANALYZE SELECT count(*) FROM hip_table
+WHERE mgx_skyregion_cone_contains(0.01814144, 3.94648893, 5.64323, raicrs, deicrs);
+
+EXPLAIN ANALYZE SELECT count(*) FROM hip_table
+WHERE mgx_skyregion_elliptical_cone_contains(0.01814144, 3.94648893, 1.6433, 4, 1.6, raicrs, deicrs);
+
+EXPLAIN ANALYZE SELECT count(*) FROM hip_table
+WHERE mgx_skyregion_polygon_contains(
+    ARRAY[mgx_create_vertexpsql(0.01814144, 3.94648893), mgx_create_vertexpsql(2.57489321, 5.38601839), mgx_create_vertexpsql(7.57489321, 3.38601839)],
+    false, raicrs, deicrs);
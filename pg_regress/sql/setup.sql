@@ -62,6 +62,15 @@ COPY tyc2 FROM '/home/mlapointe/Documents/Ressources/csv/tycho.csv' (FORMAT csv,
 -- Creation of an index on hpx_hash(29, raicrs, deicrs) for tyc2
 CREATE INDEX mgx_hash_tyc2_idx ON tyc2 (mgx_hash(29, ra_icrs_, de_icrs_));
 
+-- The indexes can also be created with mgx_healpix_index_table, which adds a generated hash column,
+-- creates the btree and GiST indexes and registers the table in mgx_indexed_tables, e.g.
+-- SELECT mgx_healpix_index_table('tyc2', 'ra_icrs_', 'de_icrs_', 29, 'healpix_hash', true);
+
+-- Registration of the tables indexed by hand above
+SELECT mgx_register_indexed_table('hip_table', 'raicrs', 'deicrs', 29,
+    hash_index => 'mgx_hash_hip_idx', range_index => 'mgx_hash_range_hip_idx');
+SELECT mgx_register_indexed_table('tyc2', 'ra_icrs_', 'de_icrs_', 29, hash_index => 'mgx_hash_tyc2_idx');
+
 -- FUNCTIONS
 
 -- int8range[] -> int8multirange
@@ -123,6 +132,7 @@ $$ LANGUAGE plpgsql IMMUTABLE STRICT;
 -- Returns true if the cell (test_lon_deg, test_lat_deg) is in the cone created with the coordinates (lon_deg, lat_deg, radius_deg)
 -- Uses the function hpx_contains_bool(...) to select only the cells in the BMOC
 -- UNPRECISE
+-- The coverage (and its depth, chosen for a table of 10^7 rows) is computed once per call in the subquery
 CREATE OR REPLACE FUNCTION mgx_in_cone_hpx(
     lon_deg double precision,
     lat_deg double precision,
@@ -132,216 +142,22 @@ CREATE OR REPLACE FUNCTION mgx_in_cone_hpx(
 RETURNS boolean AS 
 $$
     SELECT 
-    mgx_hash_range(29, test_lon_deg, test_lat_deg)
-    <@
-    mgx_to_int8multirange(
-        mgx_bmoc_flag_one(
-            mgx_bmoc_cone_coverage_approx(
-                mgx_best_starting_depth(radius_deg)+4, lon_deg, lat_deg, radius_deg)))
-    OR
-    ((mgx_hash_range(29, test_lon_deg, test_lat_deg)
-    <@
-    mgx_to_int8multirange(
-        mgx_bmoc_flag_zero(
-            mgx_bmoc_cone_coverage_approx(
-                mgx_best_starting_depth(radius_deg)+4, lon_deg, lat_deg, radius_deg))))
-        AND
-        (mgx_bmoc_contains_bool(
-            mgx_bmoc_cone_coverage_approx(
-                mgx_best_starting_depth(5.64323)+4, 0.01814144, 3.94648893, 5.64323),
-                test_lon_deg,
-                test_lat_deg)))
-$$
-LANGUAGE sql;
-
--- Returns true if the cell (test_lon_deg, test_lat_deg) is in the cone created with the coordinates (lon_deg, lat_deg, radius_deg)
--- Uses the function skyregion::cone::contains(...) to select only the cells in the BMOC
--- VERY PRECISE
-CREATE OR REPLACE FUNCTION mgx_in_cone(
-    lon_deg double precision,
-    lat_deg double precision,
-    radius_deg double precision,
-    test_lon_deg double precision,
-    test_lat_deg double precision)
-RETURNS boolean AS 
-$$
-    SELECT 
-    mgx_hash_range(29, test_lon_deg, test_lat_deg)
-    <@
-    mgx_to_int8multirange(
-        mgx_bmoc_flag_one(
-            mgx_bmoc_cone_coverage_approx(
-                mgx_best_starting_depth(radius_deg)+4, lon_deg, lat_deg, radius_deg)))
-    OR
-    ((mgx_hash_range(29, test_lon_deg, test_lat_deg)
-    <@
-    mgx_to_int8multirange(
-        mgx_bmoc_flag_zero(
-            mgx_bmoc_cone_coverage_approx(
-                mgx_best_starting_depth(radius_deg)+4, lon_deg, lat_deg, radius_deg))))
-        AND
-        (mgx_skyregion_cone_contains(
-            lon_deg, lat_deg, radius_deg, test_lon_deg, test_lat_deg)))
-$$
-LANGUAGE sql;
-
--- Returns true if the cell (test_lon_deg, test_lat_deg) is in the elliptical cone given in the parameters
--- Uses the function skyregion::ellipticalcone::contains(...) to select only the cells in the BMOC
--- VERY PRECISE
-CREATE OR REPLACE FUNCTION mgx_in_elliptical_cone(
-    lon_deg double precision,
-    lat_deg double precision,
-    a_deg double precision,
-    b_deg double precision,
-    pa_deg double precision,
-    test_lon_deg double precision,
-    test_lat_deg double precision)
-RETURNS boolean AS 
-$$
-    SELECT 
-    mgx_hash_range(29, test_lon_deg, test_lat_deg)
-    <@
-    mgx_to_int8multirange(
-        mgx_bmoc_flag_one(
-            mgx_bmoc_elliptical_cone_coverage(
-                mgx_best_starting_depth(a_deg)+4, lon_deg, lat_deg, a_deg, b_deg, pa_deg)))
-    OR
-    ((mgx_hash_range(29, test_lon_deg, test_lat_deg)
-    <@
-    mgx_to_int8multirange(
-        mgx_bmoc_flag_zero(
-            mgx_bmoc_elliptical_cone_coverage(
-                mgx_best_starting_depth(a_deg)+4, lon_deg, lat_deg, a_deg, b_deg, pa_deg))))
-        AND
-        (mgx_skyregion_elliptical_cone_contains(lon_deg, lat_deg, a_deg, b_deg, pa_deg, test_lon_deg, test_lat_deg)))
-$$
-LANGUAGE sql;
-
--- Returns true if the cell (test_lon_deg, test_lat_deg) is in the zone created with the coordinates given in the parameters
--- Uses the function skyregion::zone::contains(...) to select only the cells in the BMOC
--- VERY PRECISE
-CREATE OR REPLACE FUNCTION in_zone(
-    lon_min_deg double precision,
-    lat_min_deg double precision,
-    lon_max_deg double precision,
-    lat_max_deg double precision,
-    test_lon_deg double precision,
-    test_lat_deg double precision)
-RETURNS boolean AS 
-$$
-    SELECT 
-    hpx_hash_range(29, test_lon_deg, test_lat_deg)
-    <@
-    to_int8multirange(
-        hpx_flag_one(
-            hpx_zone_coverage(
-                hpx_best_starting_depth(lon_min_deg)+4, lon_min_deg, lat_min_deg, lon_max_deg, lat_max_deg)))
+    c.hash_range <@ mgx_to_int8multirange(mgx_bmoc_flag_one(c.bmoc))
     OR
-    ((hpx_hash_range(29, test_lon_deg, test_lat_deg)
-    <@
-    to_int8multirange(
-        hpx_flag_zero(
-            hpx_zone_coverage(
-                hpx_best_starting_depth(lon_min_deg)+4, lon_min_deg, lat_min_deg, lon_max_deg, lat_max_deg))))
+    (c.hash_range <@ mgx_to_int8multirange(mgx_bmoc_flag_zero(c.bmoc))
         AND
-        (skyregion_zone_contains(lon_min_deg, lat_min_deg, lon_max_deg, lat_max_deg, test_lon_deg, test_lat_deg)))
+        mgx_bmoc_contains_bool(c.bmoc, test_lon_deg, test_lat_deg))
+    FROM (
+        SELECT
+        mgx_hash_range(29, test_lon_deg, test_lat_deg) AS hash_range,
+        mgx_bmoc_cone_coverage_approx(
+            mgx_optimal_coverage_depth(mgx_region_cone(lon_deg, lat_deg, radius_deg), 10000000), lon_deg, lat_deg, radius_deg) AS bmoc
+    ) c
 $$
-LANGUAGE sql;
-
--- Returns true if the cell (test_lon_deg, test_lat_deg) is in the polygon created with the coordinates given in the parameters
--- Uses the function skyregion::polygon::contains(...) to select only the cells in the BMOC
+LANGUAGE sql STABLE;
 
--- The vertices aren't included in the polygon
-CREATE OR REPLACE FUNCTION in_polygon(
-    vertices_deg vertexpsql[],
-    exact_solution boolean,      -- calculates the exact solution or not (nb : the exact solution still need some tests)
-    complement boolean,          -- if complement = true, the polygon's surface is the exterior of the polygon formed by the vertices on the sphere
-    test_lon_deg double precision,
-    test_lat_deg double precision)
-RETURNS boolean AS 
-$$
-    SELECT 
-    (
-        hpx_hash_range(29, test_lon_deg, test_lat_deg)
-        <@
-        to_int8multirange(
-            hpx_flag_one(
-                hpx_polygon_coverage(
-                    hpx_polygon_characteristic_depth(vertices_deg, complement), vertices_deg, exact_solution))))
-    OR
-    ((hpx_hash_range(29, test_lon_deg, test_lat_deg)
-    <@
-    to_int8multirange(
-        hpx_flag_zero(
-            hpx_polygon_coverage(
-                hpx_polygon_characteristic_depth(vertices_deg, complement), vertices_deg, exact_solution))))
-        AND
-        (skyregion_polygon_contains(vertices_deg, complement, test_lon_deg, test_lat_deg)))
-$$
-LANGUAGE sql;
-
--- Returns true if the cell (test_lon_deg, test_lat_deg) is in the box created with the coordinates given in the parameters
--- Uses the function skyregion::polygon::contains(...) to select only the cells in the BMOC
--- VERY PRECISE
-CREATE OR REPLACE FUNCTION in_box(
-    lon_deg double precision,
-    lat_deg double precision,
-    a_deg double precision,
-    b_deg double precision,
-    pa_deg double precision,
-    test_lon_deg double precision,
-    test_lat_deg double precision)
-RETURNS boolean AS 
-$$
-    SELECT 
-    hpx_hash_range(29, test_lon_deg, test_lat_deg)
-    <@
-    to_int8multirange(
-        hpx_flag_one(
-            hpx_box_coverage(
-                hpx_best_starting_depth(a_deg)+4, lon_deg, lat_deg, a_deg, b_deg, pa_deg)))
-    OR
-    ((hpx_hash_range(29, test_lon_deg, test_lat_deg)
-    <@
-    to_int8multirange(
-        hpx_flag_zero(
-            hpx_box_coverage(
-                hpx_best_starting_depth(a_deg)+4, lon_deg, lat_deg, a_deg, b_deg, pa_deg))))
-        AND
-        (skyregion_box_contains(lon_deg, lat_deg, a_deg, b_deg, pa_deg, test_lon_deg, test_lat_deg)))
-$$
-LANGUAGE sql;
-
--- Returns true if the cell (test_lon_deg, test_lat_deg) is in the ring created with the coordinates given in the parameters
--- Uses the function skyregion::ring::contains(...) to select only the cells in the BMOC
--- VERY PRECISE
-CREATE OR REPLACE FUNCTION in_ring(
-    lon_deg double precision,
-    lat_deg double precision,
-    r_min_deg double precision,
-    r_max_deg double precision,
-    test_lon_deg double precision,
-    test_lat_deg double precision)
-RETURNS boolean AS 
-$$
-    SELECT 
-    hpx_hash_range(29, test_lon_deg, test_lat_deg)
-    <@
-    to_int8multirange(
-        hpx_flag_one(
-            hpx_zone_coverage(
-                hpx_best_starting_depth(r_min_deg)+4, lon_deg, lat_deg, r_min_deg, r_max_deg)))
-    OR
-    ((hpx_hash_range(29, test_lon_deg, test_lat_deg)
-    <@
-    to_int8multirange(
-        hpx_flag_zero(
-            hpx_zone_coverage(
-                hpx_best_starting_depth(r_min_deg)+4, lon_deg, lat_deg, r_min_deg, r_max_deg))))
-        AND
-        (skyregion_ring_contains(lon_deg, lat_deg, r_min_deg, r_max_deg, test_lon_deg, test_lat_deg)))
-$$
-LANGUAGE sql;
+-- The region predicates mgx_in_cone, mgx_in_elliptical_cone, mgx_in_zone, mgx_in_polygon, mgx_in_box and mgx_in_ring
+-- are now created with the extension (see src/predicates.rs)
 
 -- CREATE OR REPLACE FUNCTION hpx_hash_range2(depth integer, lon double precision, lat double precision) RETURNS int8range AS
 -- $$ 
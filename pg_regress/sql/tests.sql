@@ -32,6 +32,12 @@ SELECT * FROM hip_table WHERE mgx_hash_range(29, raicrs, deicrs) <@ mgx_moc_to_r
 -- Create a MOC from a cone
 SELECT moc_from_cone(13.158329, -72.80028, 5.64323, 6, 5, 'All');
 
+-- Tests if several cells are in a MOC in one call
+SELECT mgx_is_in_moc(mgx_moc_from_cone(13.158329, -72.80028, 5.64323, 6, 5, 'All'), ARRAY[13.158329, 180.0], ARRAY[-72.80028, 45.0]);
+
+-- The MOC is only deserialized once for the whole scan
+SELECT * FROM hip_table WHERE mgx_is_in_moc(mgx_moc_from_cone(13.158329, -72.80028, 5.64323, 6, 5, 'All'), raicrs, deicrs);
+
 -- Return the cells contained in the moc created from a cone
 SELECT * FROM hip_table WHERE hpx_hash_range(29, raicrs, deicrs) <@ to_ranges_moc_psql(moc_from_cone(13.158329, -72.80028, 5.64323, 6, 5, 'All'));
 
@@ -111,24 +117,164 @@ $$ language plpgsql immutable
 ;
 
 -- Cone contains
-SELECT * FROM hip_table WHERE in_cone(0.01814144, 3.94648893, 5.64323, raicrs, deicrs);
+SELECT * FROM hip_table WHERE mgx_in_cone(0.01814144, 3.94648893, 5.64323, raicrs, deicrs);
 
 -- Elliptical cone contains
-SELECT * FROM hip_table WHERE in_elliptical_cone(0.01814144, 3.94648893, 1.6433, 4, 1.6, raicrs, deicrs);
+SELECT * FROM hip_table WHERE mgx_in_elliptical_cone(0.01814144, 3.94648893, 1.6433, 4, 1.6, raicrs, deicrs);
 
 -- Zone contains
-SELECT * FROM hip_table WHERE in_zone(0.01814144, 3.94648893, 2.57489321, 5.38601839, raicrs, deicrs);
+SELECT * FROM hip_table WHERE mgx_in_zone(0.01814144, 3.94648893, 2.57489321, 5.38601839, raicrs, deicrs);
 
 -- Polygon contains
-SELECT * FROM hip_table WHERE in_polygon(
-    ARRAY[create_vertexpsql(0.01814144, 3.94648893), create_vertexpsql(2.57489321, 5.38601839), create_vertexpsql(7.57489321, 3.38601839)],
+SELECT * FROM hip_table WHERE mgx_in_polygon(
+    ARRAY[mgx_create_vertexpsql(0.01814144, 3.94648893), mgx_create_vertexpsql(2.57489321, 5.38601839), mgx_create_vertexpsql(7.57489321, 3.38601839)],
     false,  -- exact_solution
     false,  -- complement 
     raicrs,
     deicrs);
 
+-- Polygon contains, with a polygon given in degrees
+SELECT * FROM hip_table WHERE mgx_in_polygon(
+    mgx_polygon_from_deg(ARRAY[0.01814144, 2.57489321, 7.57489321], ARRAY[3.94648893, 5.38601839, 3.38601839], false),
+    false,  -- exact_solution
+    raicrs,
+    deicrs);
+
+-- Same polygon MOC and vertices
+SELECT mgx_moc_to_ascii(mgx_moc_from_polygon(mgx_polygon_from_deg(ARRAY[0.01814144, 2.57489321, 7.57489321], ARRAY[3.94648893, 5.38601839, 3.38601839], false), 8, 'All'));
+SELECT mgx_polygon_vertices_deg(mgx_polygon_from_rad(ARRAY[0.0, 0.1, 0.1], ARRAY[0.0, 0.0, 0.1], false));
+
 -- Box contains
-SELECT * FROM hip_table WHERE in_box(0.01814144, 3.94648893, 4, 1.6433, 1.6, raicrs, deicrs);
+SELECT * FROM hip_table WHERE mgx_in_box(0.01814144, 3.94648893, 4, 1.6433, 1.6, raicrs, deicrs);
 
 -- Ring contains
-SELECT * FROM hip_table WHERE in_ring(0.01814144, 3.94648893, 2.57489321, 5.38601839, raicrs, deicrs);
\ No newline at end of file
+SELECT * FROM hip_table WHERE mgx_in_ring(0.01814144, 3.94648893, 2.57489321, 5.38601839, raicrs, deicrs);
+
+-- The predicates use the GiST index on mgx_hash_range(29, raicrs, deicrs)
+EXPLAIN SELECT * FROM hip_table WHERE mgx_in_zone(0.01814144, 3.94648893, 2.57489321, 5.38601839, raicrs, deicrs);
+
+-- Generic regions
+SELECT mgx_region_from_stcs('Circle ICRS 10.68 41.27 0.5');
+SELECT mgx_region_from_stcs('Intersection ICRS (Circle 10 20 5 Not (Circle 10 20 1))');
+SELECT mgx_adql_area(mgx_region_from_stcs('Union ICRS (Circle 10 0 2 Circle 12 0 2)'));
+SELECT mgx_region_to_moc(mgx_region_cone(10.68, 41.27, 0.5), 10);
+SELECT mgx_region_to_bmoc(mgx_region_box(10.68, 41.27, 2.0, 1.0, 30.0), 8);
+SELECT * FROM hip_table WHERE mgx_region_contains(mgx_region_ring(0.01814144, 3.94648893, 2.57489321, 5.38601839), raicrs, deicrs);
+
+-- MOC from STC-S
+SELECT mgx_moc_to_ascii(mgx_moc_from_stcs('Circle ICRS 10.68 41.27 0.5', 8));
+SELECT mgx_moc_to_ascii(mgx_moc_from_stcs('Intersection ICRS (Circle 10 20 5 Not (Polygon 9 19 11 19 10 21))', 8));
+
+-- ADQL compatibility : SELECT * FROM hip_table WHERE CONTAINS(POINT(raicrs, deicrs), CIRCLE(10.68, 41.27, 5)) = 1
+SELECT * FROM hip_table WHERE mgx_adql_contains(mgx_adql_point(raicrs, deicrs), mgx_adql_circle(10.68, 41.27, 5)) = 1;
+SELECT mgx_adql_distance(mgx_adql_point(10.68, 41.27), mgx_adql_point(10.0, 40.0));
+SELECT mgx_adql_area(mgx_adql_polygon(ARRAY[10.0, 20.0, 12.0, 20.0, 11.0, 22.0]));
+SELECT mgx_adql_intersects(mgx_adql_box(10.0, 20.0, 4.0, 2.0), mgx_adql_circle(12.5, 20.0, 1.0));
+
+-- MOC from/to UNIQ cells
+SELECT mgx_moc_to_ascii(mgx_moc_from_uniq(ARRAY[mgx_to_uniq(0, 0), mgx_to_uniq(5, 100)]));
+SELECT mgx_moc_to_uniq(mgx_moc_from_cells(ARRAY[3, 3, 3, 3], ARRAY[4, 5, 6, 7]::bigint[]));
+SELECT * FROM mgx_moc_to_uniq_set(mgx_moc_from_cone(13.158329, -72.80028, 5.64323, 6, 5, 'All'));
+
+-- Cells of a MOC and of a BMOC
+SELECT * FROM mgx_moc_cells(mgx_moc_from_cone(13.158329, -72.80028, 5.64323, 6, 5, 'All'));
+SELECT count(*) FROM mgx_moc_cells_at(mgx_moc_from_cone(13.158329, -72.80028, 5.64323, 6, 5, 'All'), 6);
+SELECT * FROM mgx_bmoc_cells(mgx_bmoc_cone_coverage_approx(6, 13.158329, -72.80028, 5.64323));
+
+-- MOC normalization and depth change
+SELECT mgx_moc_to_ranges(mgx_moc_normalize(mgx_create_range_moc_psql(2, ARRAY[int8range(10, 20), int8range(0, 15)])));
+SELECT mgx_moc_to_ascii(mgx_moc_set_depth_max(mgx_moc_from_cone(13.158329, -72.80028, 5.64323, 6, 5, 'All'), 4));
+SELECT mgx_moc_to_ascii(mgx_moc_refine(mgx_moc_from_cone(13.158329, -72.80028, 5.64323, 6, 5, 'All'), 8));
+
+-- Borders and morphological operations
+SELECT mgx_moc_to_ascii(mgx_moc_contracted(mgx_moc_from_cone(13.158329, -72.80028, 5.64323, 6, 5, 'All')));
+SELECT mgx_moc_to_ascii(mgx_moc_external_border(mgx_moc_from_cone(13.158329, -72.80028, 5.64323, 6, 5, 'All')));
+SELECT mgx_moc_to_ascii(mgx_moc_internal_border(mgx_moc_from_cone(13.158329, -72.80028, 5.64323, 6, 5, 'All')));
+SELECT mgx_moc_to_ascii(mgx_moc_expand_by_angle(mgx_moc_from_cone(13.158329, -72.80028, 5.64323, 6, 5, 'All'), 1.5));
+SELECT mgx_moc_to_ascii(mgx_moc_fill_holes(mgx_moc_minus(
+    mgx_moc_from_cone(13.158329, -72.80028, 5.64323, 6, 5, 'All'),
+    mgx_moc_from_cone(13.158329, -72.80028, 2.0, 6, 5, 'Inside'))));
+SELECT mgx_is_in_moc(mgx_moc_fill_holes(mgx_moc_not(mgx_moc_or(
+    mgx_moc_from_cone(13.158329, -72.80028, 5.64323, 6, 5, 'All'),
+    mgx_moc_from_cone(13.158329, 0.0, 2.0, 6, 5, 'All'))), 13.158329, 0.0), 13.158329, -72.80028);
+
+-- Connected components
+SELECT mgx_moc_n_components(mgx_moc_or(
+    mgx_moc_from_cone(10, 20, 2, 8, 2, 'All'),
+    mgx_moc_from_cone(100, -20, 2, 8, 2, 'All')), false);
+SELECT mgx_moc_to_ascii(component) FROM mgx_moc_split(mgx_moc_or(
+    mgx_moc_from_cone(10, 20, 2, 8, 2, 'All'),
+    mgx_moc_from_cone(100, -20, 2, 8, 2, 'All')), true) AS component;
+
+-- Outlines of a MOC
+SELECT array_length(polygon, 1) FROM mgx_moc_to_polygons(mgx_moc_minus(
+    mgx_moc_from_cone(13.158329, 40.0, 5.0, 7, 2, 'All'),
+    mgx_moc_from_cone(13.158329, 40.0, 2.0, 7, 2, 'All'))) AS polygon;
+SELECT mgx_moc_outline_geojson(mgx_moc_from_cone(13.158329, -72.80028, 5.64323, 6, 5, 'All'));
+
+-- GeoJSON / WKT export
+SELECT mgx_cell_to_geojson(3, 42);
+SELECT mgx_cell_to_wkt(3, 42);
+SELECT mgx_moc_to_geojson(mgx_moc_from_cone(13.158329, -72.80028, 5.64323, 6, 5, 'All'));
+SELECT mgx_moc_to_wkt(mgx_moc_from_cone(13.158329, -72.80028, 5.64323, 6, 5, 'All'));
+SELECT mgx_bmoc_to_geojson(mgx_bmoc_cone_coverage_approx(6, 13.158329, -72.80028, 5.64323));
+SELECT mgx_bmoc_to_wkt(mgx_bmoc_cone_coverage_approx(6, 13.158329, -72.80028, 5.64323));
+-- The cells and regions crossing the antimeridian are cut in two
+SELECT mgx_cell_to_wkt(0, 6);
+SELECT mgx_region_to_geojson(mgx_region_cone(180.0, 0.0, 5.0));
+SELECT mgx_region_to_wkt(mgx_region_box(10.0, 20.0, 2.0, 1.0, 30.0));
+
+-- Catalog of the indexed tables
+SELECT * FROM mgx_lookup_indexed_table('hip_table');
+
+-- Cone search on a registered table
+SELECT * FROM mgx_cone_search_typed(NULL::hip_table, 0.01814144, 3.94648893, 5.64323);
+SELECT * FROM mgx_cone_search('hip_table', 0.01814144, 3.94648893, 5.64323)
+    AS t(hip bigint, vmag double precision, raicrs double precision, deicrs double precision);
+
+-- Region search and crossmatch on the registered tables
+SELECT count(*) FROM mgx_region_search_typed(NULL::hip_table, mgx_region_box(0.01814144, 3.94648893, 5, 3, 30));
+SELECT count(*) FROM mgx_crossmatch('hip_table', 'hip_table', 0.1)
+    AS t(hip bigint, vmag double precision, raicrs double precision, deicrs double precision,
+         hip_b bigint, vmag_b double precision, raicrs_b double precision, deicrs_b double precision, distance double precision);
+
+-- Coverage depth chosen by the cost model
+SELECT mgx_optimal_coverage_depth(mgx_region_cone(0.01814144, 3.94648893, 0.1), 100000);
+SELECT mgx_optimal_coverage_depth(mgx_region_cone(0.01814144, 3.94648893, 0.1), 'hip_table'::regclass);
+SET mogipix.coverage_depth = 10;
+SELECT mgx_optimal_coverage_depth(mgx_region_cone(0.01814144, 3.94648893, 0.1), 100000);
+RESET mogipix.coverage_depth;
+
+-- Settings of the extension, read by the _unit functions and the searches
+SET mogipix.angle_unit = 'arcsec';
+SELECT mgx_hash_unit(12, 38448, 148572);
+SELECT mgx_region_cone_unit(36, 72, 18);
+SELECT count(*) FROM mgx_cone_search_typed(NULL::hip_table, 65.3, 14206.2, 20315.6);
+RESET mogipix.angle_unit;
+SET mogipix.default_depth = 12;
+SELECT mgx_hash_unit(-1, 10.68, 41.27);
+RESET mogipix.default_depth;
+SET mogipix.coverage_delta_depth = 4;
+SELECT mgx_optimal_coverage_depth(mgx_region_cone(0.01814144, 3.94648893, 5.64323), 100000);
+RESET mogipix.coverage_delta_depth;
+SET mogipix.exact_recheck = off;
+SELECT count(*) FROM mgx_cone_search_typed(NULL::hip_table, 0.01814144, 3.94648893, 5.64323);
+RESET mogipix.exact_recheck;
+
+-- BMOC coverage modes of the cones and rings
+SELECT mgx_bmoc_cone_coverage(6, 13.158329, -72.80028, 5.64323, 'FullIn');
+SELECT mgx_bmoc_cone_coverage(6, 13.158329, -72.80028, 5.64323, 'Centers');
+SELECT mgx_bmoc_cone_coverage(6, 13.158329, -72.80028, 5.64323, 'Custom', 3);
+SELECT mgx_bmoc_ring_coverage(6, 13.158329, -72.80028, 2.0, 5.64323, 'FullIn');
+
+-- MOC of a list of cones
+SELECT mgx_moc_to_ascii(mgx_moc_from_cones(ARRAY[10.0, 10.5, 100.0], ARRAY[20.0, 20.0, -20.0], ARRAY[0.5, 0.5, 1.0], 8));
+SELECT mgx_moc_to_ascii(mgx_moc_from_cones(array_agg(raicrs), array_agg(deicrs), array_agg(0.1::float8), 10))
+    FROM hip_table WHERE vmag < 3;
+
+-- MOC of lists of elliptical cones and boxes
+SELECT mgx_moc_to_ascii(mgx_moc_from_elliptical_cones(
+    ARRAY[10.0, 100.0], ARRAY[20.0, -20.0], ARRAY[0.5, 1.0], ARRAY[0.2, 0.5], ARRAY[30.0, 0.0], 8));
+SELECT mgx_moc_to_ascii(mgx_moc_from_boxes(
+    ARRAY[10.0, 100.0], ARRAY[20.0, -20.0], ARRAY[0.5, 1.0], ARRAY[0.2, 0.5], ARRAY[30.0, 0.0], 8));
+SELECT mgx_moc_to_ascii(mgx_moc_from_ellipse_agg(raicrs, deicrs, 0.2, 0.1, 0, 10, 2, 'All')) FROM hip_table WHERE vmag < 3;
@@ -0,0 +1,247 @@
+use pgrx::prelude::*; // default
+
+// Library imports
+use std::f64::consts::PI;
+
+use crate::Coo;
+use crate::region::{RegionPSQL, box_vertices, ellipse_distance};
+use crate::polygon::{PolygonPSQL, polygon_or_error, to_xyz, cross, dot};
+use crate::boundary::{Edge, N_ELLIPSE_VERTICES, boundaries, boundaries_meet, normalized, shape_contains};
+use crate::cache::{DatumArg, with_cached_arg};
+use crate::predicates::Recheck;
+
+// ADQL geometry functions (ADQL 2.1, section 4.2) built on the mogipix types so a TAP service can translate
+//   POINT -> mgx_adql_point, CIRCLE -> mgx_adql_circle, BOX -> mgx_adql_box, POLYGON -> mgx_adql_polygon,
+//   CONTAINS -> mgx_adql_contains, INTERSECTS -> mgx_adql_intersects, DISTANCE -> mgx_adql_distance,
+//   AREA -> mgx_adql_area, COORD1/COORD2 -> mgx_adql_coord1/mgx_adql_coord2.
+// All the angles are in degrees, the predicates return 1 or 0 as ADQL expects.
+// CONTAINS and INTERSECTS between two regions compare their boundaries, made of arcs of great or small circles,
+// and are exact but for the elliptical cones whose boundaries are approximated by N_ELLIPSE_VERTICES great-circle arcs
+// (see crate::boundary). The regions parsed from STC-S can also be unions, intersections and complements.
+
+// Square degrees in a steradian
+const SQ_DEG_PER_SR: f64 = (180.0 / PI) * (180.0 / PI);
+// Depth, relative to their characteristic depth, of the BMOCs estimating the areas of the unions and intersections
+const COMPOUND_AREA_DELTA_DEPTH: u8 = 10;
+
+// --------------------------------------------------- Geometries --------------------------------------------------------
+
+// POINT(lon, lat)
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_adql_point(lon: f64, lat: f64) -> Coo {
+    Coo { lon_rad: lon.to_radians(), lat_rad: lat.to_radians() }
+}
+
+// CIRCLE(lon, lat, radius)
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_adql_circle(lon: f64, lat: f64, radius: f64) -> RegionPSQL {
+    RegionPSQL::Cone { lon, lat, radius }.validated()
+}
+
+// CIRCLE(POINT, radius)
+#[pg_extern(immutable, parallel_safe, name = "mgx_adql_circle")]
+pub fn mgx_adql_circle_from_point(center: Coo, radius: f64) -> RegionPSQL {
+    RegionPSQL::Cone { lon: center.lon_rad.to_degrees(), lat: center.lat_rad.to_degrees(), radius }.validated()
+}
+
+// BOX(lon, lat, width, height)
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_adql_box(lon: f64, lat: f64, width: f64, height: f64) -> RegionPSQL {
+    RegionPSQL::box_from_size(lon, lat, width, height).validated()
+}
+
+// POLYGON(lon1, lat1, lon2, lat2, lon3, lat3, ...)
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_adql_polygon(coords: Vec<f64>) -> RegionPSQL {
+    if coords.len() < 6 || coords.len() % 2 != 0 {
+        error!("POLYGON expects an even number (>= 6) of coordinates, {} given", coords.len());
+    }
+    let vertices = coords.chunks(2).map(|v| (v[0], v[1])).collect();
+    RegionPSQL::Polygon(polygon_or_error(PolygonPSQL::from_deg(vertices, false)))
+}
+
+// POLYGON(POINT, POINT, POINT, ...)
+#[pg_extern(immutable, parallel_safe, name = "mgx_adql_polygon")]
+pub fn mgx_adql_polygon_from_points(points: Vec<Coo>) -> RegionPSQL {
+    let coords = points
+        .iter()
+        .flat_map(|p| [p.lon_rad.to_degrees(), p.lat_rad.to_degrees()])
+        .collect();
+    mgx_adql_polygon(coords)
+}
+
+// COORD1(POINT)
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_adql_coord1(point: Coo) -> f64 {
+    point.lon_rad.to_degrees()
+}
+
+// COORD2(POINT)
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_adql_coord2(point: Coo) -> f64 {
+    point.lat_rad.to_degrees()
+}
+
+// ---------------------------------------------------- Distance ---------------------------------------------------------
+
+// Great-circle distance in radians (haversine formula, accurate for small distances)
+pub fn haversine_distance(lon1_rad: f64, lat1_rad: f64, lon2_rad: f64, lat2_rad: f64) -> f64 {
+    let sin_dlat = (0.5 * (lat2_rad - lat1_rad)).sin();
+    let sin_dlon = (0.5 * (lon2_rad - lon1_rad)).sin();
+    let h = sin_dlat * sin_dlat + lat1_rad.cos() * lat2_rad.cos() * sin_dlon * sin_dlon;
+    2.0 * h.sqrt().min(1.0).asin()
+}
+
+// DISTANCE(POINT, POINT)
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_adql_distance(p1: Coo, p2: Coo) -> f64 {
+    haversine_distance(p1.lon_rad, p1.lat_rad, p2.lon_rad, p2.lat_rad).to_degrees()
+}
+
+// DISTANCE(lon1, lat1, lon2, lat2)
+#[pg_extern(immutable, parallel_safe, name = "mgx_adql_distance")]
+pub fn mgx_adql_distance_deg(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    haversine_distance(lon1.to_radians(), lat1.to_radians(), lon2.to_radians(), lat2.to_radians()).to_degrees()
+}
+
+// ------------------------------------------------------ Area -----------------------------------------------------------
+
+// Signed spherical excess of the triangle (a, b, c), positive if it is counter-clockwise
+fn triangle_excess(a: &[f64; 3], b: &[f64; 3], c: &[f64; 3]) -> f64 {
+    2.0 * dot(a, &cross(b, c)).atan2(1.0 + dot(a, b) + dot(b, c) + dot(c, a))
+}
+
+// Area in steradians of the polygon of the region. The spherical excesses of the triangles (v0, vi, vi+1) sum to the
+// area on the left of the boundary (modulo 4 pi), the region being either this side or the other one.
+fn polygon_area(vertices: &[(f64, f64)], region: &RegionPSQL) -> f64 {
+    let v: Vec<[f64; 3]> = vertices.iter().map(|vertex| to_xyz(*vertex)).collect();
+    let left_area = (1..v.len() - 1)
+        .map(|i| triangle_excess(&v[0], &v[i], &v[i + 1]))
+        .sum::<f64>()
+        .rem_euclid(4.0 * PI);
+    // Point on the left of the first edge, near its middle
+    let (middle, normal) = (normalized([0usize, 1, 2].map(|k| v[0][k] + v[1][k])), normalized(cross(&v[0], &v[1])));
+    let left = normalized([0usize, 1, 2].map(|k| middle[k] + 1e-7 * normal[k]));
+    if shape_contains(region, &left) { left_area } else { 4.0 * PI - left_area }
+}
+
+// Area in steradians of the elliptical cone, integral of the area (1 - cos(r)) dphi of the thin sectors of the cone.
+// The integrand being smooth and periodic, the trapezoidal rule converges quickly.
+fn elliptical_cone_area(a: f64, b: f64) -> f64 {
+    const N_STEPS: usize = 3600;
+    let step = 2.0 * PI / N_STEPS as f64;
+    (0..N_STEPS).map(|i| 1.0 - ellipse_distance(a, b, i as f64 * step).cos()).sum::<f64>() * step
+}
+
+// Area in steradians of a union or an intersection, estimated from its BMOC COMPOUND_AREA_DELTA_DEPTH deeper than its
+// characteristic depth, the partial cells counting for half their area
+fn compound_area(region: &RegionPSQL) -> f64 {
+    let depth = (region.characteristic_depth() + COMPOUND_AREA_DELTA_DEPTH).min(29);
+    let bmoc = region.to_bmoc(depth);
+    bmoc.entries
+        .iter()
+        .map(|raw| bmoc.from_raw_value(*raw))
+        .map(|cell| {
+            let cell_area = 4.0 * PI / cdshealpix::nested::n_hash(cell.depth) as f64;
+            if cell.is_full { cell_area } else { 0.5 * cell_area }
+        })
+        .sum()
+}
+
+// Area of the region in steradians
+fn area_sr(region: &RegionPSQL) -> f64 {
+    let cap_area_sr = |radius_deg: f64| 2.0 * PI * (1.0 - radius_deg.to_radians().cos());
+    match region {
+        RegionPSQL::Cone { radius, .. } => cap_area_sr(*radius),
+        RegionPSQL::Ring { r_min, r_max, .. } => cap_area_sr(*r_max) - cap_area_sr(*r_min),
+        RegionPSQL::Zone { lon_min, lat_min, lon_max, lat_max } => {
+            let dlon = if lon_max >= lon_min { lon_max - lon_min } else { 360.0 - lon_min + lon_max };
+            dlon.to_radians() * (lat_max.to_radians().sin() - lat_min.to_radians().sin())
+        }
+        RegionPSQL::EllipticalCone { a, b, .. } => elliptical_cone_area(a.to_radians(), b.to_radians()),
+        RegionPSQL::Polygon(polygon) => polygon_area(&polygon.vertices_rad, region),
+        RegionPSQL::Box { lon, lat, a, b, pa } => polygon_area(&box_vertices(*lon, *lat, *a, *b, *pa), region),
+        RegionPSQL::Not(region) => 4.0 * PI - area_sr(region),
+        RegionPSQL::Union(_) | RegionPSQL::Intersection(_) => compound_area(region),
+    }
+}
+
+// AREA(region) in square degrees
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_adql_area(region: RegionPSQL) -> f64 {
+    area_sr(&region) * SQ_DEG_PER_SR
+}
+
+// --------------------------------------------------- Boundaries --------------------------------------------------------
+
+// Point of the boundary, the middle of its first edge (the ends of the pieces of the boundaries of the unions and
+// intersections are on the boundaries of their regions)
+fn boundary_point(boundary: &[Edge]) -> [f64; 3] {
+    boundary[0].middle()
+}
+
+// A region without boundary is either the whole sky or empty (e.g. an intersection of disjoint regions)
+fn is_empty(region: &RegionPSQL, boundaries: &[Vec<Edge>]) -> bool {
+    boundaries.is_empty() && !shape_contains(region, &[0.0, 0.0, 1.0])
+}
+
+// ----------------------------------------------- Contains / Intersects -------------------------------------------------
+
+fn to_int(b: bool) -> i32 {
+    if b { 1 } else { 0 }
+}
+
+// CONTAINS(POINT, region)
+// The skyregion shape is only built once per scan when the region is a constant (see crate::cache)
+#[pg_extern(immutable, parallel_safe, requires = [RegionPSQL])]
+pub fn mgx_adql_contains(fcinfo: pg_sys::FunctionCallInfo, point: Coo, region: DatumArg<RegionPSQL>) -> i32 {
+    let build = |region: RegionPSQL| match region.to_sky_region() {
+        Ok(shape) => shape,
+        Err(e) => error!("Invalid region {:?} : {}", region, e),
+    };
+    with_cached_arg(fcinfo, 1, &region, build, |shape| to_int(shape.recheck(point.lon_rad, point.lat_rad)))
+}
+
+// CONTAINS(region, region) : 1 if `inner` is inside `outer`.
+// When the boundaries don't meet, each boundary of a region is either inside or outside the other region :
+// `inner` is in `outer` if all its boundaries are in `outer` and no boundary of `outer` is in `inner`.
+// Boundaries touching each other, e.g. at a shared vertex, meet : the regions aren't considered as contained.
+#[pg_extern(immutable, parallel_safe, name = "mgx_adql_contains")]
+pub fn mgx_adql_contains_region(inner: RegionPSQL, outer: RegionPSQL) -> i32 {
+    if let (RegionPSQL::Cone { lon: lon1, lat: lat1, radius: r1 }, RegionPSQL::Cone { lon: lon2, lat: lat2, radius: r2 }) = (&inner, &outer) {
+        return to_int(mgx_adql_distance_deg(*lon1, *lat1, *lon2, *lat2) + r1 <= *r2);
+    }
+    let (inner_boundaries, outer_boundaries) = (boundaries(&inner), boundaries(&outer));
+    if inner_boundaries.is_empty() && outer_boundaries.is_empty() {
+        return to_int(is_empty(&inner, &inner_boundaries) || !is_empty(&outer, &outer_boundaries));
+    }
+    to_int(
+        !boundaries_meet(&inner_boundaries, &outer_boundaries)
+            && inner_boundaries.iter().all(|boundary| shape_contains(&outer, &boundary_point(boundary)))
+            && !outer_boundaries.iter().any(|boundary| shape_contains(&inner, &boundary_point(boundary)))
+    )
+}
+
+// INTERSECTS(region, region)
+// When the boundaries don't meet, the regions intersect if a boundary of one of them is inside the other one.
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_adql_intersects(region: RegionPSQL, other: RegionPSQL) -> i32 {
+    if let (RegionPSQL::Cone { lon: lon1, lat: lat1, radius: r1 }, RegionPSQL::Cone { lon: lon2, lat: lat2, radius: r2 }) = (&region, &other) {
+        return to_int(mgx_adql_distance_deg(*lon1, *lat1, *lon2, *lat2) <= r1 + r2);
+    }
+    let (boundaries1, boundaries2) = (boundaries(&region), boundaries(&other));
+    if boundaries1.is_empty() || boundaries2.is_empty() {
+        return to_int(!is_empty(&region, &boundaries1) && !is_empty(&other, &boundaries2));
+    }
+    to_int(
+        boundaries_meet(&boundaries1, &boundaries2)
+            || boundaries1.iter().any(|boundary| shape_contains(&other, &boundary_point(boundary)))
+            || boundaries2.iter().any(|boundary| shape_contains(&region, &boundary_point(boundary)))
+    )
+}
+
+// INTERSECTS(POINT, region) is equivalent to CONTAINS(POINT, region)
+#[pg_extern(immutable, parallel_safe, name = "mgx_adql_intersects", requires = [RegionPSQL])]
+pub fn mgx_adql_intersects_point(fcinfo: pg_sys::FunctionCallInfo, point: Coo, region: DatumArg<RegionPSQL>) -> i32 {
+    mgx_adql_contains(fcinfo, point, region)
+}
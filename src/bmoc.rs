@@ -20,6 +20,10 @@ use std::ops::BitXor;
 use std::ops::Range as StdRange;
 use pgrx::datum::Range as PgRange;
 
+use crate::cache::{DatumArg, with_cached, with_cached_arg, is_arg_stable};
+use crate::convert::{u64s_to_i64s, i64s_to_u64s};
+use crate::polygon::{PolygonPSQL, polygon_or_error};
+
 use skyregion::{
   regions::{
     cone::Cone,
@@ -76,10 +80,7 @@ pub fn mgx_create_bmoc_psql(depth_max: i32, entries: Vec<i64>) -> BMOCpsql {
 // BMOC -> BMOCpsql 
 impl From<BMOC> for BMOCpsql {
   fn from(item: BMOC) -> Self {
-    let entries_vec_i64 = unsafe {
-        let entries_vec_u64 = item.entries.to_vec();
-        std::mem::transmute::<Vec<u64>, Vec<i64>>(entries_vec_u64)
-    };
+    let entries_vec_i64 = u64s_to_i64s(item.entries.to_vec());
     BMOCpsql {depth_max:item.get_depth_max() as i32, entries: entries_vec_i64}
   }
 }
@@ -87,11 +88,8 @@ impl From<BMOC> for BMOCpsql {
 // BMOCpsql -> BMOC
 impl From<BMOCpsql> for BMOC {
   fn from(item: BMOCpsql) -> Self {
-    let entries_vec_i64 = item.entries.to_vec();
-    let entries_vec_u64: Vec<u64> = unsafe {
-        std::mem::transmute::<Vec<i64>, Vec<u64>>(entries_vec_i64)
-    };
-    
+    let entries_vec_u64: Vec<u64> = i64s_to_u64s(item.entries);
+
     BMOC::create_unsafe(item.depth_max as u8, entries_vec_u64.into_boxed_slice())
   }
 }
@@ -153,6 +151,13 @@ impl From<VertexPSQL> for (f64,f64) {
   }
 }
 
+// (lon, lat) in radians -> VertexPSQL
+impl From<(f64,f64)> for VertexPSQL {
+  fn from(item: (f64,f64)) -> Self {
+    VertexPSQL(item.0, item.1)
+  }
+}
+
 // Creation of a vertex (useful in Postgres)
 #[pg_extern(immutable, parallel_safe)]
 pub fn mgx_create_vertexpsql(lon: f64, lat: f64) -> VertexPSQL {
@@ -162,12 +167,7 @@ pub fn mgx_create_vertexpsql(lon: f64, lat: f64) -> VertexPSQL {
 // Polygon
 #[pg_extern(immutable, parallel_safe)]
 pub fn mgx_bmoc_polygon_coverage(depth: i32, vertices: Vec<VertexPSQL>, exact_solution: bool) -> BMOCpsql {
-  let mut vertices_tuple: Vec<(f64,f64)> = Vec::new();
-  for vertex in vertices {
-    vertices_tuple.push(vertex.into());
-  }
-  let vertices_as_array = vertices_tuple.as_slice();
-  cdshealpix::nested::polygon_coverage(depth as u8, vertices_as_array, exact_solution).into()
+  polygon_or_error(PolygonPSQL::from_vertices(vertices, false)).to_bmoc(depth as u8, exact_solution).into()
 }
 
 // Box
@@ -182,6 +182,76 @@ pub fn mgx_bmoc_ring_coverage_approx(depth: i32, cone_lon: f64, cone_lat: f64, c
   cdshealpix::nested::ring_coverage_approx(depth as u8, cone_lon.to_radians(), cone_lat.to_radians(), cone_radius_int.to_radians(), cone_radius_ext.to_radians()).into()
 }
 
+// Coverage modes of the cones and rings, like CellSelectionPSQL for the MOCs
+//   - Approx : cells overlapping the region (a few more cells may be returned)
+//   - Custom : cells overlapping the region, the overlap being tested at depth + delta_depth
+//   - FullIn : cells fully inside the region
+//   - Centers : cells whose center is inside the region
+#[derive(PostgresEnum, Debug, Serialize, Deserialize)]
+pub enum CoverageModePSQL {
+    Approx,
+    Custom,
+    FullIn,
+    Centers,
+}
+
+// Cone with a coverage mode (delta_depth is only used by the Custom mode)
+#[pg_extern(immutable, parallel_safe, name = "mgx_bmoc_cone_coverage")]
+pub fn mgx_bmoc_cone_coverage_mode(
+  depth: i32,
+  cone_lon: f64,
+  cone_lat: f64,
+  cone_radius: f64,
+  mode: CoverageModePSQL,
+  delta_depth: default!(i32, 2),
+) -> BMOCpsql {
+  let (depth, lon, lat, radius) = (depth as u8, cone_lon.to_radians(), cone_lat.to_radians(), cone_radius.to_radians());
+  match mode {
+    CoverageModePSQL::Approx => cdshealpix::nested::cone_coverage_approx(depth, lon, lat, radius),
+    CoverageModePSQL::Custom => cdshealpix::nested::cone_coverage_approx_custom(depth, delta_depth as u8, lon, lat, radius),
+    CoverageModePSQL::FullIn => cdshealpix::nested::cone_coverage_fullin(depth, lon, lat, radius),
+    CoverageModePSQL::Centers => cdshealpix::nested::cone_coverage_centers(depth, lon, lat, radius),
+  }.into()
+}
+
+// Cells of the BMOC flagged as full
+fn full_cells(bmoc: BMOC) -> BMOC {
+  let mut builder = BMOCBuilderUnsafe::new(bmoc.get_depth_max(), bmoc.entries.len());
+  for raw in bmoc.entries.iter() {
+    let cell = bmoc.from_raw_value(*raw);
+    if cell.is_full {
+      builder.push(cell.depth, cell.hash, true);
+    }
+  }
+  builder.to_bmoc()
+}
+
+// Ring with a coverage mode (delta_depth is only used by the Custom mode)
+// The FullIn and Centers modes are computed from the cones of the external and internal radii
+#[pg_extern(immutable, parallel_safe, name = "mgx_bmoc_ring_coverage")]
+pub fn mgx_bmoc_ring_coverage_mode(
+  depth: i32,
+  cone_lon: f64,
+  cone_lat: f64,
+  cone_radius_int: f64,
+  cone_radius_ext: f64,
+  mode: CoverageModePSQL,
+  delta_depth: default!(i32, 2),
+) -> BMOCpsql {
+  let (depth, lon, lat) = (depth as u8, cone_lon.to_radians(), cone_lat.to_radians());
+  let (r_int, r_ext) = (cone_radius_int.to_radians(), cone_radius_ext.to_radians());
+  match mode {
+    CoverageModePSQL::Approx => cdshealpix::nested::ring_coverage_approx(depth, lon, lat, r_int, r_ext),
+    CoverageModePSQL::Custom => cdshealpix::nested::ring_coverage_approx_custom(depth, delta_depth as u8, lon, lat, r_int, r_ext),
+    // Cells fully in the external cone and not overlapping the internal one : the cells partially covered by the
+    // internal cone are kept as partial cells by the intersection, so only the full cells are selected
+    CoverageModePSQL::FullIn => full_cells(cdshealpix::nested::cone_coverage_fullin(depth, lon, lat, r_ext)
+      .and(&cdshealpix::nested::cone_coverage_approx(depth, lon, lat, r_int).not())),
+    CoverageModePSQL::Centers => cdshealpix::nested::cone_coverage_centers(depth, lon, lat, r_ext)
+      .and(&cdshealpix::nested::cone_coverage_centers(depth, lon, lat, r_int).not()),
+  }.into()
+}
+
 // ------------------------------------------------ Contains -----------------------------------------------
 
 // Status type that is PSQL compatible
@@ -204,15 +274,18 @@ impl From<Status> for Statuspsql {
 }
 
 // Contains
-#[pg_extern(immutable, parallel_safe)]
-pub fn mgx_bmoc_contains(bmoc: BMOCpsql, lon: f64, lat:f64) -> Statuspsql {
-    BMOC::from(bmoc).test_coo(lon.to_radians(), lat.to_radians()).into()
+// The BMOC is only built once per scan when it is a constant (see crate::cache)
+#[pg_extern(immutable, parallel_safe, requires = [BMOCpsql])]
+pub fn mgx_bmoc_contains(fcinfo: pg_sys::FunctionCallInfo, bmoc: DatumArg<BMOCpsql>, lon: f64, lat:f64) -> Statuspsql {
+    with_cached_arg(fcinfo, 0, &bmoc, BMOC::from, |hpx_bmoc| {
+        hpx_bmoc.test_coo(lon.to_radians(), lat.to_radians()).into()
+    })
 }
 
 // Contains
-#[pg_extern(immutable, parallel_safe)]
-pub fn mgx_bmoc_contains_bool(bmoc: BMOCpsql, lon: f64, lat:f64) -> bool {
-    let status: Statuspsql = mgx_bmoc_contains(bmoc, lon, lat);
+#[pg_extern(immutable, parallel_safe, requires = [BMOCpsql])]
+pub fn mgx_bmoc_contains_bool(fcinfo: pg_sys::FunctionCallInfo, bmoc: DatumArg<BMOCpsql>, lon: f64, lat:f64) -> bool {
+    let status: Statuspsql = mgx_bmoc_contains(fcinfo, bmoc, lon, lat);
     match status {
       Statuspsql::In => true,
       Statuspsql::Out => false,
@@ -338,13 +411,16 @@ pub fn mgx_bmoc_flag_one(bmoc: BMOCpsql) -> Vec<PgRange<i64>> {
 // Warning : the ranges are not at the MOC depth, not a the depth 29
 #[pg_extern(immutable, parallel_safe)]
 pub fn mgx_bmoc_to_ranges(bmoc: BMOCpsql) -> Vec<PgRange<i64>> {
-    let depth_max = bmoc.depth_max;
     let std_bmoc: BMOC = bmoc.into();
+    bmoc_to_depth_29_ranges(&std_bmoc)
+}
 
-    let vec_range_u64: Vec<StdRange<u64>> = std_bmoc.to_ranges().into_vec();
+// Ranges of the BMOC shifted to the depth 29 (the depth of the hash indexes)
+pub fn bmoc_to_depth_29_ranges(bmoc: &BMOC) -> Vec<PgRange<i64>> {
+    let vec_range_u64: Vec<StdRange<u64>> = bmoc.to_ranges().into_vec();
     let mut vec_range_i64: Vec<PgRange<i64>> = Vec::new();
 
-    let shift = (29 - depth_max) << 1;
+    let shift = (29 - bmoc.get_depth_max()) << 1;
     for r in vec_range_u64 {
         vec_range_i64.push(StdRangeCrate((r.start << shift)..(r.end << shift)).into());
     }
@@ -352,30 +428,51 @@ pub fn mgx_bmoc_to_ranges(bmoc: BMOCpsql) -> Vec<PgRange<i64>> {
     vec_range_i64
 }
 
+// ------------------------------------------------ Cells ---------------------------------------------------
+
+// Returns one row per cell of the BMOC, with its flag
+// The rows are generated on the fly so large BMOCs don't have to be copied in an array
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_bmoc_cells(bmoc: BMOCpsql) -> TableIterator<'static, (name!(depth, i32), name!(hash, i64), name!(is_full, bool))> {
+  let std_bmoc: BMOC = bmoc.into();
+  let n_cells = std_bmoc.entries.len();
+  TableIterator::new((0..n_cells).map(move |i| {
+    let cell = std_bmoc.from_raw_value(std_bmoc.entries[i]);
+    (cell.depth as i32, cell.hash as i64, cell.is_full)
+  }))
+}
+
 // ----------------------------------------------------- Skyregion::contains -----------------------------------------------------------
 
+// The regions are cached in fn_extra, keyed on their parameters, so they are not rebuilt for every row (see crate::cache)
+
 // Cone
 #[pg_extern(immutable, parallel_safe)]
 pub fn mgx_skyregion_cone_contains(
+    fcinfo: pg_sys::FunctionCallInfo,
     lon_deg: f64,
     lat_deg: f64,
     radius_deg: f64,
     test_lon_deg: f64,
     test_lat_deg: f64,
 ) -> bool {
-    match Cone::from_deg(lon_deg, lat_deg, radius_deg) {
-        Ok(cone) => {
-            let test_lon = test_lon_deg.to_radians();
-            let test_lat = test_lat_deg.to_radians();
-            cone.contains(test_lon, test_lat)
+    let key = (lon_deg, lat_deg, radius_deg);
+    with_cached(fcinfo, true, key, || Cone::from_deg(lon_deg, lat_deg, radius_deg).ok(), |cone| {
+        match cone {
+            Some(cone) => {
+                let test_lon = test_lon_deg.to_radians();
+                let test_lat = test_lat_deg.to_radians();
+                cone.contains(test_lon, test_lat)
+            }
+            None => false,
         }
-        Err(_) => false,
-    }
+    })
 }
 
 // Elliptical cone
 #[pg_extern(immutable, parallel_safe)]
 pub fn mgx_skyregion_elliptical_cone_contains(
+    fcinfo: pg_sys::FunctionCallInfo,
     lon_deg: f64,
     lat_deg: f64,
     a_deg: f64,
@@ -384,20 +481,24 @@ pub fn mgx_skyregion_elliptical_cone_contains(
     test_lon_deg: f64,
     test_lat_deg: f64,
 ) -> bool {
-    match EllipticalCone::from_deg(lon_deg, lat_deg, a_deg, b_deg, pa_deg) {
-        Ok(elliptical_cone) => {
-            let test_lon = test_lon_deg.to_radians();
-            let test_lat = test_lat_deg.to_radians();
-
-            elliptical_cone.contains(test_lon, test_lat)
+    let key = (lon_deg, lat_deg, a_deg, b_deg, pa_deg);
+    with_cached(fcinfo, true, key, || EllipticalCone::from_deg(lon_deg, lat_deg, a_deg, b_deg, pa_deg).ok(), |elliptical_cone| {
+        match elliptical_cone {
+            Some(elliptical_cone) => {
+                let test_lon = test_lon_deg.to_radians();
+                let test_lat = test_lat_deg.to_radians();
+
+                elliptical_cone.contains(test_lon, test_lat)
+            }
+            None => false,
         }
-        Err(_) => false,
-    }
+    })
 }
 
 // Zone
 #[pg_extern(immutable, parallel_safe)]
 pub fn mgx_skyregion_zone_contains(
+    fcinfo: pg_sys::FunctionCallInfo,
     lon_min_deg: f64,
     lat_min_deg: f64,
     lon_max_deg: f64,
@@ -405,55 +506,59 @@ pub fn mgx_skyregion_zone_contains(
     test_lon_deg: f64,
     test_lat_deg: f64,
 ) -> bool {
-    match Zone::from_deg(lon_min_deg, lat_min_deg, lon_max_deg, lat_max_deg) {
-        Ok(zone) => {
-            let test_lon = test_lon_deg.to_radians();
-            let test_lat = test_lat_deg.to_radians();
-
-            zone.contains(test_lon, test_lat)
+    let key = (lon_min_deg, lat_min_deg, lon_max_deg, lat_max_deg);
+    with_cached(fcinfo, true, key, || Zone::from_deg(lon_min_deg, lat_min_deg, lon_max_deg, lat_max_deg).ok(), |zone| {
+        match zone {
+            Some(zone) => {
+                let test_lon = test_lon_deg.to_radians();
+                let test_lat = test_lat_deg.to_radians();
+
+                zone.contains(test_lon, test_lat)
+            }
+            None => false,
         }
-        Err(_) => false,
-    }
+    })
 }
 
 // Like mgx_best_starting_depth but from the skyregion repository, needed for in_polygon
 #[pg_extern(immutable, parallel_safe)]
 pub fn mgx_polygon_characteristic_depth(
-    vertices_deg: Vec<VertexPSQL>,
+    vertices: Vec<VertexPSQL>,
     complement: bool,
 ) -> i32 {
-    let std_vertices_deg: Vec<(f64, f64)> = unsafe {std::mem::transmute::<Vec<VertexPSQL>, Vec<(f64, f64)>>(vertices_deg)};
-    match Polygon::from_deg(std_vertices_deg, complement) {
-        Ok(polygon) => {
-            polygon.characteristic_depth() as i32
-        }
-        Err(e) => error!("Failed to find the polygon's characteristic depth : {}", e),
-    }
+    polygon_or_error(PolygonPSQL::from_vertices(vertices, complement)).characteristic_depth() as i32
 }
 
 // Polygon
-#[pg_extern(immutable, parallel_safe)]
+// The vertices are created with mgx_create_vertexpsql (i.e. stored in radians)
+// The vertices array is keyed on its datum pointer, so it is only decoded once per scan when it is a constant
+#[pg_extern(immutable, parallel_safe, requires = [VertexPSQL])]
 pub fn mgx_skyregion_polygon_contains(
-    vertices_deg: Vec<VertexPSQL>,
+    fcinfo: pg_sys::FunctionCallInfo,
+    vertices: DatumArg<Vec<VertexPSQL>>,
     complement: bool,
     test_lon_deg: f64,
     test_lat_deg: f64,
 ) -> bool {
-    let std_vertices_deg: Vec<(f64, f64)> = unsafe {std::mem::transmute::<Vec<VertexPSQL>, Vec<(f64, f64)>>(vertices_deg)};
-    match Polygon::from_deg(std_vertices_deg, complement) {
-        Ok(polygon) => {
-            let test_lon = test_lon_deg.to_radians();
-            let test_lat = test_lat_deg.to_radians();
-
-            polygon.contains(test_lon, test_lat)
+    let key = (vertices.datum().value(), complement);
+    let build = || polygon_or_error(PolygonPSQL::from_vertices(vertices.decode(), complement)).to_sky_region().ok();
+    with_cached(fcinfo, is_arg_stable(fcinfo, 0), key, build, |polygon| {
+        match polygon {
+            Some(polygon) => {
+                let test_lon = test_lon_deg.to_radians();
+                let test_lat = test_lat_deg.to_radians();
+
+                polygon.contains(test_lon, test_lat)
+            }
+            None => false,
         }
-        Err(_) => false,
-    }
+    })
 }
 
 // Box
 #[pg_extern(immutable, parallel_safe)]
 pub fn mgx_skyregion_box_contains(
+    fcinfo: pg_sys::FunctionCallInfo,
     lon_deg: f64,
     lat_deg: f64,
     a_deg: f64,
@@ -462,20 +567,24 @@ pub fn mgx_skyregion_box_contains(
     test_lon_deg: f64,
     test_lat_deg: f64,
 ) -> bool {
-    match Polygon::from_box_deg(lon_deg, lat_deg, a_deg, b_deg, pa_deg) {
-        Ok(my_box) => {
-            let test_lon = test_lon_deg.to_radians();
-            let test_lat = test_lat_deg.to_radians();
-
-            my_box.contains(test_lon, test_lat)
+    let key = (lon_deg, lat_deg, a_deg, b_deg, pa_deg);
+    with_cached(fcinfo, true, key, || Polygon::from_box_deg(lon_deg, lat_deg, a_deg, b_deg, pa_deg).ok(), |my_box| {
+        match my_box {
+            Some(my_box) => {
+                let test_lon = test_lon_deg.to_radians();
+                let test_lat = test_lat_deg.to_radians();
+
+                my_box.contains(test_lon, test_lat)
+            }
+            None => false,
         }
-        Err(_) => false,
-    }
+    })
 }
 
 // Ring
 #[pg_extern(immutable, parallel_safe)]
 pub fn mgx_skyregion_ring_contains(
+    fcinfo: pg_sys::FunctionCallInfo,
     lon_deg: f64,
     lat_deg: f64,
     r_min_deg: f64,
@@ -483,13 +592,16 @@ pub fn mgx_skyregion_ring_contains(
     test_lon_deg: f64,
     test_lat_deg: f64,
 ) -> bool {
-    match Ring::from_deg(lon_deg, lat_deg, r_min_deg, r_max_deg) {
-        Ok(ring) => {
-            let test_lon = test_lon_deg.to_radians();
-            let test_lat = test_lat_deg.to_radians();
-
-            ring.contains(test_lon, test_lat)
+    let key = (lon_deg, lat_deg, r_min_deg, r_max_deg);
+    with_cached(fcinfo, true, key, || Ring::from_deg(lon_deg, lat_deg, r_min_deg, r_max_deg).ok(), |ring| {
+        match ring {
+            Some(ring) => {
+                let test_lon = test_lon_deg.to_radians();
+                let test_lat = test_lat_deg.to_radians();
+
+                ring.contains(test_lon, test_lat)
+            }
+            None => false,
         }
-        Err(_) => false,
-    }
+    })
 }
\ No newline at end of file
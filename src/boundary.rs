@@ -0,0 +1,280 @@
+use pgrx::prelude::*; // default
+
+// Library imports
+use std::f64::consts::PI;
+
+use crate::region::{RegionPSQL, destination, box_vertices, ellipse_vertices};
+use crate::polygon::{to_xyz, to_lon_lat, cross, dot};
+use crate::predicates::Recheck;
+
+// Number of vertices of the polygons approximating the elliptical cones (sides of about 0.5 deg for a = 90 deg)
+pub const N_ELLIPSE_VERTICES: usize = 720;
+// Tolerance, in radians, of the tests of the points on the edges
+const EPSILON: f64 = 1e-12;
+
+// ----------------------------------------------------- Edges -----------------------------------------------------------
+
+// Edge of a region : arc of the circle {x : axis.x = d} of the unit sphere (great circle if d = 0), starting at `from`
+// and going counter-clockwise around `axis` over the angle `length` (2 pi for a full circle).
+// The edges of the boundaries are oriented so that the region is on their left, i.e. on the side of `axis`.
+#[derive(Clone)]
+pub struct Edge {
+    pub axis: [f64; 3],
+    pub d: f64,
+    pub from: [f64; 3],
+    pub length: f64,
+}
+
+impl Edge {
+    // Shortest great-circle arc from a to b
+    pub fn arc(a: [f64; 3], b: [f64; 3]) -> Self {
+        let normal = cross(&a, &b);
+        let length = dot(&normal, &normal).sqrt().atan2(dot(&a, &b));
+        Edge { axis: normalized(normal), d: 0.0, from: a, length }
+    }
+
+    // Circle of radius `radius` around the center, in radians
+    pub fn circle(lon: f64, lat: f64, radius: f64) -> Self {
+        let from = to_xyz(destination(lon, lat, 0.0, radius));
+        Edge { axis: to_xyz((lon, lat)), d: radius.cos(), from, length: 2.0 * PI }
+    }
+
+    // Parallel of latitude `lat` from the longitude `lon` eastwards over `length`, in radians
+    pub fn parallel(lat: f64, lon: f64, length: f64) -> Self {
+        Edge { axis: [0.0, 0.0, 1.0], d: lat.sin(), from: to_xyz((lon, lat)), length }
+    }
+
+    // Meridian of longitude `lon` from the latitude `lat_min` northwards to `lat_max`, in radians
+    pub fn meridian(lon: f64, lat_min: f64, lat_max: f64) -> Self {
+        Edge { axis: [lon.sin(), -lon.cos(), 0.0], d: 0.0, from: to_xyz((lon, lat_min)), length: lat_max - lat_min }
+    }
+
+    // Same edge travelled in the other direction
+    pub fn reversed(&self) -> Self {
+        Edge { axis: self.axis.map(|x| -x), d: -self.d, from: self.end(), length: self.length }
+    }
+
+    // Point of the edge at the angle `angle` from its start
+    pub fn point_at(&self, angle: f64) -> [f64; 3] {
+        let center = self.axis.map(|x| self.d * x);
+        let u = [0usize, 1, 2].map(|k| self.from[k] - center[k]);
+        let v = cross(&self.axis, &u);
+        let (sin, cos) = angle.sin_cos();
+        [0usize, 1, 2].map(|k| center[k] + cos * u[k] + sin * v[k])
+    }
+
+    pub fn end(&self) -> [f64; 3] {
+        self.point_at(self.length)
+    }
+
+    pub fn middle(&self) -> [f64; 3] {
+        self.point_at(0.5 * self.length)
+    }
+
+    // Angle in [0, 2 pi[ from the start of the edge to the point p of its circle
+    fn angle_of(&self, p: &[f64; 3]) -> f64 {
+        let center = self.axis.map(|x| self.d * x);
+        let (u, w) = ([0usize, 1, 2].map(|k| self.from[k] - center[k]), [0usize, 1, 2].map(|k| p[k] - center[k]));
+        let angle = dot(&self.axis, &cross(&u, &w)).atan2(dot(&u, &w));
+        if angle < 0.0 { angle + 2.0 * PI } else { angle }
+    }
+
+    // Tests if the point p, on the circle of the edge, is on the edge
+    fn is_on_edge(&self, p: &[f64; 3]) -> bool {
+        let angle = self.angle_of(p);
+        angle <= self.length + EPSILON || angle >= 2.0 * PI - EPSILON
+    }
+
+    // Common points of the edges
+    pub fn crossings(&self, other: &Edge) -> Vec<[f64; 3]> {
+        let (n1, n2) = (&self.axis, &other.axis);
+        let c = dot(n1, n2);
+        let det = 1.0 - c * c;
+        if det < EPSILON {
+            // Parallel planes : distinct circles or the same circle, the common arcs being ignored
+            return Vec::new();
+        }
+        // Point of the line of intersection of the two planes closest to the center of the sphere
+        let (alpha, beta) = ((self.d - other.d * c) / det, (other.d - self.d * c) / det);
+        let p0 = [0usize, 1, 2].map(|k| alpha * n1[k] + beta * n2[k]);
+        let h2 = 1.0 - dot(&p0, &p0);
+        if h2 < -EPSILON {
+            return Vec::new();
+        }
+        let direction = cross(n1, n2);
+        let h = (h2.max(0.0) / dot(&direction, &direction)).sqrt();
+        [1.0, -1.0]
+            .iter()
+            .map(|sign| normalized([0usize, 1, 2].map(|k| p0[k] + sign * h * direction[k])))
+            .filter(|p| self.is_on_edge(p) && other.is_on_edge(p))
+            .collect()
+    }
+
+    // Tests if the edges have a common point
+    pub fn meets(&self, other: &Edge) -> bool {
+        !self.crossings(other).is_empty()
+    }
+
+    // Pieces of the edge between the given points of the edge
+    fn split(&self, points: &[[f64; 3]]) -> Vec<Edge> {
+        let is_circle = self.length >= 2.0 * PI - EPSILON;
+        let mut cuts: Vec<f64> = points
+            .iter()
+            .map(|p| self.angle_of(p))
+            .filter(|angle| is_circle || (*angle > EPSILON && *angle < self.length - EPSILON))
+            .collect();
+        cuts.sort_by(f64::total_cmp);
+        cuts.dedup_by(|angle, previous| *angle - *previous < EPSILON);
+        if is_circle {
+            // The circle starts and ends at its first cut
+            match cuts.first().copied() {
+                Some(first) => {
+                    if cuts.len() > 1 && cuts[cuts.len() - 1] > first + 2.0 * PI - EPSILON {
+                        cuts.pop();
+                    }
+                    cuts.push(first + 2.0 * PI);
+                }
+                None => return vec![self.clone()],
+            }
+        } else {
+            cuts.insert(0, 0.0);
+            cuts.push(self.length);
+        }
+        cuts
+            .windows(2)
+            .map(|angles| Edge { axis: self.axis, d: self.d, from: self.point_at(angles[0]), length: angles[1] - angles[0] })
+            .collect()
+    }
+}
+
+pub fn normalized(v: [f64; 3]) -> [f64; 3] {
+    let norm = dot(&v, &v).sqrt();
+    [v[0] / norm, v[1] / norm, v[2] / norm]
+}
+
+// --------------------------------------------------- Boundaries --------------------------------------------------------
+
+// Exact test of the point p of the unit sphere
+pub fn shape_contains(region: &RegionPSQL, p: &[f64; 3]) -> bool {
+    let (lon, lat) = to_lon_lat(p);
+    match region.to_sky_region() {
+        Ok(shape) => shape.recheck(lon, lat),
+        Err(e) => error!("Invalid region {:?} : {}", region, e),
+    }
+}
+
+// Great-circle arcs joining the vertices
+fn arcs(vertices: Vec<(f64, f64)>) -> Vec<Edge> {
+    let v: Vec<[f64; 3]> = vertices.into_iter().map(to_xyz).collect();
+    (0..v.len()).map(|i| Edge::arc(v[i], v[(i + 1) % v.len()])).collect()
+}
+
+// Same boundary travelled in the other direction
+fn reversed(boundary: &[Edge]) -> Vec<Edge> {
+    boundary.iter().rev().map(Edge::reversed).collect()
+}
+
+// Edges of the polygon, oriented so that the region is on their left
+fn polygon_edges(vertices: Vec<(f64, f64)>, region: &RegionPSQL) -> Vec<Edge> {
+    let edges = arcs(vertices);
+    // Point on the left of the first edge, near its middle
+    let middle = edges[0].middle();
+    let left = normalized([0usize, 1, 2].map(|k| middle[k] + 1e-7 * edges[0].axis[k]));
+    if shape_contains(region, &left) { edges } else { reversed(&edges) }
+}
+
+// Closed boundaries of the region, made of edges having the region on their left. The boundaries of the unions and
+// intersections are the pieces of the boundaries of their regions, cut where they cross the other boundaries,
+// that are on the boundary of the compound region, each piece being returned as a boundary.
+pub fn boundaries(region: &RegionPSQL) -> Vec<Vec<Edge>> {
+    match region {
+        RegionPSQL::Cone { lon, lat, radius } =>
+            vec![vec![Edge::circle(lon.to_radians(), lat.to_radians(), radius.to_radians())]],
+        RegionPSQL::Ring { lon, lat, r_min, r_max } => vec![
+            vec![Edge::circle(lon.to_radians(), lat.to_radians(), r_max.to_radians())],
+            vec![Edge::circle(lon.to_radians(), lat.to_radians(), r_min.to_radians()).reversed()],
+        ],
+        // The vertices of the ellipse go clockwise, from the north towards the east
+        RegionPSQL::EllipticalCone { lon, lat, a, b, pa } =>
+            vec![reversed(&arcs(ellipse_vertices(*lon, *lat, *a, *b, *pa, N_ELLIPSE_VERTICES)))],
+        RegionPSQL::Polygon(p) => vec![polygon_edges(p.vertices_rad.clone(), region)],
+        RegionPSQL::Box { lon, lat, a, b, pa } => vec![polygon_edges(box_vertices(*lon, *lat, *a, *b, *pa), region)],
+        RegionPSQL::Zone { lon_min, lat_min, lon_max, lat_max } => {
+            let dlon = if lon_max >= lon_min { lon_max - lon_min } else { 360.0 - lon_min + lon_max };
+            let (lon_min, lat_min, lat_max, dlon) =
+                (lon_min.to_radians(), lat_min.to_radians(), lat_max.to_radians(), dlon.to_radians());
+            // Eastwards along the lower parallel, westwards along the upper one
+            let lower = (lat_min > -0.5 * PI).then(|| Edge::parallel(lat_min, lon_min, dlon));
+            let upper = (lat_max < 0.5 * PI).then(|| Edge::parallel(lat_max, lon_min, dlon).reversed());
+            if dlon < 2.0 * PI {
+                // Two parallels and two meridians forming a single boundary
+                let mut edges: Vec<Edge> = lower.into_iter().collect();
+                edges.push(Edge::meridian(lon_min + dlon, lat_min, lat_max));
+                edges.extend(upper);
+                edges.push(Edge::meridian(lon_min, lat_min, lat_max).reversed());
+                vec![edges]
+            } else {
+                // Band around the sky : each parallel is a boundary
+                lower.into_iter().chain(upper).map(|edge| vec![edge]).collect()
+            }
+        }
+        RegionPSQL::Not(region) => boundaries(region).iter().map(|boundary| reversed(boundary)).collect(),
+        RegionPSQL::Union(regions) | RegionPSQL::Intersection(regions) => {
+            let is_union = matches!(region, RegionPSQL::Union(_));
+            let edges: Vec<Vec<Edge>> = regions.iter().map(|region| boundaries(region).into_iter().flatten().collect()).collect();
+            let mut pieces: Vec<Vec<Edge>> = Vec::new();
+            for (i, region_edges) in edges.iter().enumerate() {
+                for edge in region_edges {
+                    let crossings: Vec<[f64; 3]> = edges
+                        .iter()
+                        .enumerate()
+                        .filter(|(j, _)| *j != i)
+                        .flat_map(|(_, other_edges)| other_edges.iter().flat_map(|other_edge| edge.crossings(other_edge)))
+                        .collect();
+                    for piece in edge.split(&crossings) {
+                        // The piece is on the boundary of a union outside the other regions and on the boundary
+                        // of an intersection inside all of them
+                        let middle = piece.middle();
+                        let mut others = regions.iter().enumerate().filter(|(j, _)| *j != i).map(|(_, other)| other);
+                        let on_boundary = if is_union {
+                            !others.any(|other| shape_contains(other, &middle))
+                        } else {
+                            others.all(|other| shape_contains(other, &middle))
+                        };
+                        if on_boundary {
+                            pieces.push(vec![piece]);
+                        }
+                    }
+                }
+            }
+            pieces
+        }
+    }
+}
+
+pub fn boundaries_meet(boundaries: &[Vec<Edge>], other: &[Vec<Edge>]) -> bool {
+    boundaries.iter().flatten().any(|edge| other.iter().flatten().any(|other_edge| edge.meets(other_edge)))
+}
+
+// Closed boundaries of the region, the pieces of the boundaries of the unions and intersections being chained
+// end to start
+pub fn rings(region: &RegionPSQL) -> Vec<Vec<Edge>> {
+    let gap = |p: &[f64; 3], q: &[f64; 3]| (0..3).map(|k| (p[k] - q[k]) * (p[k] - q[k])).sum::<f64>();
+    let mut edges: Vec<Edge> = boundaries(region).into_iter().flatten().collect();
+    edges.reverse();
+    let mut rings = Vec::new();
+    while let Some(first) = edges.pop() {
+        let start = first.from;
+        let mut ring = vec![first];
+        loop {
+            let end = ring[ring.len() - 1].end();
+            let next = (0..edges.len()).min_by(|&i, &j| gap(&edges[i].from, &end).total_cmp(&gap(&edges[j].from, &end)));
+            match next {
+                Some(i) if gap(&edges[i].from, &end) < gap(&start, &end) => ring.push(edges.remove(i)),
+                _ => break,
+            }
+        }
+        rings.push(ring);
+    }
+    rings
+}
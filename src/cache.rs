@@ -0,0 +1,146 @@
+use pgrx::prelude::*; // default
+
+// Library imports
+use pgrx::{
+    callconv::{Arg, ArgAbi},
+    pgrx_sql_entity_graph::metadata::{
+        ArgumentError, Returns, ReturnsError, SqlMapping, SqlTranslatable,
+    },
+    FromDatum, IntoDatum, PgMemoryContexts,
+};
+use std::marker::PhantomData;
+
+// ------------------------------------------------ Lazy arguments -------------------------------------------------------
+
+// Argument whose decoding is postponed : pgrx decodes a PostgresType (CBOR) before calling the function,
+// which is what we want to avoid on every row when the argument is the same for the whole scan.
+// The SQL signature is the one of T.
+pub struct DatumArg<T> {
+    datum: pg_sys::Datum,
+    _marker: PhantomData<T>,
+}
+
+impl<T: FromDatum> DatumArg<T> {
+    // Raw datum, only meaningful during the current call
+    pub fn datum(&self) -> pg_sys::Datum {
+        self.datum
+    }
+
+    // Decoding of the argument (detoast + CBOR)
+    pub fn decode(&self) -> T {
+        match unsafe { T::from_datum(self.datum, false) } {
+            Some(value) => value,
+            None => error!("Failed to decode the argument"),
+        }
+    }
+}
+
+// For direct Rust calls : the value is converted to a datum allocated in the current memory context
+impl<T: IntoDatum> From<T> for DatumArg<T> {
+    fn from(value: T) -> Self {
+        match value.into_datum() {
+            Some(datum) => DatumArg { datum, _marker: PhantomData },
+            None => error!("Failed to convert the argument to a datum"),
+        }
+    }
+}
+
+impl<T> FromDatum for DatumArg<T> {
+    unsafe fn from_polymorphic_datum(datum: pg_sys::Datum, is_null: bool, _typoid: pg_sys::Oid) -> Option<Self> {
+        if is_null {
+            None
+        } else {
+            Some(DatumArg { datum, _marker: PhantomData })
+        }
+    }
+}
+
+unsafe impl<'fcx, T> ArgAbi<'fcx> for DatumArg<T>
+where
+    Self: 'fcx,
+{
+    unsafe fn unbox_arg_unchecked(arg: Arg<'_, 'fcx>) -> Self {
+        let index = arg.index();
+        unsafe { arg.unbox_arg_using_from_datum() }
+            .unwrap_or_else(|| panic!("argument {index} must not be null"))
+    }
+}
+
+unsafe impl<T: SqlTranslatable> SqlTranslatable for DatumArg<T> {
+    fn argument_sql() -> Result<SqlMapping, ArgumentError> {
+        T::argument_sql()
+    }
+
+    fn return_sql() -> Result<Returns, ReturnsError> {
+        T::return_sql()
+    }
+}
+
+// ------------------------------------------------ fn_extra cache -------------------------------------------------------
+
+// Calls `f` on the value cached in `flinfo->fn_extra` for `key`, (re)building it with `build` when the key changed.
+// The value lives in `fn_mcxt` (i.e. as long as the query) and is dropped with it.
+// Each SQL function must always use the same (K, V) types since fn_extra is a single untyped slot.
+// Without any flinfo (direct Rust call) or when the argument isn't `stable`, nothing is cached.
+pub fn with_cached<K, V, R>(
+    fcinfo: pg_sys::FunctionCallInfo,
+    stable: bool,
+    key: K,
+    build: impl FnOnce() -> V,
+    f: impl FnOnce(&V) -> R,
+) -> R
+where
+    K: PartialEq,
+{
+    if fcinfo.is_null() || !stable {
+        return f(&build());
+    }
+    unsafe {
+        let flinfo = (*fcinfo).flinfo;
+        if flinfo.is_null() {
+            return f(&build());
+        }
+        if (*flinfo).fn_extra.is_null() {
+            let slot = PgMemoryContexts::For((*flinfo).fn_mcxt).leak_and_drop_on_delete(None::<(K, V)>);
+            (*flinfo).fn_extra = slot.cast();
+        }
+        let slot = &mut *((*flinfo).fn_extra as *mut Option<(K, V)>);
+        match slot {
+            Some((cached_key, _)) if *cached_key == key => {}
+            _ => *slot = Some((key, build())),
+        }
+        match slot {
+            Some((_, value)) => f(value),
+            None => unreachable!(),
+        }
+    }
+}
+
+// Tests if the argument `argnum` is a constant (or a parameter) for the whole scan
+pub fn is_arg_stable(fcinfo: pg_sys::FunctionCallInfo, argnum: i32) -> bool {
+    unsafe {
+        !fcinfo.is_null()
+            && !(*fcinfo).flinfo.is_null()
+            && pg_sys::get_fn_expr_arg_stable((*fcinfo).flinfo, argnum)
+    }
+}
+
+// Calls `f` on the Rust structure built from the argument `argnum`, keyed on its datum pointer
+pub fn with_cached_arg<T, V, R>(
+    fcinfo: pg_sys::FunctionCallInfo,
+    argnum: i32,
+    arg: &DatumArg<T>,
+    build: impl FnOnce(T) -> V,
+    f: impl FnOnce(&V) -> R,
+) -> R
+where
+    T: FromDatum,
+{
+    with_cached(
+        fcinfo,
+        is_arg_stable(fcinfo, argnum),
+        arg.datum().value(),
+        || build(arg.decode()),
+        f,
+    )
+}
@@ -0,0 +1,311 @@
+use pgrx::prelude::*; // default
+
+// Catalog of the HEALPix-indexed tables.
+// mgx_healpix_index_table replaces the manual index creation of setup.sql : it adds a generated HEALPix hash column,
+// creates the indexes used by mgx_hash/mgx_hash_range queries and by the region predicates (see crate::predicates)
+// and records the table in mgx_indexed_tables.
+// Tables indexed by hand (e.g. with an index on mgx_hash(29, lon, lat) as in setup.sql) are recorded with
+// mgx_register_indexed_table. The searches (mgx_cone_search, mgx_region_search) and the crossmatch (mgx_crossmatch)
+// find the columns, the depth and the frame with lookup_indexed_table (see crate::search).
+// mgx_indexed_tables belongs to the extension and is dumped by pg_dump (pg_extension_config_dump).
+
+extension_sql!(
+    r#"
+CREATE TABLE mgx_indexed_tables (
+    table_name regclass PRIMARY KEY,
+    lon_column name NOT NULL,
+    lat_column name NOT NULL,
+    -- NULL when the hash is an index expression (mgx_hash(depth, lon_column, lat_column))
+    hash_column name,
+    depth integer NOT NULL CHECK (depth BETWEEN 0 AND 29),
+    -- Frame of (lon_column, lat_column), e.g. ICRS or GALACTIC
+    frame text NOT NULL DEFAULT 'ICRS',
+    -- btree index on the hash and GiST index on mgx_hash_range(29, lon_column, lat_column), if any
+    hash_index name,
+    range_index name
+);
+SELECT pg_catalog.pg_extension_config_dump('mgx_indexed_tables', '');
+"#,
+    name = "mgx_indexed_tables",
+);
+
+// Quotes an SQL identifier (like quote_ident)
+pub fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+// Quotes an SQL literal (like quote_literal)
+pub fn quote_literal(literal: &str) -> String {
+    format!("'{}'", literal.replace('\'', "''"))
+}
+
+// Runs an SQL command, raises a Postgres error if it fails
+fn run(sql: &str) {
+    if let Err(e) = Spi::run(sql) {
+        error!("Failed to run '{}' : {}", sql, e);
+    }
+}
+
+// Qualified name of the table, usable in SQL
+fn table_name_of(table_oid: pg_sys::Oid) -> String {
+    match Spi::get_one_with_args::<String>("SELECT $1::regclass::text", &[table_oid.into()]) {
+        Ok(Some(table)) => table,
+        Ok(None) => error!("Table {:?} not found", table_oid),
+        Err(e) => error!("Table {:?} not found : {}", table_oid, e),
+    }
+}
+
+// Indexes of the table
+fn index_oids(table_oid: pg_sys::Oid) -> Vec<pg_sys::Oid> {
+    match Spi::get_one_with_args::<Vec<pg_sys::Oid>>(
+        "SELECT coalesce(array_agg(indexrelid), '{}') FROM pg_index WHERE indrelid = $1",
+        &[table_oid.into()],
+    ) {
+        Ok(oids) => oids.unwrap_or_default(),
+        Err(e) => error!("Failed to read the indexes of the table {:?} : {}", table_oid, e),
+    }
+}
+
+// Runs the CREATE INDEX command `sql` and returns the name of the new index of the table
+// The names are generated by Postgres (unique in the schema and not truncated), so they are read back from pg_index
+fn create_index(table_oid: pg_sys::Oid, sql: &str) -> String {
+    let before = index_oids(table_oid);
+    run(sql);
+    let name = Spi::get_one_with_args::<String>(
+        "SELECT c.relname::text FROM pg_index i JOIN pg_class c ON c.oid = i.indexrelid
+         WHERE i.indrelid = $1 AND NOT (i.indexrelid = ANY($2))",
+        &[table_oid.into(), before.into()],
+    );
+    match name {
+        Ok(Some(name)) => name,
+        Ok(None) => error!("The index created by '{}' was not found", sql),
+        Err(e) => error!("The index created by '{}' was not found : {}", sql, e),
+    }
+}
+
+// Adds the generated column `hash_column` = mgx_hash(depth, lon_column, lat_column) to the table, creates
+//   - a btree index on `hash_column`,
+//   - a GiST index on mgx_hash_range(29, lon_column, lat_column), used by the region predicates mgx_in_*,
+// optionally clusters the table on the hash (to group the rows close on the sky) and registers the table.
+// Called by mgx_healpix_index_table(table regclass, ...) (see below).
+#[pg_extern(volatile)]
+pub fn mgx_healpix_index_table_oid(
+    table_oid: pg_sys::Oid,
+    lon_column: &str,
+    lat_column: &str,
+    depth: i32,
+    hash_column: &str,
+    cluster: bool,
+    frame: &str,
+) {
+    if !(0..=29).contains(&depth) {
+        error!("Invalid depth {} (max 29)", depth);
+    }
+    let table = table_name_of(table_oid);
+    let (lon, lat, hash) = (quote_ident(lon_column), quote_ident(lat_column), quote_ident(hash_column));
+
+    run(&format!(
+        "ALTER TABLE {table} ADD COLUMN {hash} bigint GENERATED ALWAYS AS (mgx_hash({depth}, {lon}, {lat})) STORED"
+    ));
+    let hash_index = create_index(table_oid, &format!("CREATE INDEX ON {table} ({hash})"));
+    let range_index = create_index(table_oid, &format!("CREATE INDEX ON {table} USING GIST (mgx_hash_range(29, {lon}, {lat}))"));
+    if cluster {
+        run(&format!("CLUSTER {table} USING {}", quote_ident(&hash_index)));
+        run(&format!("ANALYZE {table}"));
+    }
+
+    register_indexed_table(&table, lon_column, lat_column, depth, Some(hash_column), frame, Some(&hash_index), Some(&range_index));
+}
+
+extension_sql!(
+    r#"
+-- e.g. SELECT mgx_healpix_index_table('tyc2', 'ra_icrs_', 'de_icrs_', 29, 'healpix_hash', true);
+CREATE FUNCTION mgx_healpix_index_table(
+    tbl regclass, lon_column text, lat_column text, depth integer,
+    hash_column text DEFAULT 'healpix_hash', "cluster" boolean DEFAULT false, frame text DEFAULT 'ICRS')
+RETURNS void AS $$
+    SELECT mgx_healpix_index_table_oid(tbl::oid, lon_column, lat_column, depth, hash_column, "cluster", frame)
+$$ LANGUAGE sql VOLATILE;
+"#,
+    name = "mgx_healpix_index_table",
+    requires = [mgx_healpix_index_table_oid, "mgx_indexed_tables"]
+);
+
+// ----------------------------------------------------- Registration ----------------------------------------------------
+
+// Records (or updates) the table in mgx_indexed_tables
+#[allow(clippy::too_many_arguments)]
+fn register_indexed_table(
+    table_name: &str,
+    lon_column: &str,
+    lat_column: &str,
+    depth: i32,
+    hash_column: Option<&str>,
+    frame: &str,
+    hash_index: Option<&str>,
+    range_index: Option<&str>,
+) {
+    if !(0..=29).contains(&depth) {
+        error!("Invalid depth {} (max 29)", depth);
+    }
+    let registered = Spi::run_with_args(
+        "INSERT INTO mgx_indexed_tables (table_name, lon_column, lat_column, hash_column, depth, frame, hash_index, range_index)
+         VALUES ($1::regclass, $2, $3, $4, $5, $6, $7, $8)
+         ON CONFLICT (table_name) DO UPDATE
+         SET lon_column = EXCLUDED.lon_column, lat_column = EXCLUDED.lat_column, hash_column = EXCLUDED.hash_column,
+             depth = EXCLUDED.depth, frame = EXCLUDED.frame,
+             hash_index = EXCLUDED.hash_index, range_index = EXCLUDED.range_index",
+        &[
+            table_name.into(), lon_column.into(), lat_column.into(), hash_column.into(), depth.into(),
+            frame.to_uppercase().into(), hash_index.into(), range_index.into(),
+        ],
+    );
+    if let Err(e) = registered {
+        error!("Failed to register the table {} : {}", table_name, e);
+    }
+}
+
+// Records a table indexed by hand, e.g. for the table of setup.sql :
+// SELECT mgx_register_indexed_table('hip_table', 'raicrs', 'deicrs', 29, hash_index => 'mgx_hash_hip_idx', range_index => 'mgx_hash_range_hip_idx');
+#[allow(clippy::too_many_arguments)]
+#[pg_extern(volatile)]
+pub fn mgx_register_indexed_table(
+    table_name: &str,
+    lon_column: &str,
+    lat_column: &str,
+    depth: i32,
+    hash_column: default!(Option<&str>, "NULL"),
+    frame: default!(&str, "'ICRS'"),
+    hash_index: default!(Option<&str>, "NULL"),
+    range_index: default!(Option<&str>, "NULL"),
+) {
+    register_indexed_table(table_name, lon_column, lat_column, depth, hash_column, frame, hash_index, range_index);
+}
+
+// Removes the table from mgx_indexed_tables (the columns and the indexes are kept)
+#[pg_extern(volatile)]
+pub fn mgx_unregister_indexed_table(table_name: &str) -> bool {
+    let deleted = Spi::get_one_with_args::<bool>(
+        "WITH deleted AS (DELETE FROM mgx_indexed_tables WHERE table_name = $1::regclass RETURNING 1)
+         SELECT count(*) > 0 FROM deleted",
+        &[table_name.into()],
+    );
+    match deleted {
+        Ok(deleted) => deleted.unwrap_or(false),
+        Err(e) => error!("Failed to unregister the table {} : {}", table_name, e),
+    }
+}
+
+// ------------------------------------------------------- Lookup --------------------------------------------------------
+
+// Row of mgx_indexed_tables
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexedTable {
+    // Qualified name, usable in SQL
+    pub table: String,
+    pub lon_column: String,
+    pub lat_column: String,
+    pub hash_column: Option<String>,
+    pub depth: i32,
+    pub frame: String,
+    pub hash_index: Option<String>,
+    pub range_index: Option<String>,
+}
+
+impl IndexedTable {
+    // SQL expression of the HEALPix hash of the rows at `self.depth`
+    pub fn hash_expression(&self) -> String {
+        match &self.hash_column {
+            Some(hash_column) => quote_ident(hash_column),
+            None => format!("mgx_hash({}, {}, {})", self.depth, quote_ident(&self.lon_column), quote_ident(&self.lat_column)),
+        }
+    }
+
+    // Same as hash_expression, the columns being qualified by the alias of the table
+    pub fn hash_expression_of(&self, alias: &str) -> String {
+        match &self.hash_column {
+            Some(hash_column) => format!("{alias}.{}", quote_ident(hash_column)),
+            None => format!(
+                "mgx_hash({}, {alias}.{}, {alias}.{})",
+                self.depth, quote_ident(&self.lon_column), quote_ident(&self.lat_column)
+            ),
+        }
+    }
+
+    // Raises a Postgres error if the coordinates of the table aren't in `frame`
+    pub fn check_frame(&self, frame: &str) {
+        if !self.frame.eq_ignore_ascii_case(frame) {
+            error!("The coordinates of {} are in the frame {}, not in {}", self.table, self.frame, frame.to_uppercase());
+        }
+    }
+
+    // Number of rows estimated by the last ANALYZE (pg_class.reltuples), None if the table was never analyzed
+    pub fn n_rows(&self) -> Option<f64> {
+        let reltuples = Spi::get_one_with_args::<f32>(
+            "SELECT reltuples FROM pg_class WHERE oid = $1::regclass",
+            &[self.table.as_str().into()],
+        );
+        match reltuples {
+            Ok(Some(reltuples)) if reltuples >= 0.0 => Some(reltuples as f64),
+            Ok(_) => None,
+            Err(e) => error!("Failed to read the statistics of {} : {}", self.table, e),
+        }
+    }
+}
+
+// Registered table, None if the table isn't in mgx_indexed_tables
+pub fn lookup_indexed_table(table_name: &str) -> Option<IndexedTable> {
+    let row = Spi::connect(|client| {
+        let mut rows = client.select(
+            "SELECT table_name::text, lon_column::text, lat_column::text, hash_column::text, depth, frame,
+                    hash_index::text, range_index::text
+             FROM mgx_indexed_tables WHERE table_name = $1::regclass",
+            Some(1),
+            &[table_name.into()],
+        )?;
+        match rows.next() {
+            Some(row) => Ok::<_, pgrx::spi::Error>(Some(IndexedTable {
+                table: row.get::<String>(1)?.unwrap_or_default(),
+                lon_column: row.get::<String>(2)?.unwrap_or_default(),
+                lat_column: row.get::<String>(3)?.unwrap_or_default(),
+                hash_column: row.get::<String>(4)?,
+                depth: row.get::<i32>(5)?.unwrap_or(29),
+                frame: row.get::<String>(6)?.unwrap_or_default(),
+                hash_index: row.get::<String>(7)?,
+                range_index: row.get::<String>(8)?,
+            })),
+            None => Ok(None),
+        }
+    });
+    match row {
+        Ok(row) => row,
+        Err(e) => error!("Failed to read mgx_indexed_tables : {}", e),
+    }
+}
+
+// Same as lookup_indexed_table, raises a Postgres error if the table isn't registered
+pub fn registered_table(table_name: &str) -> IndexedTable {
+    match lookup_indexed_table(table_name) {
+        Some(table) => table,
+        None => error!("The table {} isn't registered in mgx_indexed_tables (see mgx_register_indexed_table)", table_name),
+    }
+}
+
+// Lookup from SQL, no row if the table isn't registered
+#[pg_extern(stable)]
+pub fn mgx_lookup_indexed_table(
+    table_name: &str,
+) -> TableIterator<'static, (
+    name!(lon_column, String),
+    name!(lat_column, String),
+    name!(hash_expression, String),
+    name!(depth, i32),
+    name!(frame, String),
+    name!(hash_index, Option<String>),
+    name!(range_index, Option<String>),
+)> {
+    TableIterator::new(lookup_indexed_table(table_name).map(|table| {
+        let hash_expression = table.hash_expression();
+        (table.lon_column, table.lat_column, hash_expression, table.depth, table.frame, table.hash_index, table.range_index)
+    }))
+}
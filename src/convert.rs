@@ -0,0 +1,39 @@
+// ----------------------------------------- u64 (Rust libraries) <-> i64 (Postgres bigint) -------------------------------------
+
+// HEALPix hashes, ranges and BMOC raw values are u64 in cdshealpix and moc, but Postgres only has signed bigints.
+// The conversions below are bit-for-bit (`as`) so values >= 2^63 (e.g. the BMOC raw values at depth 29)
+// are stored as negative bigints and recovered unchanged.
+// `into_iter().map(..).collect()` reuses the allocation of the input Vec (u64 and i64 have the same layout),
+// so these conversions are as cheap as the former transmutes without relying on unsafe code.
+
+use std::ops::Range as StdRange;
+
+// Vec<u64> -> Vec<i64>
+pub fn u64s_to_i64s(values: Vec<u64>) -> Vec<i64> {
+    values.into_iter().map(|v| v as i64).collect()
+}
+
+// Vec<i64> -> Vec<u64>
+pub fn i64s_to_u64s(values: Vec<i64>) -> Vec<u64> {
+    values.into_iter().map(|v| v as u64).collect()
+}
+
+// Range<u64> -> Range<i64>
+pub fn u64_range_to_i64(range: StdRange<u64>) -> StdRange<i64> {
+    (range.start as i64)..(range.end as i64)
+}
+
+// Range<i64> -> Range<u64>
+pub fn i64_range_to_u64(range: StdRange<i64>) -> StdRange<u64> {
+    (range.start as u64)..(range.end as u64)
+}
+
+// Vec<Range<u64>> -> Vec<Range<i64>>
+pub fn u64_ranges_to_i64(ranges: Vec<StdRange<u64>>) -> Vec<StdRange<i64>> {
+    ranges.into_iter().map(u64_range_to_i64).collect()
+}
+
+// Vec<Range<i64>> -> Vec<Range<u64>>
+pub fn i64_ranges_to_u64(ranges: Vec<StdRange<i64>>) -> Vec<StdRange<u64>> {
+    ranges.into_iter().map(i64_range_to_u64).collect()
+}
@@ -0,0 +1,497 @@
+use pgrx::prelude::*; // default
+
+// Library imports
+use pgrx::JsonB;
+use serde_json::json;
+use std::f64::consts::PI;
+
+use cdshealpix::compass_point::Cardinal;
+use cdshealpix::nested::bmoc::BMOC;
+
+use crate::bmoc::BMOCpsql;
+use crate::moc::{RangeMOCPSQL, moc_outlines, moc_into_cells};
+use crate::region::RegionPSQL;
+use crate::polygon::to_lon_lat;
+use crate::boundary::{Edge, rings};
+use crate::predicates::Recheck;
+
+// Export of the mogipix objects to formats understood by web maps and GIS tools.
+// The coordinates are in degrees, in the (lon, lat) order of GeoJSON, the longitudes being in [-180, 180].
+
+// ------------------------------------------------ Coordinates ----------------------------------------------------------
+
+// (lon, lat) in radians -> [lon, lat] in degrees, the longitudes being unwrapped so that two consecutive
+// vertices are never more than 180 deg apart : a path crossing lon = 0 doesn't jump to the other side of the map.
+// With `closed`, the first vertex is repeated at the end (GeoJSON rings).
+pub fn unwrapped_coordinates(vertices: &[(f64, f64)], closed: bool) -> Vec<[f64; 2]> {
+    let mut coordinates: Vec<[f64; 2]> = Vec::with_capacity(vertices.len() + 1);
+    let mut prev_lon: Option<f64> = None;
+    let first = if closed { vertices.first() } else { None };
+    for (lon, lat) in vertices.iter().chain(first) {
+        let mut lon = lon.to_degrees();
+        if let Some(prev) = prev_lon {
+            lon += 360.0 * ((prev - lon) / 360.0).round();
+        }
+        prev_lon = Some(lon);
+        coordinates.push([lon, lat.to_degrees()]);
+    }
+    coordinates
+}
+
+// ------------------------------------------------ Antimeridian ---------------------------------------------------------
+
+// RFC 7946 (section 3.1.9) : the longitudes are in [-180, 180] and the geometries crossing the antimeridian are cut in
+// two. The unwrapped longitudes are cut at the odd multiples of 180 deg, the sheet [360 k - 180, 360 k + 180] being
+// shifted by -360 k. The rings are open (the first vertex isn't repeated) until they are written.
+
+// Sheet of an unwrapped longitude
+fn sheet_of(lon: f64) -> i64 {
+    ((lon + 180.0) / 360.0).floor() as i64
+}
+
+fn lon_range(ring: &[[f64; 2]]) -> (f64, f64) {
+    ring.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), [lon, _]| (min.min(*lon), max.max(*lon)))
+}
+
+// Sheets overlapped by the ring, a ring touching a sheet boundary not overlapping the next sheet
+fn sheet_range(ring: &[[f64; 2]]) -> (i64, i64) {
+    let (min_lon, max_lon) = lon_range(ring);
+    let first = sheet_of(min_lon);
+    let last = ((max_lon + 180.0) / 360.0).ceil() as i64 - 1;
+    (first, last.max(first))
+}
+
+fn shifted(ring: &[[f64; 2]], offset: f64) -> Vec<[f64; 2]> {
+    ring.iter().map(|[lon, lat]| [lon + offset, *lat]).collect()
+}
+
+// Adds the vertex unless it is a duplicate of the previous one
+fn push_vertex(ring: &mut Vec<[f64; 2]>, vertex: [f64; 2]) {
+    if ring.last() != Some(&vertex) {
+        ring.push(vertex);
+    }
+}
+
+// Latitude of the segment (p, q) at the longitude `lon`
+fn lat_at(p: &[f64; 2], q: &[f64; 2], lon: f64) -> f64 {
+    p[1] + (q[1] - p[1]) * (lon - p[0]) / (q[0] - p[0])
+}
+
+fn closed(ring: &[[f64; 2]]) -> Vec<[f64; 2]> {
+    ring.iter().chain(ring.first()).copied().collect()
+}
+
+// Planar signed area, positive for the counter-clockwise rings
+fn signed_area(ring: &[[f64; 2]]) -> f64 {
+    let n = ring.len();
+    0.5 * (0..n).map(|i| ring[i][0] * ring[(i + 1) % n][1] - ring[(i + 1) % n][0] * ring[i][1]).sum::<f64>()
+}
+
+// Tests if the point is inside the ring, the longitude of the point being taken modulo 360 in the range of the ring
+fn ring_contains(ring: &[[f64; 2]], [lon, lat]: [f64; 2]) -> bool {
+    let (min_lon, _) = lon_range(ring);
+    let lon = lon - 360.0 * ((lon - min_lon) / 360.0).floor();
+    let n = ring.len();
+    (0..n)
+        .filter(|&i| {
+            let (p, q) = (&ring[i], &ring[(i + 1) % n]);
+            (p[1] > lat) != (q[1] > lat) && lon < p[0] + (q[0] - p[0]) * (lat - p[1]) / (q[1] - p[1])
+        })
+        .count() % 2 == 1
+}
+
+// Part of the ring on the `keep` side of the meridian `cut` (one step of the Sutherland-Hodgman algorithm)
+fn clip_ring(ring: &[[f64; 2]], cut: f64, keep: impl Fn(f64) -> bool) -> Vec<[f64; 2]> {
+    let mut clipped = Vec::with_capacity(ring.len() + 2);
+    for (i, p) in ring.iter().enumerate() {
+        let q = &ring[(i + 1) % ring.len()];
+        match (keep(p[0]), keep(q[0])) {
+            (true, true) => push_vertex(&mut clipped, *q),
+            (true, false) => push_vertex(&mut clipped, [cut, lat_at(p, q, cut)]),
+            (false, true) => {
+                push_vertex(&mut clipped, [cut, lat_at(p, q, cut)]);
+                push_vertex(&mut clipped, *q);
+            }
+            (false, false) => (),
+        }
+    }
+    clipped
+}
+
+// Outer ring joined to a hole by a two-way bridge between their closest vertices, giving a single ring that can be
+// cut along the antimeridian like any outer ring
+fn bridged(outer: &[[f64; 2]], hole: &[[f64; 2]]) -> Vec<[f64; 2]> {
+    let distance = |p: &[f64; 2], q: &[f64; 2]| (p[0] - q[0]).hypot(p[1] - q[1]);
+    let (i, j) = (0..outer.len())
+        .flat_map(|i| (0..hole.len()).map(move |j| (i, j)))
+        .min_by(|(i1, j1), (i2, j2)| distance(&outer[*i1], &hole[*j1]).total_cmp(&distance(&outer[*i2], &hole[*j2])))
+        .unwrap_or((0, 0));
+    let mut ring = outer[..=i].to_vec();
+    ring.extend(hole[j..].iter().chain(&hole[..=j]));
+    ring.extend(&outer[i..]);
+    ring
+}
+
+// Polygon (outer ring then holes, unwrapped) -> polygons with the longitudes in [-180, 180], following the right-hand
+// rule (counter-clockwise outer rings, clockwise holes). A hole crossing the antimeridian is bridged to the outer ring.
+pub fn split_polygon(mut rings: Vec<Vec<[f64; 2]>>) -> Vec<Vec<Vec<[f64; 2]>>> {
+    for (i, ring) in rings.iter_mut().enumerate() {
+        if (signed_area(ring) > 0.0) != (i == 0) {
+            ring.reverse();
+        }
+    }
+    let mut outer = rings.remove(0);
+    outer = shifted(&outer, -360.0 * sheet_of(outer[0][0]) as f64);
+    let (min_lon, _) = lon_range(&outer);
+    let mut holes: Vec<(i64, Vec<[f64; 2]>)> = Vec::new();
+    for hole in rings {
+        let hole = shifted(&hole, -360.0 * ((hole[0][0] - min_lon) / 360.0).floor());
+        match sheet_range(&hole) {
+            (first, last) if first == last => holes.push((first, hole)),
+            _ => outer = bridged(&outer, &hole),
+        }
+    }
+    let (first, last) = sheet_range(&outer);
+    (first..=last)
+        .filter_map(|sheet| {
+            let offset = 360.0 * sheet as f64;
+            let part = clip_ring(&outer, offset - 180.0, |lon| lon >= offset - 180.0);
+            let part = clip_ring(&part, offset + 180.0, |lon| lon <= offset + 180.0);
+            if part.len() < 3 || signed_area(&part).abs() < 1e-12 {
+                return None;
+            }
+            let mut polygon = vec![shifted(&part, -offset)];
+            polygon.extend(holes.iter().filter(|(s, _)| *s == sheet).map(|(_, hole)| shifted(hole, -offset)));
+            Some(polygon)
+        })
+        .collect()
+}
+
+// Unwrapped line -> lines with the longitudes in [-180, 180]
+pub fn split_line(line: &[[f64; 2]]) -> Vec<Vec<[f64; 2]>> {
+    let mut lines: Vec<Vec<[f64; 2]>> = Vec::new();
+    let Some(first) = line.first() else { return lines };
+    let mut sheet = sheet_of(first[0]);
+    let mut current = vec![[first[0] - 360.0 * sheet as f64, first[1]]];
+    for segment in line.windows(2) {
+        let (p, q) = (&segment[0], &segment[1]);
+        let next_sheet = sheet_of(q[0]);
+        while sheet != next_sheet {
+            let step = (next_sheet - sheet).signum();
+            let cut = 360.0 * sheet as f64 + 180.0 * step as f64;
+            let lat = lat_at(p, q, cut);
+            push_vertex(&mut current, [cut - 360.0 * sheet as f64, lat]);
+            lines.push(std::mem::take(&mut current));
+            sheet += step;
+            current.push([cut - 360.0 * sheet as f64, lat]);
+        }
+        push_vertex(&mut current, [q[0] - 360.0 * sheet as f64, q[1]]);
+    }
+    lines.push(current);
+    lines.retain(|line| line.len() > 1);
+    lines
+}
+
+// ------------------------------------------------- Outlines ------------------------------------------------------------
+
+// Boundaries of the MOC (see mgx_moc_to_polygons) as a GeoJSON Feature of closed lines, cut along the antimeridian
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_outline_geojson(moc: RangeMOCPSQL) -> JsonB {
+    let moc = moc.normalized_or_error();
+    let depth_max = moc.depth_max;
+    let lines: Vec<Vec<[f64; 2]>> = moc_outlines(moc)
+        .iter()
+        .flat_map(|outline| split_line(&unwrapped_coordinates(outline, true)))
+        .collect();
+    JsonB(json!({
+        "type": "Feature",
+        "geometry": { "type": "MultiLineString", "coordinates": lines },
+        "properties": { "depth_max": depth_max },
+    }))
+}
+
+// ---------------------------------------------------- Cells ------------------------------------------------------------
+
+// Polygons of the cell, one or two when the cell crosses the antimeridian.
+// The edges of the large cells aren't great circles so they are split.
+pub fn cell_polygons(depth: u8, hash: u64) -> Vec<Vec<Vec<[f64; 2]>>> {
+    let n_segments = if depth < 3 { 1u32 << (3 - depth) } else { 1 };
+    let path = cdshealpix::nested::path_along_cell_edge(depth, hash, &Cardinal::S, false, n_segments);
+    split_polygon(vec![unwrapped_coordinates(&path, false)])
+}
+
+// GeoJSON Polygon, or MultiPolygon when there are several polygons
+fn geojson_geometry(polygons: &[Vec<Vec<[f64; 2]>>]) -> serde_json::Value {
+    let coordinates: Vec<Vec<Vec<[f64; 2]>>> = polygons
+        .iter()
+        .map(|polygon| polygon.iter().map(|ring| closed(ring)).collect())
+        .collect();
+    if coordinates.len() == 1 {
+        json!({ "type": "Polygon", "coordinates": coordinates[0] })
+    } else {
+        json!({ "type": "MultiPolygon", "coordinates": coordinates })
+    }
+}
+
+fn cell_feature(depth: u8, hash: u64, is_full: Option<bool>) -> serde_json::Value {
+    let mut properties = json!({
+        "depth": depth,
+        "hash": hash,
+        "uniq": cdshealpix::nested::to_uniq(depth, hash),
+    });
+    if let Some(is_full) = is_full {
+        properties["is_full"] = json!(is_full);
+    }
+    json!({
+        "type": "Feature",
+        "geometry": geojson_geometry(&cell_polygons(depth, hash)),
+        "properties": properties,
+    })
+}
+
+fn feature_collection(features: Vec<serde_json::Value>) -> JsonB {
+    JsonB(json!({ "type": "FeatureCollection", "features": features }))
+}
+
+fn wkt_ring(ring: &[[f64; 2]]) -> String {
+    let vertices: Vec<String> = closed(ring).iter().map(|[lon, lat]| format!("{} {}", lon, lat)).collect();
+    format!("({})", vertices.join(", "))
+}
+
+fn wkt_polygon(polygon: &[Vec<[f64; 2]>]) -> String {
+    let rings: Vec<String> = polygon.iter().map(|ring| wkt_ring(ring)).collect();
+    format!("({})", rings.join(", "))
+}
+
+fn wkt_multipolygon(polygons: impl Iterator<Item = Vec<Vec<[f64; 2]>>>) -> String {
+    let polygons: Vec<String> = polygons.map(|polygon| wkt_polygon(&polygon)).collect();
+    if polygons.is_empty() {
+        String::from("MULTIPOLYGON EMPTY")
+    } else {
+        format!("MULTIPOLYGON({})", polygons.join(", "))
+    }
+}
+
+// POLYGON, or MULTIPOLYGON when there are several polygons
+fn wkt_geometry(polygons: Vec<Vec<Vec<[f64; 2]>>>) -> String {
+    if polygons.len() == 1 {
+        format!("POLYGON{}", wkt_polygon(&polygons[0]))
+    } else {
+        wkt_multipolygon(polygons.into_iter())
+    }
+}
+
+fn cells_wkt(cells: impl Iterator<Item = (u8, u64)>) -> String {
+    wkt_multipolygon(cells.flat_map(|(depth, hash)| cell_polygons(depth, hash)))
+}
+
+fn bmoc_cells(bmoc: BMOCpsql) -> Vec<(u8, u64, bool)> {
+    let std_bmoc: BMOC = bmoc.into();
+    std_bmoc
+        .entries
+        .iter()
+        .map(|raw| {
+            let cell = std_bmoc.from_raw_value(*raw);
+            (cell.depth, cell.hash, cell.is_full)
+        })
+        .collect()
+}
+
+fn checked_cell(depth: i32, hash: i64) -> (u8, u64) {
+    if !(0..=29).contains(&depth) || hash < 0 || hash as u64 >= cdshealpix::nested::n_hash(depth as u8) {
+        error!("Invalid cell ({}, {})", depth, hash);
+    }
+    (depth as u8, hash as u64)
+}
+
+// --------------------------------------------------- Regions -----------------------------------------------------------
+
+// Number of vertices of the full circles
+const N_CURVE_VERTICES: usize = 72;
+// Maximum length of the segments approximating the great-circle edges, in radians
+const MAX_EDGE_STEP: f64 = PI / 180.0;
+
+// Vertices of a closed boundary, its edges being split in segments of at most MAX_EDGE_STEP for the great circles
+// and of 2 pi / N_CURVE_VERTICES around their axis for the small circles
+fn sampled(ring: &[Edge]) -> Vec<(f64, f64)> {
+    let mut vertices = Vec::new();
+    for edge in ring {
+        let step = if edge.d == 0.0 { MAX_EDGE_STEP } else { 2.0 * PI / N_CURVE_VERTICES as f64 };
+        let n_steps = (edge.length / step).ceil().max(1.0) as usize;
+        vertices.extend((0..n_steps).map(|i| to_lon_lat(&edge.point_at(edge.length * i as f64 / n_steps as f64))));
+    }
+    vertices
+}
+
+// Point slightly inside the (counter-clockwise) ring, on the bisector of the angle of its first vertex
+fn interior_point(ring: &[[f64; 2]]) -> [f64; 2] {
+    const EPSILON: f64 = 1e-6;
+    let [lon, lat] = ring[0];
+    let direction = |[lon2, lat2]: [f64; 2]| {
+        let norm = (lon2 - lon).hypot(lat2 - lat);
+        [(lon2 - lon) / norm, (lat2 - lat) / norm]
+    };
+    let (next, prev) = (direction(ring[1]), direction(ring[ring.len() - 1]));
+    // Left of the next edge is inside, the bisector of a reflex angle pointing outside
+    let normal = [-next[1], next[0]];
+    let bisector = [next[0] + prev[0], next[1] + prev[1]];
+    let norm = bisector[0].hypot(bisector[1]);
+    let inward = if norm < 1e-9 {
+        normal
+    } else {
+        let sign = (bisector[0] * normal[0] + bisector[1] * normal[1]).signum();
+        [sign * bisector[0] / norm, sign * bisector[1] / norm]
+    };
+    [lon + EPSILON * inward[0], (lat + EPSILON * inward[1]).clamp(-90.0, 90.0)]
+}
+
+// Curve winding eastwards around a pole, restarted on the antimeridian so that it goes from lon = -180 to lon = 180
+fn from_antimeridian(curve: &[[f64; 2]]) -> Vec<[f64; 2]> {
+    let n = curve.len();
+    let offset = -360.0 * sheet_of(curve[0][0]) as f64;
+    let at = |j: usize| [curve[j % n][0] + offset + 360.0 * (j / n) as f64, curve[j % n][1]];
+    // The curve starts before lon = 180 and ends after it
+    let j = (0..n).find(|&j| at(j + 1)[0] >= 180.0).unwrap_or(0);
+    let lat = lat_at(&at(j), &at(j + 1), 180.0);
+    let mut restarted = vec![[-180.0, lat]];
+    for k in j + 1..=j + n {
+        let [lon, lat] = at(k);
+        push_vertex(&mut restarted, [lon - 360.0, lat]);
+    }
+    push_vertex(&mut restarted, [180.0, lat]);
+    restarted
+}
+
+// Polygons of a region given its boundaries (in radians) and its containment test (in degrees).
+// The boundaries winding around a pole cut the sky in strips, from the south pole to the north pole, that are outer
+// rings when they are in the region. The other boundaries are outer rings when the region is on their inner side and
+// holes of the smallest outer ring containing them otherwise.
+fn boundaries_to_polygons(boundaries: Vec<Vec<(f64, f64)>>, contains: impl Fn(f64, f64) -> bool) -> Vec<Vec<Vec<[f64; 2]>>> {
+    let mut curves: Vec<Vec<[f64; 2]>> = Vec::new();
+    let mut outers: Vec<Vec<[f64; 2]>> = Vec::new();
+    let mut holes: Vec<Vec<[f64; 2]>> = Vec::new();
+    for boundary in boundaries {
+        let mut ring = unwrapped_coordinates(&boundary, true);
+        let last = ring.pop().unwrap_or_default();
+        let winding = ((last[0] - ring[0][0]) / 360.0).round();
+        if winding != 0.0 {
+            if winding < 0.0 {
+                ring.reverse();
+            }
+            curves.push(from_antimeridian(&ring));
+        } else {
+            if signed_area(&ring) < 0.0 {
+                ring.reverse();
+            }
+            let [lon, lat] = interior_point(&ring);
+            if contains(lon, lat) { outers.push(ring) } else { holes.push(ring) }
+        }
+    }
+    // The curves don't cross each other and all start at lon = -180
+    curves.sort_by(|c1, c2| c1[0][1].total_cmp(&c2[0][1]));
+    for i in 0..=curves.len() {
+        let inside = if i == curves.len() {
+            contains(0.0, 90.0)
+        } else if i == 0 {
+            contains(0.0, -90.0)
+        } else {
+            contains(-180.0, 0.5 * (curves[i - 1][0][1] + curves[i][0][1]))
+        };
+        if inside {
+            let mut ring = match i.checked_sub(1) {
+                Some(k) => curves[k].clone(),
+                None => vec![[-180.0, -90.0], [180.0, -90.0]],
+            };
+            match curves.get(i) {
+                Some(upper) => ring.extend(upper.iter().rev()),
+                None => ring.extend([[180.0, 90.0], [-180.0, 90.0]]),
+            }
+            outers.push(ring);
+        }
+    }
+    let mut polygons: Vec<Vec<Vec<[f64; 2]>>> = outers.into_iter().map(|outer| vec![outer]).collect();
+    for hole in holes {
+        let container = polygons
+            .iter_mut()
+            .filter(|polygon| ring_contains(&polygon[0], hole[0]))
+            .min_by(|p1, p2| signed_area(&p1[0]).abs().total_cmp(&signed_area(&p2[0]).abs()));
+        if let Some(polygon) = container {
+            polygon.push(hole);
+        }
+    }
+    polygons.into_iter().flat_map(split_polygon).collect()
+}
+
+// Polygons of the region, in degrees, from its boundaries (see crate::boundary) : the small circles are approximated
+// by polygons of N_CURVE_VERTICES vertices and the great-circle edges by segments in (lon, lat)
+fn region_polygons(region: &RegionPSQL) -> Vec<Vec<Vec<[f64; 2]>>> {
+    if let RegionPSQL::Zone { lon_min, lat_min, lon_max, lat_max } = region {
+        let lon_max = if lon_max > lon_min { *lon_max } else { lon_max + 360.0 };
+        let rectangle = vec![[*lon_min, *lat_min], [lon_max, *lat_min], [lon_max, *lat_max], [*lon_min, *lat_max]];
+        return split_polygon(vec![rectangle]);
+    }
+    let boundaries = rings(region).iter().map(|ring| sampled(ring)).collect();
+    let shape = match region.to_sky_region() {
+        Ok(shape) => shape,
+        Err(e) => error!("Invalid region {:?} : {}", region, e),
+    };
+    boundaries_to_polygons(boundaries, |lon, lat| shape.recheck(lon.to_radians(), lat.to_radians()))
+}
+
+// --------------------------------------------------- GeoJSON -----------------------------------------------------------
+
+// FeatureCollection with the polygon of the cell (two polygons if it crosses the antimeridian)
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_cell_to_geojson(depth: i32, hash: i64) -> JsonB {
+    let (depth, hash) = checked_cell(depth, hash);
+    feature_collection(vec![cell_feature(depth, hash, None)])
+}
+
+// FeatureCollection with one polygon per cell of the MOC (each cell at the lowest possible depth)
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_to_geojson(moc: RangeMOCPSQL) -> JsonB {
+    feature_collection(moc_into_cells(moc.normalized_or_error()).map(|(depth, hash)| cell_feature(depth, hash, None)).collect())
+}
+
+// FeatureCollection with one polygon per cell of the BMOC, with its flag
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_bmoc_to_geojson(bmoc: BMOCpsql) -> JsonB {
+    feature_collection(bmoc_cells(bmoc).into_iter().map(|(depth, hash, is_full)| cell_feature(depth, hash, Some(is_full))).collect())
+}
+
+// Feature with the polygons of the region, the region being given in the properties
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_region_to_geojson(region: RegionPSQL) -> JsonB {
+    JsonB(json!({
+        "type": "Feature",
+        "geometry": geojson_geometry(&region_polygons(&region)),
+        "properties": { "region": region },
+    }))
+}
+
+// ----------------------------------------------------- WKT -------------------------------------------------------------
+
+// POLYGON of the cell (MULTIPOLYGON if it crosses the antimeridian)
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_cell_to_wkt(depth: i32, hash: i64) -> String {
+    let (depth, hash) = checked_cell(depth, hash);
+    wkt_geometry(cell_polygons(depth, hash))
+}
+
+// MULTIPOLYGON of the cells of the MOC
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_to_wkt(moc: RangeMOCPSQL) -> String {
+    cells_wkt(moc_into_cells(moc.normalized_or_error()))
+}
+
+// MULTIPOLYGON of the cells of the BMOC (the flags are lost)
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_bmoc_to_wkt(bmoc: BMOCpsql) -> String {
+    cells_wkt(bmoc_cells(bmoc).into_iter().map(|(depth, hash, _)| (depth, hash)))
+}
+
+// POLYGON or MULTIPOLYGON of the region
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_region_to_wkt(region: RegionPSQL) -> String {
+    wkt_geometry(region_polygons(&region))
+}
@@ -0,0 +1,235 @@
+// Library imports
+use pgrx::prelude::*;
+use pgrx::{GucContext, GucFlags, GucRegistry, GucSetting, PostgresGucEnum};
+
+// Settings of the extension (SET mogipix.<name> = ...), registered by _PG_init
+// The immutable functions (mgx_hash, mgx_hash_range, mgx_bmoc_cone_coverage, ...) never read the settings :
+// they take degrees and an explicit depth so the indexes, generated columns and cached plans built on them stay valid.
+// The unit-aware functions (suffixed by _unit, see the end of this file) are stable wrappers converting their
+// arguments before calling the immutable ones.
+// The region predicates (mgx_in_* and their mgx_*_coverage_ranges and mgx_*_recheck) and the searches read
+// mogipix.coverage_depth and mogipix.coverage_delta_depth, so they are stable too.
+
+// Depth of the BMOC coverages of the region predicates and searches, -1 to let the cost model choose it
+pub static COVERAGE_DEPTH: GucSetting<i32> = GucSetting::<i32>::new(-1);
+// Depth added to the characteristic depth of a region for its coverage, -1 to let the cost model choose it
+pub static COVERAGE_DELTA_DEPTH: GucSetting<i32> = GucSetting::<i32>::new(-1);
+// Depth used by the _unit functions called with the depth -1
+pub static DEFAULT_DEPTH: GucSetting<i32> = GucSetting::<i32>::new(29);
+// Unit of the angles taken by the _unit functions and the searches
+pub static ANGLE_UNIT: GucSetting<AngleUnit> = GucSetting::<AngleUnit>::new(AngleUnit::Deg);
+// Number of threads building the MOCs of the lists of regions (see crate::multi), 1 to build them in the backend
+pub static MAX_THREADS: GucSetting<i32> = GucSetting::<i32>::new(4);
+// Exact test of the points in the partial cells of the search coverages, the coverage only is used when off
+// Only read by the searches (see crate::search) : the mgx_in_* predicates always recheck the points
+pub static EXACT_RECHECK: GucSetting<bool> = GucSetting::<bool>::new(true);
+
+#[derive(PostgresGucEnum, Clone, Copy, PartialEq, Debug)]
+pub enum AngleUnit {
+    Deg,
+    Rad,
+    Arcsec,
+}
+
+pub fn init() {
+    GucRegistry::define_int_guc(
+        c"mogipix.coverage_depth",
+        c"Depth of the BMOC coverages used by the region predicates and searches.",
+        c"-1 (default) lets a cost model choose the depth (see mgx_optimal_coverage_depth), 0 to 29 forces it.",
+        &COVERAGE_DEPTH,
+        -1,
+        29,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        c"mogipix.coverage_delta_depth",
+        c"Depth added to the characteristic depth of a region to compute its coverage.",
+        c"-1 (default) lets a cost model choose the depth, mogipix.coverage_depth takes precedence when set.",
+        &COVERAGE_DELTA_DEPTH,
+        -1,
+        29,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        c"mogipix.default_depth",
+        c"Depth used by the _unit functions called with the depth -1.",
+        c"",
+        &DEFAULT_DEPTH,
+        0,
+        29,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_enum_guc(
+        c"mogipix.angle_unit",
+        c"Unit of the angles taken by the _unit functions and the searches (deg, rad or arcsec).",
+        c"The other functions take degrees, or radians when suffixed by _rad.",
+        &ANGLE_UNIT,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_int_guc(
+        c"mogipix.max_threads",
+        c"Number of threads building the MOCs of the lists of regions (mgx_moc_from_cones, ...).",
+        c"The threads are started once per backend, 1 builds the MOCs in the backend only.",
+        &MAX_THREADS,
+        1,
+        64,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+    GucRegistry::define_bool_guc(
+        c"mogipix.exact_recheck",
+        c"Exact test of the points in the partial cells of the search coverages.",
+        c"When off, the searches (mgx_cone_search, mgx_region_search) return all the points of the coverage. The region predicates (mgx_in_cone, ...) always test the points exactly.",
+        &EXACT_RECHECK,
+        GucContext::Userset,
+        GucFlags::default(),
+    );
+}
+
+// Depth forced by mogipix.coverage_depth, if any
+pub fn coverage_depth_override() -> Option<u8> {
+    match COVERAGE_DEPTH.get() {
+        depth if depth >= 0 => Some(depth as u8),
+        _ => None,
+    }
+}
+
+// Depth delta forced by mogipix.coverage_delta_depth, if any
+pub fn coverage_delta_depth_override() -> Option<u8> {
+    match COVERAGE_DELTA_DEPTH.get() {
+        delta_depth if delta_depth >= 0 => Some(delta_depth as u8),
+        _ => None,
+    }
+}
+
+// Depth given to a function, -1 being mogipix.default_depth
+pub fn depth_or_default(depth: i32) -> u8 {
+    match depth {
+        -1 => DEFAULT_DEPTH.get() as u8,
+        0..=29 => depth as u8,
+        _ => error!("The depth must be in [0, 29] (or -1 for mogipix.default_depth), {} given", depth),
+    }
+}
+
+// Angle in mogipix.angle_unit -> degrees
+pub fn angle_to_deg(angle: f64) -> f64 {
+    match ANGLE_UNIT.get() {
+        AngleUnit::Deg => angle,
+        AngleUnit::Rad => angle.to_degrees(),
+        AngleUnit::Arcsec => angle / 3600.0,
+    }
+}
+
+// Number of threads of the MOC builders, see mogipix.max_threads
+pub fn max_threads() -> usize {
+    MAX_THREADS.get() as usize
+}
+
+// Exact test of the partial cells, see mogipix.exact_recheck
+pub fn exact_recheck() -> bool {
+    EXACT_RECHECK.get()
+}
+
+// ------------------------------------------------ Unit-aware functions -------------------------------------------------
+
+// Angle in mogipix.angle_unit -> degrees
+#[pg_extern(stable, parallel_safe)]
+pub fn mgx_angle_to_deg(angle: f64) -> f64 {
+    angle_to_deg(angle)
+}
+
+// Depth, -1 being mogipix.default_depth
+#[pg_extern(stable, parallel_safe)]
+pub fn mgx_depth_or_default(depth: i32) -> i32 {
+    depth_or_default(depth) as i32
+}
+
+// Stable LANGUAGE sql wrappers, inlined by the planner : they can't be used in index expressions or generated columns,
+// which must call the immutable functions in degrees.
+extension_sql!(
+    r#"
+CREATE FUNCTION mgx_hash_unit(depth integer, lon double precision, lat double precision)
+RETURNS bigint AS $$
+    SELECT mgx_hash(mgx_depth_or_default(depth), mgx_angle_to_deg(lon), mgx_angle_to_deg(lat))
+$$ LANGUAGE sql STABLE PARALLEL SAFE;
+
+CREATE FUNCTION mgx_hash_range_unit(depth integer, lon double precision, lat double precision)
+RETURNS int8range AS $$
+    SELECT mgx_hash_range(mgx_depth_or_default(depth), mgx_angle_to_deg(lon), mgx_angle_to_deg(lat))
+$$ LANGUAGE sql STABLE PARALLEL SAFE;
+
+CREATE FUNCTION mgx_best_starting_depth_unit(d_max double precision)
+RETURNS integer AS $$
+    SELECT mgx_best_starting_depth(mgx_angle_to_deg(d_max))
+$$ LANGUAGE sql STABLE PARALLEL SAFE;
+
+CREATE FUNCTION mgx_is_in_moc_unit(moc RangeMOCPSQL, lon double precision, lat double precision)
+RETURNS boolean AS $$
+    SELECT mgx_is_in_moc(moc, mgx_angle_to_deg(lon), mgx_angle_to_deg(lat))
+$$ LANGUAGE sql STABLE PARALLEL SAFE;
+
+CREATE FUNCTION mgx_moc_from_cone_unit(
+    lon double precision, lat double precision, radius double precision,
+    depth integer, delta_depth integer, selection CellSelectionPSQL)
+RETURNS RangeMOCPSQL AS $$
+    SELECT mgx_moc_from_cone(mgx_angle_to_deg(lon), mgx_angle_to_deg(lat), mgx_angle_to_deg(radius),
+                             mgx_depth_or_default(depth), delta_depth, selection)
+$$ LANGUAGE sql STABLE PARALLEL SAFE;
+
+CREATE FUNCTION mgx_region_cone_unit(lon double precision, lat double precision, radius double precision)
+RETURNS RegionPSQL AS $$
+    SELECT mgx_region_cone(mgx_angle_to_deg(lon), mgx_angle_to_deg(lat), mgx_angle_to_deg(radius))
+$$ LANGUAGE sql STABLE PARALLEL SAFE;
+
+CREATE FUNCTION mgx_region_elliptical_cone_unit(
+    lon double precision, lat double precision, a double precision, b double precision, pa double precision)
+RETURNS RegionPSQL AS $$
+    SELECT mgx_region_elliptical_cone(mgx_angle_to_deg(lon), mgx_angle_to_deg(lat), mgx_angle_to_deg(a),
+                                      mgx_angle_to_deg(b), mgx_angle_to_deg(pa))
+$$ LANGUAGE sql STABLE PARALLEL SAFE;
+
+CREATE FUNCTION mgx_region_zone_unit(
+    lon_min double precision, lat_min double precision, lon_max double precision, lat_max double precision)
+RETURNS RegionPSQL AS $$
+    SELECT mgx_region_zone(mgx_angle_to_deg(lon_min), mgx_angle_to_deg(lat_min), mgx_angle_to_deg(lon_max),
+                           mgx_angle_to_deg(lat_max))
+$$ LANGUAGE sql STABLE PARALLEL SAFE;
+
+CREATE FUNCTION mgx_region_box_unit(
+    lon double precision, lat double precision, a double precision, b double precision, pa double precision)
+RETURNS RegionPSQL AS $$
+    SELECT mgx_region_box(mgx_angle_to_deg(lon), mgx_angle_to_deg(lat), mgx_angle_to_deg(a),
+                          mgx_angle_to_deg(b), mgx_angle_to_deg(pa))
+$$ LANGUAGE sql STABLE PARALLEL SAFE;
+
+CREATE FUNCTION mgx_region_ring_unit(
+    lon double precision, lat double precision, r_min double precision, r_max double precision)
+RETURNS RegionPSQL AS $$
+    SELECT mgx_region_ring(mgx_angle_to_deg(lon), mgx_angle_to_deg(lat), mgx_angle_to_deg(r_min),
+                           mgx_angle_to_deg(r_max))
+$$ LANGUAGE sql STABLE PARALLEL SAFE;
+
+CREATE FUNCTION mgx_region_contains_unit(region RegionPSQL, lon double precision, lat double precision)
+RETURNS boolean AS $$
+    SELECT mgx_region_contains(region, mgx_angle_to_deg(lon), mgx_angle_to_deg(lat))
+$$ LANGUAGE sql STABLE PARALLEL SAFE;
+
+CREATE FUNCTION mgx_region_to_moc_unit(region RegionPSQL, depth integer)
+RETURNS RangeMOCPSQL AS $$
+    SELECT mgx_region_to_moc(region, mgx_depth_or_default(depth))
+$$ LANGUAGE sql STABLE PARALLEL SAFE;
+"#,
+    name = "mgx_unit_functions",
+    requires = [
+        mgx_angle_to_deg, mgx_depth_or_default,
+        crate::mgx_hash, crate::mgx_hash_range, crate::mgx_best_starting_depth,
+        crate::moc::mgx_is_in_moc, crate::moc::mgx_moc_from_cone,
+        crate::region::mgx_region_cone, crate::region::mgx_region_elliptical_cone, crate::region::mgx_region_zone,
+        crate::region::mgx_region_box, crate::region::mgx_region_ring, crate::region::mgx_region_contains,
+        crate::region::mgx_region_to_moc,
+    ]
+);
@@ -12,6 +12,27 @@ use std::ops::RangeInclusive;
 mod bmoc;
 mod tests;
 mod moc;
+mod cache;
+mod convert;
+mod predicates;
+mod region;
+mod polygon;
+mod boundary;
+mod adql;
+mod export;
+mod catalog;
+mod search;
+mod multi;
+mod guc;
+#[cfg(feature = "postgis")]
+mod postgis;
+#[cfg(feature = "pgsphere")]
+mod pgsphere;
+
+#[pg_guard]
+pub extern "C-unwind" fn _PG_init() {
+  guc::init();
+}
 
 // HEALPix functions
 
@@ -172,7 +193,7 @@ pub fn mgx_from_zuniq(zuniq: i64) -> UniqTuple {
 // Original signature : pub fn external_edge(depth: u8, hash: u64, delta_depth: u8) -> Box<[u64]> 
 pub fn mgx_external_edge(depth: i32, hash: i64, delta_depth: i32) -> Vec<i64> {
   let vec_u64: Vec<u64> = cdshealpix::nested::external_edge(depth as u8, hash as u64, delta_depth as u8).into_vec();
-  unsafe { std::mem::transmute::<Vec<u64>, Vec<i64>>(vec_u64) }
+  convert::u64s_to_i64s(vec_u64)
 }
 
 // -------------------------------------------------- nested::internal_edge --------------------------------------------------------------
@@ -180,7 +201,7 @@ pub fn mgx_external_edge(depth: i32, hash: i64, delta_depth: i32) -> Vec<i64> {
 // Original signature : pub fn external_edge(depth: u8, hash: u64, delta_depth: u8) -> Box<[u64]> 
 pub fn mgx_internal_edge(depth: i32, hash: i64, delta_depth: i32) -> Vec<i64> {
   let vec_u64: Vec<u64> = cdshealpix::nested::internal_edge(depth as u8, hash as u64, delta_depth as u8).into_vec();
-  unsafe { std::mem::transmute::<Vec<u64>, Vec<i64>>(vec_u64) }
+  convert::u64s_to_i64s(vec_u64)
 }
 
 // -------------------------------------------------- nested::neighbours -----------------------------------------------------------------
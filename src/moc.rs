@@ -20,20 +20,82 @@ use moc::{
     elemset::range::MocRanges,
     qty::Hpx,
     deser::ascii::{from_ascii_ivoa, AsciiError},
+    deser::stcs::stcs2moc,
     elem::cellcellrange::CellOrCellRange,
 };
 
+use std::collections::BTreeMap;
+use cdshealpix::compass_point::MainWind;
+
 use crate::bmoc::*;
+use crate::region::parse_stcs;
+use crate::polygon::{PolygonPSQL, polygon_or_error};
+use crate::cache::{DatumArg, with_cached_arg};
+use crate::convert::{u64_ranges_to_i64, i64_ranges_to_u64};
 
 // ----------------------------- Postgres compatible types declarations & types conversions ------------------------------
 
 // Creation of a PSQL compatible type of RangeMOC
-#[derive(PostgresType, Debug, Serialize, Deserialize)]
+// The values read from the text input or from the stored datums are normalized while being deserialized
+#[derive(PostgresType, Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "RawRangeMOCPSQL")]
 pub struct RangeMOCPSQL {
     pub depth_max: i32,
     pub ranges: Vec<StdRange<i64>>,
 }
 
+// RangeMOCPSQL as given to the input functions, before its normalization
+#[derive(Deserialize)]
+struct RawRangeMOCPSQL {
+    depth_max: i32,
+    ranges: Vec<StdRange<i64>>,
+}
+
+impl TryFrom<RawRangeMOCPSQL> for RangeMOCPSQL {
+    type Error = String;
+
+    fn try_from(raw: RawRangeMOCPSQL) -> Result<Self, Self::Error> {
+        RangeMOCPSQL { depth_max: raw.depth_max, ranges: raw.ranges }.normalized()
+    }
+}
+
+impl RangeMOCPSQL {
+    // Fraction of the sky covered by the MOC (the ranges are at depth 29)
+    pub fn sky_fraction(&self) -> f64 {
+        let n_cells: i64 = self.ranges.iter().map(|r| r.end - r.start).sum();
+        n_cells as f64 / cdshealpix::nested::n_hash(29) as f64
+    }
+
+    // Canonical form of the MOC : ranges aligned on the cells of depth `depth_max` (enlarged if needed),
+    // sorted, non-empty, non-overlapping and non-contiguous.
+    // Fails if the depth or a range is out of the HEALPix bounds.
+    pub fn normalized(self) -> Result<Self, String> {
+        if !(0..=29).contains(&self.depth_max) {
+            return Err(format!("Invalid depth {} (max 29)", self.depth_max));
+        }
+        let n_hash_29 = cdshealpix::nested::n_hash(29);
+        let shift = 2 * (29 - self.depth_max) as u32;
+        let mut ranges: Vec<StdRange<u64>> = Vec::with_capacity(self.ranges.len());
+        for r in self.ranges {
+            if r.start < 0 || r.end < r.start || r.end as u64 > n_hash_29 {
+                return Err(format!("Invalid range {}..{} (the ranges are at depth 29)", r.start, r.end));
+            }
+            let start = ((r.start as u64) >> shift) << shift;
+            let end = (((r.end as u64) + (1u64 << shift) - 1) >> shift) << shift;
+            ranges.push(start..end);
+        }
+        Ok(RangeMOCPSQL { depth_max: self.depth_max, ranges: u64_ranges_to_i64(sort_and_merge(ranges)) })
+    }
+
+    // Same as normalized but raises a Postgres error if the MOC is invalid
+    pub fn normalized_or_error(self) -> Self {
+        match self.normalized() {
+            Ok(moc) => moc,
+            Err(e) => error!("Invalid MOC : {}", e),
+        }
+    }
+}
+
 // Creation of a StdRange type that is in the current crate to satisfy the orphan rule 
 pub struct StdRangeCrate(pub StdRange<i64>);
 
@@ -66,11 +128,9 @@ impl From<StdRangeCrate> for PgRange<i64> {
 // RangeMOCPSQL -> RangeMOC
 impl From<RangeMOCPSQL> for RangeMOC<u64, Hpx::<u64>> {
     fn from(item: RangeMOCPSQL) -> Self {
-        let mut ranges_u64 = Vec::new();
-
-        for r in item.ranges {
-            ranges_u64.push(StdRange{start : r.start as u64, end: r.end as u64});
-        }
+        // The ranges are checked since new_unchecked relies on them being canonical
+        let item = item.normalized_or_error();
+        let ranges_u64 = i64_ranges_to_u64(item.ranges);
 
         RangeMOC::new(item.depth_max as u8, MocRanges::new_unchecked(ranges_u64))
     }
@@ -86,11 +146,7 @@ impl From<RangeMOC<u64, Hpx::<u64>>> for RangeMOCPSQL {
             .0
             .clone()
             .into_vec();
-        let mut ranges_i64 = Vec::new();
-
-        for r in ranges_u64 {
-            ranges_i64.push(unsafe {std::mem::transmute::<StdRange<u64>, StdRange<i64>>(r)});
-        }
+        let ranges_i64 = u64_ranges_to_i64(ranges_u64);
 
         RangeMOCPSQL {depth_max: item.depth_max() as i32, ranges: ranges_i64}
     }
@@ -108,7 +164,7 @@ pub fn mgx_create_range_moc_psql(depth_max: i32, ranges: Vec<PgRange<i64>>) -> R
         std_ranges.push(new_range.0);
     }
     
-    RangeMOCPSQL { depth_max, ranges:std_ranges }
+    RangeMOCPSQL { depth_max, ranges:std_ranges }.normalized_or_error()
 }
 
 // Returns the vec of ranges of the moc
@@ -121,6 +177,155 @@ pub fn mgx_moc_to_ranges(moc: RangeMOCPSQL) -> Vec<PgRange<i64>> {
     res
 }
 
+// ------------------------------------------------- MOC <-> cells -------------------------------------------------------
+
+// Cell (depth, hash) -> range at depth 29, fails if the cell doesn't exist
+fn cell_to_depth_29_range(depth: u8, hash: u64) -> Result<StdRange<u64>, String> {
+    if depth > 29 {
+        return Err(format!("Invalid depth {} (max 29)", depth));
+    }
+    if hash >= cdshealpix::nested::n_hash(depth) {
+        return Err(format!("Invalid hash {} at depth {}", hash, depth));
+    }
+    let shift = 2 * (29 - depth) as u32;
+    Ok((hash << shift)..((hash + 1) << shift))
+}
+
+// Sorts the ranges and merges the overlapping or contiguous ones
+pub fn sort_and_merge(mut ranges: Vec<StdRange<u64>>) -> Vec<StdRange<u64>> {
+    ranges.sort_unstable_by_key(|r| r.start);
+    let mut merged: Vec<StdRange<u64>> = Vec::with_capacity(ranges.len());
+    for r in ranges.into_iter().filter(|r| r.start < r.end) {
+        match merged.last_mut() {
+            Some(last) if r.start <= last.end => last.end = last.end.max(r.end),
+            _ => merged.push(r),
+        }
+    }
+    merged
+}
+
+// Cells (depth, hash) at mixed depths -> normalized MOC whose depth is the deepest cell depth
+pub fn moc_from_cells(cells: impl IntoIterator<Item = (u8, u64)>) -> Result<RangeMOCPSQL, String> {
+    let mut depth_max = 0;
+    let mut ranges: Vec<StdRange<u64>> = Vec::new();
+    for (depth, hash) in cells {
+        ranges.push(cell_to_depth_29_range(depth, hash)?);
+        depth_max = depth_max.max(depth);
+    }
+    Ok(RangeMOCPSQL { depth_max: depth_max as i32, ranges: u64_ranges_to_i64(sort_and_merge(ranges)) })
+}
+
+// Decomposition of a range at depth 29 into the largest possible cells, in ascending order
+pub fn depth_29_range_to_cells(range: StdRange<u64>) -> Vec<(u8, u64)> {
+    let mut cells: Vec<(u8, u64)> = Vec::new();
+    let mut start = range.start;
+    while start < range.end {
+        // Largest cell starting at `start` and included in the range
+        let mut depth: u8 = 0;
+        loop {
+            let shift = 2 * (29 - depth) as u32;
+            let aligned = start & ((1u64 << shift) - 1) == 0;
+            if aligned && start + (1u64 << shift) <= range.end {
+                cells.push((depth, start >> shift));
+                start += 1u64 << shift;
+                break;
+            }
+            depth += 1;
+        }
+    }
+    cells
+}
+
+// MOC -> cells (depth, hash), in ascending order
+// The cells are generated range by range so the whole list never has to be in memory
+pub fn moc_into_cells(moc: RangeMOCPSQL) -> impl Iterator<Item = (u8, u64)> {
+    // Normalized first : an empty or unsorted range can't be split into cells
+    moc.normalized_or_error()
+        .ranges
+        .into_iter()
+        .flat_map(|r| depth_29_range_to_cells(r.start as u64..r.end as u64))
+}
+
+// MOC from UNIQ cells (see mgx_to_uniq)
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_from_uniq(uniqs: Vec<i64>) -> RangeMOCPSQL {
+    let mut cells: Vec<(u8, u64)> = Vec::with_capacity(uniqs.len());
+    for uniq in uniqs {
+        // The smallest UNIQ is the one of the cell 0 at depth 0 : 4
+        if uniq < 4 {
+            error!("Failed to create the MOC : invalid UNIQ {}", uniq);
+        }
+        let cell = crate::mgx_from_uniq(uniq);
+        cells.push((cell.depth as u8, cell.hash as u64));
+    }
+    match moc_from_cells(cells) {
+        Ok(moc) => moc,
+        Err(e) => error!("Failed to create the MOC : {}", e),
+    }
+}
+
+// MOC from cells given by their depth and hash
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_from_cells(depths: Vec<i32>, hashes: Vec<i64>) -> RangeMOCPSQL {
+    if depths.len() != hashes.len() {
+        error!("depths and hashes must have the same length ({} != {})", depths.len(), hashes.len());
+    }
+    let mut cells: Vec<(u8, u64)> = Vec::with_capacity(depths.len());
+    for (depth, hash) in depths.into_iter().zip(hashes) {
+        if !(0..=29).contains(&depth) || hash < 0 {
+            error!("Failed to create the MOC : invalid cell ({}, {})", depth, hash);
+        }
+        cells.push((depth as u8, hash as u64));
+    }
+    match moc_from_cells(cells) {
+        Ok(moc) => moc,
+        Err(e) => error!("Failed to create the MOC : {}", e),
+    }
+}
+
+// MOC -> UNIQ cells (each cell at the lowest possible depth), in ascending order of the cells position
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_to_uniq(moc: RangeMOCPSQL) -> Vec<i64> {
+    moc_into_cells(moc).map(|(depth, hash)| crate::mgx_to_uniq(depth as i32, hash as i64)).collect()
+}
+
+// Same as mgx_moc_to_uniq but returns one row per cell
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_to_uniq_set(moc: RangeMOCPSQL) -> SetOfIterator<'static, i64> {
+    SetOfIterator::new(moc_into_cells(moc).map(|(depth, hash)| crate::mgx_to_uniq(depth as i32, hash as i64)))
+}
+
+// Returns one row per cell of the MOC (each cell at the lowest possible depth)
+// The rows are generated on the fly so large MOCs don't have to be copied in an array
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_cells(moc: RangeMOCPSQL) -> TableIterator<'static, (name!(depth, i32), name!(hash, i64))> {
+    TableIterator::new(moc_into_cells(moc).map(|(depth, hash)| (depth as i32, hash as i64)))
+}
+
+// Hash of every cell at the given depth overlapping the MOC, in ascending order
+pub fn moc_into_cells_at(moc: RangeMOCPSQL, depth: u8) -> impl Iterator<Item = u64> {
+    let shift = 2 * (29 - depth) as u32;
+    // Two consecutive ranges can overlap the same cell at a low depth : it is returned only once
+    // The ranges are normalized first : they are then non-empty and sorted
+    let mut next_hash: u64 = 0;
+    moc.normalized_or_error().ranges.into_iter().flat_map(move |r| {
+        let first = ((r.start as u64) >> shift).max(next_hash);
+        let end = ((r.end as u64 - 1) >> shift) + 1;
+        next_hash = next_hash.max(end);
+        first..end
+    })
+}
+
+// Returns the hash of every cell at the given depth overlapping the MOC
+// For a depth lower than the MOC depth, a cell partially covered by the MOC is returned too
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_cells_at(moc: RangeMOCPSQL, depth: i32) -> SetOfIterator<'static, i64> {
+    if !(0..=29).contains(&depth) {
+        error!("Invalid depth {} (max 29)", depth);
+    }
+    SetOfIterator::new(moc_into_cells_at(moc, depth as u8).map(|hash| hash as i64))
+}
+
 // ------------------------------------------------ deser::to_ascii_ivoa -------------------------------------------------
 
 // RangeMOCPSQL -> Ascii
@@ -167,14 +372,21 @@ impl From<CellOrCellRangeMOC<u64, Hpx::<u64>>> for RangeMOCPSQL {
         let depth_max = item.depth_max() as i32;
         let vec_moc = item.moc_elems().0.0.into_vec();
         let mut vec_u64: Vec<StdRange<u64>> = Vec::new();
+        // The cells are at mixed depths, the ranges of a RangeMOCPSQL are at depth 29
         for elem in vec_moc {
             match elem {
-                CellOrCellRange::Cell(cell) => vec_u64.push(StdRange {start: cell.idx, end: cell.idx+1}),
-                CellOrCellRange::CellRange(cell_range) => vec_u64.push(cell_range.range),
+                CellOrCellRange::Cell(cell) => {
+                    let shift = 2 * (29 - cell.depth) as u32;
+                    vec_u64.push(StdRange {start: cell.idx << shift, end: (cell.idx+1) << shift})
+                }
+                CellOrCellRange::CellRange(cell_range) => {
+                    let shift = 2 * (29 - cell_range.depth) as u32;
+                    vec_u64.push(StdRange {start: cell_range.range.start << shift, end: cell_range.range.end << shift})
+                }
             }
         }
-        let vec_i64 = unsafe {std::mem::transmute::<Vec<StdRange<u64>>, Vec<StdRange<i64>>>(vec_u64)};
-        RangeMOCPSQL{depth_max, ranges: vec_i64}
+        let vec_i64 = u64_ranges_to_i64(vec_u64);
+        RangeMOCPSQL{depth_max, ranges: vec_i64}.normalized_or_error()
     }
 }
 
@@ -189,6 +401,26 @@ pub fn mgx_moc_from_ascii_ivoa(input: &str) -> SpiResult<RangeMOCPSQL> {
     res
 }
 
+// ---------------------------------------------------- deser::stcs ----------------------------------------------------
+
+// STC-S -> RangeMOC, e.g. "Circle ICRS 10.68 41.27 0.5" or "Union ICRS (Circle 10 20 1 Polygon 12 20 14 20 13 22)"
+pub fn moc_from_stcs(stcs: &str, depth: u8) -> Result<RangeMOC<u64, Hpx::<u64>>, String> {
+    // Depth used to test the cells overlap, same default as the moc CLI
+    const DELTA_DEPTH: u8 = 2;
+
+    let stc = parse_stcs(stcs)?;
+    stcs2moc(depth, DELTA_DEPTH, stc).map_err(|e| e.to_string())
+}
+
+// STC-S -> RangeMOCPSQL
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_from_stcs(stcs: &str, depth: i32) -> RangeMOCPSQL {
+    match moc_from_stcs(stcs, depth as u8) {
+        Ok(range_moc) => range_moc.into(),
+        Err(e) => error!("Failed to convert STC-S to RangeMOC: {}", e),
+    }
+}
+
 // ----------------------------------------------- moc::range::degrade -------------------------------------------------
 
 // Degrade the input MOC (= MOC complement)
@@ -199,6 +431,30 @@ pub fn mgx_moc_degrade(moc: RangeMOCPSQL, new_depth: i32) -> RangeMOCPSQL {
     std_res.into()
 }
 
+// --------------------------------------------- Normalization / depth ------------------------------------------------
+
+// Sorts and merges the ranges and aligns them on the cells of the MOC depth
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_normalize(moc: RangeMOCPSQL) -> RangeMOCPSQL {
+    moc.normalized_or_error()
+}
+
+// Changes the depth of the MOC : a deeper depth keeps the same cells, a lower depth enlarges
+// the partially covered cells (like mgx_moc_degrade)
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_set_depth_max(moc: RangeMOCPSQL, new_depth: i32) -> RangeMOCPSQL {
+    RangeMOCPSQL { depth_max: new_depth, ranges: moc.ranges }.normalized_or_error()
+}
+
+// Inverse of mgx_moc_degrade : same coverage, expressed at a deeper depth
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_refine(moc: RangeMOCPSQL, new_depth: i32) -> RangeMOCPSQL {
+    if new_depth < moc.depth_max {
+        error!("Failed to refine the MOC : the new depth {} is lower than the MOC depth {}", new_depth, moc.depth_max);
+    }
+    mgx_moc_set_depth_max(moc, new_depth)
+}
+
 // ----------------------------------------------- moc::range::expanded -------------------------------------------------
 
 // Add the MOC external border of depth `self.depth_max`.
@@ -209,19 +465,331 @@ pub fn mgx_moc_expanded(moc: RangeMOCPSQL) -> RangeMOCPSQL {
     std_res.into()
 }
 
+// --------------------------------------------- Borders and morphology ------------------------------------------------
+
+// Removes the MOC internal border of depth `self.depth_max` (inverse of mgx_moc_expanded)
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_contracted(moc: RangeMOCPSQL) -> RangeMOCPSQL {
+    let std_moc: RangeMOC<u64, Hpx::<u64>> = moc.into();
+    std_moc.contracted().into()
+}
+
+// Cells of depth `self.depth_max` outside the MOC and touching it
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_external_border(moc: RangeMOCPSQL) -> RangeMOCPSQL {
+    let std_moc: RangeMOC<u64, Hpx::<u64>> = moc.into();
+    std_moc.external_border().into()
+}
+
+// Cells of depth `self.depth_max` in the MOC and touching its complement
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_internal_border(moc: RangeMOCPSQL) -> RangeMOCPSQL {
+    let std_moc: RangeMOC<u64, Hpx::<u64>> = moc.into();
+    std_moc.internal_border().into()
+}
+
+// Dilation of the MOC by an angular distance : adds every cell of depth `self.depth_max` overlapping
+// a cone of radius `radius_deg` centred on a point of the MOC.
+// Each internal border cell is replaced by a cone around its center whose radius is enlarged by the cell
+// largest center-to-vertex distance, so the result can be slightly larger than the exact dilation.
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_expand_by_angle(moc: RangeMOCPSQL, radius_deg: f64) -> RangeMOCPSQL {
+    if radius_deg.is_nan() || radius_deg < 0.0 {
+        error!("Invalid radius {} (must be >= 0)", radius_deg);
+    }
+    let moc = moc.normalized_or_error();
+    if radius_deg == 0.0 || moc.ranges.is_empty() {
+        return moc;
+    }
+    let depth = moc.depth_max as u8;
+    let shift = 2 * (29 - depth) as u32;
+    let std_moc: RangeMOC<u64, Hpx::<u64>> = RangeMOCPSQL { depth_max: moc.depth_max, ranges: moc.ranges.clone() }.into();
+    let border: RangeMOCPSQL = std_moc.internal_border().into();
+
+    let mut ranges: Vec<StdRange<u64>> = i64_ranges_to_u64(moc.ranges);
+    for hash in moc_into_cells_at(border, depth) {
+        let (lon, lat) = cdshealpix::nested::center(depth, hash);
+        let radius = radius_deg.to_radians() + cdshealpix::largest_center_to_vertex_distance(depth, lon, lat);
+        if radius >= std::f64::consts::PI {
+            return RangeMOCPSQL { depth_max: depth as i32, ranges: vec![0..cdshealpix::nested::n_hash(29) as i64] };
+        }
+        let cone = cdshealpix::nested::cone_coverage_approx(depth, lon, lat, radius);
+        ranges.extend(cone.to_ranges().iter().map(|r| (r.start << shift)..(r.end << shift)));
+    }
+    RangeMOCPSQL { depth_max: depth as i32, ranges: u64_ranges_to_i64(sort_and_merge(ranges)) }
+}
+
+// Adds the holes of the MOC : the connected components of its complement, except the one of index `outside`
+fn fill_holes(depth_max: i32, std_moc: RangeMOC<u64, Hpx::<u64>>, mut components: Vec<RangeMOCPSQL>, outside: usize) -> RangeMOCPSQL {
+    components.swap_remove(outside);
+    let mut ranges: Vec<StdRange<u64>> = i64_ranges_to_u64(RangeMOCPSQL::from(std_moc).ranges);
+    for hole in components {
+        ranges.extend(i64_ranges_to_u64(hole.ranges));
+    }
+    RangeMOCPSQL { depth_max, ranges: u64_ranges_to_i64(sort_and_merge(ranges)) }
+}
+
+// Adds the holes of the MOC : the connected components of its complement, except the largest one
+// which is considered as the outside of the MOC.
+// For a MOC covering more than half of the sky, its outside may be smaller than one of its holes :
+// give a point of the outside with mgx_moc_fill_holes(moc, lon, lat) instead.
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_fill_holes(moc: RangeMOCPSQL) -> RangeMOCPSQL {
+    let depth_max = moc.depth_max;
+    let std_moc: RangeMOC<u64, Hpx::<u64>> = moc.into();
+    let components: Vec<RangeMOCPSQL> = split_moc(std_moc.not().into(), false);
+    if components.is_empty() {
+        return std_moc.into();
+    }
+    let outside = components
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.sky_fraction().total_cmp(&b.sky_fraction()))
+        .map(|(i, _)| i)
+        .unwrap_or(0);
+    fill_holes(depth_max, std_moc, components, outside)
+}
+
+// Adds the holes of the MOC : the connected components of its complement, except the one containing
+// the point (lon, lat) which is the outside of the MOC
+#[pg_extern(immutable, parallel_safe, name = "mgx_moc_fill_holes")]
+pub fn mgx_moc_fill_holes_around(moc: RangeMOCPSQL, lon: f64, lat: f64) -> RangeMOCPSQL {
+    let depth_max = moc.depth_max;
+    let std_moc: RangeMOC<u64, Hpx::<u64>> = moc.into();
+    let components: Vec<RangeMOCPSQL> = split_moc(std_moc.not().into(), false);
+    let outside = components.iter().position(|component| {
+        RangeMOC::<u64, Hpx::<u64>>::from(component.clone()).is_in(lon.to_radians(), lat.to_radians())
+    });
+    match outside {
+        Some(outside) => fill_holes(depth_max, std_moc, components, outside),
+        None => error!("The point ({}, {}) is in the MOC, it can't be the outside of the MOC", lon, lat),
+    }
+}
+
+// ----------------------------------------------- Connected components ------------------------------------------------
+
+// Splits the MOC into its disjoint connected components
+// With `indirect_neighbours`, cells touching only by a vertex are considered as connected
+pub fn split_moc(moc: RangeMOCPSQL, indirect_neighbours: bool) -> Vec<RangeMOCPSQL> {
+    let std_moc: RangeMOC<u64, Hpx::<u64>> = moc.into();
+    std_moc
+        .split_into_joint_mocs(indirect_neighbours)
+        .into_iter()
+        .map(RangeMOCPSQL::from)
+        .collect()
+}
+
+// Returns one MOC per connected component
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_split(moc: RangeMOCPSQL, indirect_neighbours: bool) -> SetOfIterator<'static, RangeMOCPSQL> {
+    SetOfIterator::new(split_moc(moc, indirect_neighbours))
+}
+
+// Number of connected components of the MOC
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_n_components(moc: RangeMOCPSQL, indirect_neighbours: bool) -> i32 {
+    split_moc(moc, indirect_neighbours).len() as i32
+}
+
+// ----------------------------------------------------- Outlines --------------------------------------------------------
+
+// Exact key of a vertex of the HEALPix grid at the depth `depth_max` : its integer coordinates (u, v) in the HEALPix
+// projection plane, in units of 1/nside, with u modulo 8 nside and -2 nside <= v <= 2 nside.
+// In the polar caps (|v| > nside) the right side of each triangle is the left side of the next one, so the vertices
+// on it are keyed by their left side coordinates, and the poles are single points.
+type VertexKey = (i64, i64);
+
+// Corners of a cell, in the order of cdshealpix::nested::vertices, and their (i, j) offsets in the cell
+#[derive(Clone, Copy)]
+enum Corner { S = 0, E = 1, N = 2, W = 3 }
+
+impl Corner {
+    fn offset(self) -> (i64, i64) {
+        match self {
+            Corner::S => (0, 0),
+            Corner::E => (1, 0),
+            Corner::N => (1, 1),
+            Corner::W => (0, 1),
+        }
+    }
+}
+
+fn vertex_key(depth: u8, hash: u64, corner: Corner, depth_max: u8) -> VertexKey {
+    let nside = 1i64 << depth_max;
+    let scale = 1i64 << (depth_max - depth);
+    let base_cell = (hash >> (2 * depth)) as i64;
+    // (i, j) of the cell in its base cell : even and odd bits of the hash
+    let (mut i, mut j) = (0i64, 0i64);
+    for bit in 0..depth {
+        i |= (((hash >> (2 * bit)) & 1) as i64) << bit;
+        j |= (((hash >> (2 * bit + 1)) & 1) as i64) << bit;
+    }
+    let (di, dj) = corner.offset();
+    let (i, j) = ((i + di) * scale, (j + dj) * scale);
+    // Projection of the center of the base cell, in units of pi/4
+    let (x, y) = match base_cell {
+        0..=3 => (2 * base_cell + 1, 1),
+        4..=7 => (2 * (base_cell - 4), 0),
+        _ => (2 * (base_cell - 8) + 1, -1),
+    };
+    let (u, v) = ((nside * x + i - j).rem_euclid(8 * nside), nside * (y - 1) + i + j);
+    if v.abs() <= nside {
+        return (u, v);
+    }
+    // Half-width of the polar triangles at v
+    let t = 2 * nside - v.abs();
+    if t == 0 {
+        return (0, v);
+    }
+    let center = nside * (2 * (u / (2 * nside)) + 1);
+    if u - center == t {
+        ((u + 2 * nside - 2 * t).rem_euclid(8 * nside), v)
+    } else {
+        (u, v)
+    }
+}
+
+// Sides of a cell, counter-clockwise : direction of the neighbour sharing the side, children along the side, corners
+const SIDES: [(MainWind, [u64; 2], Corner, Corner); 4] = [
+    (MainWind::SE, [0, 1], Corner::S, Corner::E),
+    (MainWind::NE, [1, 3], Corner::E, Corner::N),
+    (MainWind::NW, [3, 2], Corner::N, Corner::W),
+    (MainWind::SW, [2, 0], Corner::W, Corner::S),
+];
+
+// Tests if the cell overlaps the ranges and if it is fully inside them (sorted and merged ranges at depth 29)
+fn cell_in_ranges(ranges: &[StdRange<u64>], depth: u8, hash: u64) -> (bool, bool) {
+    let shift = 2 * (29 - depth);
+    let (start, end) = (hash << shift, (hash + 1) << shift);
+    let i = ranges.partition_point(|r| r.end <= start);
+    match ranges.get(i) {
+        Some(r) if r.start < end => (true, r.start <= start && end <= r.end),
+        _ => (false, false),
+    }
+}
+
+// Boundary segments of a MOC, chained into outlines
+struct Outlines {
+    depth_max: u8,
+    vertices: BTreeMap<VertexKey, (f64, f64)>,
+    next: BTreeMap<VertexKey, Vec<VertexKey>>,
+}
+
+impl Outlines {
+    // Adds the segments of the side of the cell (of the MOC) that are not shared with a cell of the MOC.
+    // A side is a single segment if the neighbour at the depth of the cell is fully in or fully out of the MOC,
+    // otherwise the side is split in the sides of the two children along it, down to the MOC depth if needed.
+    fn trace_side(&mut self, ranges: &[StdRange<u64>], depth: u8, hash: u64, side: usize) {
+        let (direction, children, from, to) = SIDES[side];
+        let Some(neighbour) = cdshealpix::nested::neighbours(depth, hash, false).get(direction).copied() else {
+            return;
+        };
+        match cell_in_ranges(ranges, depth, neighbour) {
+            (_, true) => (),
+            (true, false) if depth < self.depth_max => {
+                for child in children {
+                    self.trace_side(ranges, depth + 1, 4 * hash + child, side);
+                }
+            }
+            (true, false) => (),
+            (false, _) => {
+                let corners = cdshealpix::nested::vertices(depth, hash);
+                let from_key = vertex_key(depth, hash, from, self.depth_max);
+                let to_key = vertex_key(depth, hash, to, self.depth_max);
+                self.vertices.entry(from_key).or_insert(corners[from as usize]);
+                self.next.entry(from_key).or_default().push(to_key);
+            }
+        }
+    }
+}
+
+// Boundaries of the MOC as closed paths of (lon, lat) vertices in radians (the first vertex isn't repeated).
+// Only the sides of the cells with a neighbour (at least partly) outside the MOC are traced, at the depth of the cell,
+// and split at a deeper depth only where the neighbour is partly in the MOC : the memory is proportional to the number
+// of vertices of the boundaries, not to their length at the MOC depth. The vertices are matched by exact keys.
+// The outer boundaries are counter-clockwise and the boundaries of the holes are clockwise.
+pub fn moc_outlines(moc: RangeMOCPSQL) -> Vec<Vec<(f64, f64)>> {
+    let moc = moc.normalized_or_error();
+    let ranges: Vec<StdRange<u64>> = i64_ranges_to_u64(moc.ranges.clone());
+    let mut segments = Outlines { depth_max: moc.depth_max as u8, vertices: BTreeMap::new(), next: BTreeMap::new() };
+    for (depth, hash) in moc_into_cells(moc) {
+        for side in 0..SIDES.len() {
+            segments.trace_side(&ranges, depth, hash, side);
+        }
+    }
+    // Chaining of the segments
+    let Outlines { vertices, mut next, .. } = segments;
+    let mut outlines: Vec<Vec<(f64, f64)>> = Vec::new();
+    while let Some(start) = next.keys().next().copied() {
+        let mut outline: Vec<(f64, f64)> = Vec::new();
+        let mut current = start;
+        loop {
+            let Some(to) = next.get_mut(&current).and_then(|targets| targets.pop()) else {
+                break;
+            };
+            if next.get(&current).is_some_and(|targets| targets.is_empty()) {
+                next.remove(&current);
+            }
+            outline.push(vertices[&current]);
+            current = to;
+            if current == start {
+                break;
+            }
+        }
+        outlines.push(outline);
+    }
+    outlines
+}
+
+// Returns one array of vertices per boundary of the MOC (see mgx_create_vertexpsql)
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_to_polygons(moc: RangeMOCPSQL) -> SetOfIterator<'static, Vec<VertexPSQL>> {
+    SetOfIterator::new(
+        moc_outlines(moc)
+            .into_iter()
+            .map(|outline| outline.into_iter().map(VertexPSQL::from).collect()),
+    )
+}
+
 // --------------------------------------------------- Contains ----------------------------------------------------------
 
 // Tests if the cell is in the MOC 
-#[pg_extern(immutable, parallel_safe)]
-pub fn mgx_is_in_moc(moc: RangeMOCPSQL, lon: f64, lat: f64) -> bool {
-    let range_moc: RangeMOC<u64, Hpx::<u64>> = moc.into();
-    range_moc.is_in(lon.to_radians(), lat.to_radians())
+// The RangeMOC is only built once per scan when the MOC is a constant (see crate::cache)
+#[pg_extern(immutable, parallel_safe, requires = [RangeMOCPSQL])]
+pub fn mgx_is_in_moc(
+    fcinfo: pg_sys::FunctionCallInfo,
+    moc: DatumArg<RangeMOCPSQL>,
+    lon: f64,
+    lat: f64
+) -> bool {
+    with_cached_arg(fcinfo, 0, &moc, RangeMOC::<u64, Hpx::<u64>>::from, |range_moc| {
+        range_moc.is_in(lon.to_radians(), lat.to_radians())
+    })
+}
+
+// Tests if each cell (lons[i], lats[i]) is in the MOC
+#[pg_extern(immutable, parallel_safe, name = "mgx_is_in_moc", requires = [RangeMOCPSQL])]
+pub fn mgx_is_in_moc_array(
+    fcinfo: pg_sys::FunctionCallInfo,
+    moc: DatumArg<RangeMOCPSQL>,
+    lons: Vec<f64>,
+    lats: Vec<f64>
+) -> Vec<bool> {
+    if lons.len() != lats.len() {
+        error!("lons and lats must have the same length ({} != {})", lons.len(), lats.len());
+    }
+    with_cached_arg(fcinfo, 0, &moc, RangeMOC::<u64, Hpx::<u64>>::from, |range_moc| {
+        lons.iter()
+            .zip(lats.iter())
+            .map(|(lon, lat)| range_moc.is_in(lon.to_radians(), lat.to_radians()))
+            .collect()
+    })
 }
 
 //  ------------------------------- Creation of a MOC from different coverage types --------------------------------------
 
 // Creation of a PSQL compatible type of CellSelection
-#[derive(PostgresEnum, Debug, Serialize, Deserialize)]
+#[derive(PostgresEnum, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum CellSelectionPSQL {
     All,
     Inside,
@@ -280,13 +848,7 @@ pub fn mgx_moc_from_polygon(
     selection: CellSelectionPSQL
 ) -> RangeMOCPSQL
 {
-    let mut vertices_tuple: Vec<(f64,f64)> = Vec::new();
-    for vertex in vertices {
-      vertices_tuple.push(vertex.into());
-    }
-    let vertices_as_array = vertices_tuple.as_slice();
-    let range_moc: RangeMOC<u64, Hpx::<u64>> = RangeMOC::from_polygon(vertices_as_array, complement, depth as u8, selection.into());
-    range_moc.into()
+    polygon_or_error(PolygonPSQL::from_vertices(vertices, complement)).to_moc(depth as u8, selection.into()).into()
 }
 
 // Creation of a MOC from a Box
@@ -0,0 +1,293 @@
+use pgrx::prelude::*; // default
+
+// Library imports
+use pgrx::aggregate::Aggregate;
+use pgrx::datum::Internal;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use std::cell::RefCell;
+use std::rc::Rc;
+use moc::{
+    moc::range::{RangeMOC, CellSelection},
+    qty::Hpx,
+};
+
+use crate::moc::{RangeMOCPSQL, CellSelectionPSQL};
+use crate::guc::max_threads;
+
+// MOCs of lists of regions (e.g. one cone per target of a catalog).
+// The angles are converted and checked in the backend, then the MOCs of the regions are built and merged by chunks
+// in the threads of a pool of mogipix.max_threads threads, which never call Postgres. The backend checks the
+// interrupts between the batches of chunks given to the pool.
+
+// ------------------------------------------------ Union builder --------------------------------------------------------
+
+// Number of regions merged sequentially by a thread
+const CHUNK_SIZE: usize = 256;
+
+// Pool of the backend, rebuilt when mogipix.max_threads changes
+// (not the rayon global pool, which would start one thread per CPU in every backend and parallel worker)
+thread_local! {
+    static POOL: RefCell<Option<Rc<ThreadPool>>> = const { RefCell::new(None) };
+}
+
+// Pool of mogipix.max_threads threads, None if the MOCs are built in the backend
+fn pool() -> Option<Rc<ThreadPool>> {
+    let n_threads = max_threads();
+    if n_threads <= 1 {
+        return None;
+    }
+    POOL.with(|pool| {
+        let mut pool = pool.borrow_mut();
+        match pool.as_ref() {
+            Some(current) if current.current_num_threads() == n_threads => Some(current.clone()),
+            _ => match ThreadPoolBuilder::new().num_threads(n_threads).build() {
+                Ok(new_pool) => {
+                    *pool = Some(Rc::new(new_pool));
+                    pool.clone()
+                },
+                Err(e) => error!("Failed to start the {} threads of the MOC builders : {}", n_threads, e),
+            },
+        }
+    })
+}
+
+// Union of the MOCs of the chunks of regions, each chunk MOC being built by `build_chunk`
+fn union_par<T: Sync>(
+    regions: &[T],
+    depth: u8,
+    build_chunk: impl Fn(&[T]) -> RangeMOC<u64, Hpx<u64>> + Sync,
+) -> RangeMOC<u64, Hpx<u64>> {
+    let pool = pool();
+    let batch_size = CHUNK_SIZE * pool.as_ref().map_or(1, |pool| pool.current_num_threads());
+    let mut moc = RangeMOC::new_empty(depth);
+    for batch in regions.chunks(batch_size) {
+        pg_sys::check_for_interrupts!();
+        let batch_moc = match &pool {
+            Some(pool) => pool.install(|| {
+                batch
+                    .par_chunks(CHUNK_SIZE)
+                    .map(&build_chunk)
+                    .reduce(|| RangeMOC::new_empty(depth), |moc, other| moc.or(&other))
+            }),
+            None => build_chunk(batch),
+        };
+        moc = moc.or(&batch_moc);
+    }
+    moc
+}
+
+// Union of the MOCs of the regions of a chunk, built one by one
+fn union_seq<T>(regions: &[T], depth: u8, build: impl Fn(&T) -> RangeMOC<u64, Hpx<u64>>) -> RangeMOC<u64, Hpx<u64>> {
+    regions.iter().fold(RangeMOC::new_empty(depth), |moc, region| moc.or(&build(region)))
+}
+
+// Raises a Postgres error if the arrays don't have the same length
+fn check_lengths(lengths: &[(&str, usize)]) {
+    if let Some((name, len)) = lengths.iter().find(|(_, len)| *len != lengths[0].1) {
+        error!("{} and {} must have the same length ({} != {})", lengths[0].0, name, lengths[0].1, len);
+    }
+}
+
+// Depth in [0, 29], raises a Postgres error if it is invalid
+fn check_depth(depth: i32) -> u8 {
+    if !(0..=29).contains(&depth) {
+        error!("The depth must be in [0, 29], {} given", depth);
+    }
+    depth as u8
+}
+
+// Raises a Postgres error if the depth delta doesn't keep the depth of the cells in [0, 29]
+fn check_delta_depth(depth: u8, delta_depth: i32) -> u8 {
+    if !(0..=29 - depth as i32).contains(&delta_depth) {
+        error!("The delta depth must be in [0, {}] at depth {}, {} given", 29 - depth, depth, delta_depth);
+    }
+    delta_depth as u8
+}
+
+// Center of a region in radians, raises a Postgres error if it is invalid
+fn center_rad(i: usize, lon: f64, lat: f64) -> (f64, f64) {
+    let (lon, lat) = (lon.to_radians(), lat.to_radians());
+    if !lon.is_finite() || !lat.is_finite() || lat.abs() > std::f64::consts::FRAC_PI_2 {
+        error!("Invalid center ({}, {}) (in radians) for the region {}", lon, lat, i);
+    }
+    (lon, lat)
+}
+
+// Angle in radians in ]0, PI], raises a Postgres error if it is invalid
+fn size_rad(i: usize, name: &str, angle: f64) -> f64 {
+    let angle = angle.to_radians();
+    if angle.is_nan() || angle <= 0.0 || angle > std::f64::consts::PI {
+        error!("Invalid {} {} (in radians) for the region {}", name, angle, i);
+    }
+    angle
+}
+
+// ---------------------------------------------------- Cones ------------------------------------------------------------
+
+// MOC of the union of the cones (lons[i], lats[i], radii[i])
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_from_cones(
+    lons: Vec<f64>,
+    lats: Vec<f64>,
+    radii: Vec<f64>,
+    depth: i32,
+    delta_depth: default!(i32, 2),
+    selection: default!(CellSelectionPSQL, "'All'"),
+) -> RangeMOCPSQL {
+    check_lengths(&[("lons", lons.len()), ("lats", lats.len()), ("radii", radii.len())]);
+    let depth = check_depth(depth);
+    let cones: Vec<(f64, f64, f64)> = (0..lons.len())
+        .map(|i| {
+            let (lon, lat) = center_rad(i, lons[i], lats[i]);
+            (lon, lat, size_rad(i, "radius", radii[i]))
+        })
+        .collect();
+    let delta_depth = check_delta_depth(depth, delta_depth);
+    match selection.into() {
+        // Multi-cone builder of the moc crate, merging the cells of the cones of the chunk at once
+        CellSelection::All => union_par(&cones, depth, |chunk| {
+            RangeMOC::from_small_cones(depth, delta_depth, chunk.iter().copied(), Some(chunk.len() * 16))
+        }),
+        selection => union_par(&cones, depth, |chunk| union_seq(chunk, depth, |&(lon, lat, radius)| {
+            RangeMOC::from_cone(lon, lat, radius, depth, delta_depth, selection)
+        })),
+    }.into()
+}
+
+// ----------------------------------------------- Elliptical cones ------------------------------------------------------
+
+// Elliptical cone in radians : (lon, lat, a, b, pa)
+type Ellipse = (f64, f64, f64, f64, f64);
+
+// Elliptical cone in radians, raises a Postgres error if it is invalid
+fn ellipse_rad(i: usize, lon: f64, lat: f64, a: f64, b: f64, pa: f64) -> Ellipse {
+    let (lon, lat) = center_rad(i, lon, lat);
+    let (a, b, pa) = (size_rad(i, "semi-major axis", a), size_rad(i, "semi-minor axis", b), pa.to_radians());
+    if b > a || !pa.is_finite() {
+        error!("Invalid elliptical cone {} (a >= b and a finite pa expected)", i);
+    }
+    (lon, lat, a, b, pa)
+}
+
+// MOC of the union of the elliptical cones
+fn elliptical_cones_moc(ellipses: &[Ellipse], depth: u8, delta_depth: u8, selection: CellSelection) -> RangeMOC<u64, Hpx<u64>> {
+    union_par(ellipses, depth, |chunk| union_seq(chunk, depth, |&(lon, lat, a, b, pa)| {
+        RangeMOC::from_elliptical_cone(lon, lat, a, b, pa, depth, delta_depth, selection)
+    }))
+}
+
+// MOC of the union of the elliptical cones (lons[i], lats[i], a[i], b[i], pa[i])
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_from_elliptical_cones(
+    lons: Vec<f64>,
+    lats: Vec<f64>,
+    a: Vec<f64>,
+    b: Vec<f64>,
+    pa: Vec<f64>,
+    depth: i32,
+    delta_depth: default!(i32, 2),
+    selection: default!(CellSelectionPSQL, "'All'"),
+) -> RangeMOCPSQL {
+    check_lengths(&[("lons", lons.len()), ("lats", lats.len()), ("a", a.len()), ("b", b.len()), ("pa", pa.len())]);
+    let ellipses: Vec<Ellipse> = (0..lons.len())
+        .map(|i| ellipse_rad(i, lons[i], lats[i], a[i], b[i], pa[i]))
+        .collect();
+    let depth = check_depth(depth);
+    elliptical_cones_moc(&ellipses, depth, check_delta_depth(depth, delta_depth), selection.into()).into()
+}
+
+// ----------------------------------------------------- Boxes -----------------------------------------------------------
+
+// MOC of the union of the boxes (lons[i], lats[i], a[i], b[i], pa[i]), a and b being the half sizes
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_from_boxes(
+    lons: Vec<f64>,
+    lats: Vec<f64>,
+    a: Vec<f64>,
+    b: Vec<f64>,
+    pa: Vec<f64>,
+    depth: i32,
+    selection: default!(CellSelectionPSQL, "'All'"),
+) -> RangeMOCPSQL {
+    check_lengths(&[("lons", lons.len()), ("lats", lats.len()), ("a", a.len()), ("b", b.len()), ("pa", pa.len())]);
+    let depth = check_depth(depth);
+    let boxes: Vec<(f64, f64, f64, f64, f64)> = (0..lons.len())
+        .map(|i| {
+            let (lon, lat) = center_rad(i, lons[i], lats[i]);
+            let pa = pa[i].to_radians();
+            if !pa.is_finite() {
+                error!("Invalid position angle {} for the region {}", pa, i);
+            }
+            (lon, lat, size_rad(i, "a", a[i]), size_rad(i, "b", b[i]), pa)
+        })
+        .collect();
+    let selection: CellSelection = selection.into();
+    union_par(&boxes, depth, |chunk| union_seq(chunk, depth, |&(lon, lat, a, b, pa)| {
+        RangeMOC::from_box(lon, lat, a, b, pa, depth, selection)
+    })).into()
+}
+
+// -------------------------------------------------- Aggregate ----------------------------------------------------------
+
+// MOC of the elliptical cones of the rows, e.g.
+// SELECT mgx_moc_from_ellipse_agg(ra, dec, a, b, pa, 12, 2, 'All') FROM detections;
+// The ellipses are collected in the aggregate state, the MOC is built in parallel by the final function.
+// The depth, delta_depth and selection must be the same for all the rows.
+pub struct EllipseAgg;
+
+#[derive(Default)]
+pub struct EllipseAggState {
+    // (depth, delta_depth, selection), set by the first row
+    params: Option<(u8, u8, CellSelectionPSQL)>,
+    ellipses: Vec<Ellipse>,
+}
+
+#[pg_aggregate]
+impl Aggregate<EllipseAgg> for EllipseAgg {
+    type State = Internal;
+    type Args = (
+        name!(lon, f64),
+        name!(lat, f64),
+        name!(a, f64),
+        name!(b, f64),
+        name!(pa, f64),
+        name!(depth, i32),
+        name!(delta_depth, i32),
+        name!(selection, CellSelectionPSQL),
+    );
+    type Finalize = Option<RangeMOCPSQL>;
+    const NAME: &'static str = "mgx_moc_from_ellipse_agg";
+
+    fn state(
+        mut current: Self::State,
+        (lon, lat, a, b, pa, depth, delta_depth, selection): Self::Args,
+        _fcinfo: pg_sys::FunctionCallInfo,
+    ) -> Self::State {
+        let state = unsafe { current.get_or_insert_default::<EllipseAggState>() };
+        let i = state.ellipses.len();
+        match state.params {
+            None => {
+                let first_depth = check_depth(depth);
+                state.params = Some((first_depth, check_delta_depth(first_depth, delta_depth), selection));
+            },
+            Some((first_depth, first_delta_depth, first_selection)) => {
+                if (first_depth as i32, first_delta_depth as i32, first_selection) != (depth, delta_depth, selection) {
+                    error!(
+                        "The depth, delta_depth and selection must be the same for all the rows : ({}, {}, {:?}) then ({}, {}, {:?}) for the row {}",
+                        first_depth, first_delta_depth, first_selection, depth, delta_depth, selection, i
+                    );
+                }
+            },
+        }
+        state.ellipses.push(ellipse_rad(i, lon, lat, a, b, pa));
+        current
+    }
+
+    fn finalize(mut current: Self::State, _direct_args: Self::OrderedSetArgs, _fcinfo: pg_sys::FunctionCallInfo) -> Self::Finalize {
+        // No state without rows : NULL, like sum()
+        let state = unsafe { current.get_mut::<EllipseAggState>() }?;
+        let (depth, delta_depth, selection) = state.params?;
+        Some(elliptical_cones_moc(&state.ellipses, depth, delta_depth, selection.into()).into())
+    }
+}
@@ -0,0 +1,206 @@
+use pgrx::prelude::*; // default
+
+use crate::Coo;
+use crate::region::RegionPSQL;
+use crate::polygon::{PolygonPSQL, polygon_or_error};
+
+// pgSphere interoperability, compiled with the `pgsphere` feature only, to migrate the tables progressively.
+// The conversions go through the text representations of pgSphere so mogipix doesn't depend on its C API :
+//   spoint  "(lon , lat)"              <-> Coo
+//   scircle "<(lon , lat) , radius>"   <-> RegionPSQL (cone)
+//   spoly   "{(lon , lat),(lon , lat),...}" <-> RegionPSQL (polygon)
+//   smoc    "depth/cells ..."          <-> RangeMOCPSQL (IVOA ASCII serialization, see mgx_moc_from_ascii_ivoa)
+// The angles are read in radians (pgSphere default output), degrees ("10.5d"), DMS ("10d 30m 0s") or HMS ("1h 2m 3s")
+// and are written in radians so the output doesn't depend on the pgSphere output settings.
+// pgSphere must be installed in the database before mogipix since the SQL below uses its types.
+
+// ---------------------------------------------------- Parsing ----------------------------------------------------------
+
+// pgSphere angle -> radians
+fn parse_angle(s: &str) -> Result<f64, String> {
+    let s = s.trim();
+    let (sign, unsigned) = match s.strip_prefix('-') {
+        Some(rest) => (-1.0, rest),
+        None => (1.0, s.strip_prefix('+').unwrap_or(s)),
+    };
+    if !unsigned.contains(|c: char| matches!(c, 'd' | 'h' | 'm' | 's')) {
+        return s.parse::<f64>().map_err(|e| format!("Invalid angle '{}' : {}", s, e));
+    }
+    // Sexagesimal or decimal degrees (hours) : each value is followed by its unit
+    let unit_deg = if unsigned.contains('h') { 15.0 } else { 1.0 };
+    let mut value_deg = 0.0;
+    let mut number = String::new();
+    for c in unsigned.chars() {
+        let factor = match c {
+            'd' => 1.0,
+            'h' => 15.0,
+            'm' => unit_deg / 60.0,
+            's' => unit_deg / 3600.0,
+            c if c.is_whitespace() => continue,
+            c => {
+                number.push(c);
+                continue;
+            }
+        };
+        let n = number.parse::<f64>().map_err(|e| format!("Invalid angle '{}' : {}", s, e))?;
+        value_deg += n * factor;
+        number.clear();
+    }
+    if !number.is_empty() {
+        return Err(format!("Invalid angle '{}' : missing unit after '{}'", s, number));
+    }
+    Ok(sign * value_deg.to_radians())
+}
+
+// "(lon , lat)" -> (lon, lat) in radians
+fn parse_point(s: &str) -> Result<(f64, f64), String> {
+    let inner = s
+        .trim()
+        .strip_prefix('(')
+        .and_then(|s| s.strip_suffix(')'))
+        .ok_or_else(|| format!("Invalid spoint '{}'", s.trim()))?;
+    match inner.split_once(',') {
+        Some((lon, lat)) => Ok((parse_angle(lon)?, parse_angle(lat)?)),
+        None => Err(format!("Invalid spoint '{}'", s.trim())),
+    }
+}
+
+// "<(lon , lat) , radius>" -> ((lon, lat), radius) in radians
+fn parse_circle(s: &str) -> Result<((f64, f64), f64), String> {
+    let inner = s
+        .trim()
+        .strip_prefix('<')
+        .and_then(|s| s.strip_suffix('>'))
+        .ok_or_else(|| format!("Invalid scircle '{}'", s.trim()))?;
+    let end_center = inner.find(')').ok_or_else(|| format!("Invalid scircle '{}'", s.trim()))?;
+    let center = parse_point(&inner[..=end_center])?;
+    let radius = inner[end_center + 1..]
+        .trim()
+        .strip_prefix(',')
+        .ok_or_else(|| format!("Invalid scircle '{}'", s.trim()))?;
+    Ok((center, parse_angle(radius)?))
+}
+
+// "{(lon , lat),(lon , lat),...}" -> vertices in radians
+fn parse_polygon(s: &str) -> Result<Vec<(f64, f64)>, String> {
+    let inner = s
+        .trim()
+        .strip_prefix('{')
+        .and_then(|s| s.strip_suffix('}'))
+        .ok_or_else(|| format!("Invalid spoly '{}'", s.trim()))?;
+    inner
+        .split_inclusive(')')
+        .map(|point| point.trim().trim_start_matches(','))
+        .filter(|point| !point.trim().is_empty())
+        .map(parse_point)
+        .collect()
+}
+
+fn or_error<T>(result: Result<T, String>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(e) => error!("Failed to convert the pgSphere value : {}", e),
+    }
+}
+
+// ---------------------------------------------------- pgSphere -> mogipix ----------------------------------------------
+
+// spoint text -> Coo
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_coo_from_spoint_text(spoint: &str) -> Coo {
+    let (lon_rad, lat_rad) = or_error(parse_point(spoint));
+    Coo { lon_rad, lat_rad }
+}
+
+// scircle text -> cone
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_region_from_scircle_text(scircle: &str) -> RegionPSQL {
+    let ((lon, lat), radius) = or_error(parse_circle(scircle));
+    RegionPSQL::Cone { lon: lon.to_degrees(), lat: lat.to_degrees(), radius: radius.to_degrees() }.validated()
+}
+
+// spoly text -> polygon
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_region_from_spoly_text(spoly: &str) -> RegionPSQL {
+    let vertices = or_error(parse_polygon(spoly));
+    RegionPSQL::Polygon(polygon_or_error(PolygonPSQL::from_rad(vertices, false)))
+}
+
+// ---------------------------------------------------- mogipix -> pgSphere ----------------------------------------------
+
+fn format_point(lon_rad: f64, lat_rad: f64) -> String {
+    format!("({} , {})", lon_rad, lat_rad)
+}
+
+// Coo -> spoint text
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_coo_to_spoint_text(coo: Coo) -> String {
+    format_point(coo.lon_rad, coo.lat_rad)
+}
+
+// Cone -> scircle text, polygon -> spoly text, the other regions have no pgSphere equivalent
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_region_to_pgsphere_text(region: RegionPSQL) -> String {
+    match region {
+        RegionPSQL::Cone { lon, lat, radius } =>
+            format!("<{} , {}>", format_point(lon.to_radians(), lat.to_radians()), radius.to_radians()),
+        RegionPSQL::Polygon(polygon) if !polygon.complement => {
+            let vertices: Vec<String> = polygon.vertices_rad.iter().map(|(lon, lat)| format_point(*lon, *lat)).collect();
+            format!("{{{}}}", vertices.join(","))
+        }
+        region => error!("The region {:?} has no pgSphere equivalent", region),
+    }
+}
+
+// ------------------------------------------------- SQL functions -------------------------------------------------------
+
+extension_sql!(
+    r#"
+CREATE FUNCTION mgx_coo_from_spoint(p spoint) RETURNS Coo AS $$
+    SELECT mgx_coo_from_spoint_text(p::text)
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT;
+
+CREATE FUNCTION mgx_coo_to_spoint(c Coo) RETURNS spoint AS $$
+    SELECT mgx_coo_to_spoint_text(c)::spoint
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT;
+
+CREATE FUNCTION mgx_region_from_scircle(c scircle) RETURNS RegionPSQL AS $$
+    SELECT mgx_region_from_scircle_text(c::text)
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT;
+
+CREATE FUNCTION mgx_region_from_spoly(p spoly) RETURNS RegionPSQL AS $$
+    SELECT mgx_region_from_spoly_text(p::text)
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT;
+
+CREATE FUNCTION mgx_region_to_scircle(r RegionPSQL) RETURNS scircle AS $$
+    SELECT mgx_region_to_pgsphere_text(r)::scircle
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT;
+
+CREATE FUNCTION mgx_region_to_spoly(r RegionPSQL) RETURNS spoly AS $$
+    SELECT mgx_region_to_pgsphere_text(r)::spoly
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT;
+
+CREATE FUNCTION mgx_moc_from_smoc(m smoc) RETURNS RangeMOCPSQL AS $$
+    SELECT mgx_moc_from_ascii_ivoa(m::text)
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT;
+
+CREATE FUNCTION mgx_moc_to_smoc(m RangeMOCPSQL) RETURNS smoc AS $$
+    SELECT mgx_moc_to_ascii(m)::smoc
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT;
+
+CREATE CAST (spoint AS Coo) WITH FUNCTION mgx_coo_from_spoint(spoint);
+CREATE CAST (Coo AS spoint) WITH FUNCTION mgx_coo_to_spoint(Coo);
+CREATE CAST (scircle AS RegionPSQL) WITH FUNCTION mgx_region_from_scircle(scircle);
+CREATE CAST (spoly AS RegionPSQL) WITH FUNCTION mgx_region_from_spoly(spoly);
+CREATE CAST (RegionPSQL AS scircle) WITH FUNCTION mgx_region_to_scircle(RegionPSQL);
+CREATE CAST (RegionPSQL AS spoly) WITH FUNCTION mgx_region_to_spoly(RegionPSQL);
+CREATE CAST (smoc AS RangeMOCPSQL) WITH FUNCTION mgx_moc_from_smoc(smoc);
+CREATE CAST (RangeMOCPSQL AS smoc) WITH FUNCTION mgx_moc_to_smoc(RangeMOCPSQL);
+"#,
+    name = "mgx_pgsphere_casts",
+    requires = [
+        mgx_coo_from_spoint_text, mgx_coo_to_spoint_text,
+        mgx_region_from_scircle_text, mgx_region_from_spoly_text, mgx_region_to_pgsphere_text,
+        crate::moc::mgx_moc_from_ascii_ivoa, crate::moc::mgx_moc_to_ascii,
+    ]
+);
@@ -0,0 +1,250 @@
+use pgrx::prelude::*; // default
+
+// Library imports
+use serde::{Deserialize, Serialize};
+use cdshealpix::nested::bmoc::BMOC;
+use moc::{
+    moc::range::{RangeMOC, CellSelection},
+    qty::Hpx,
+};
+use skyregion::{regions::polygon::Polygon, SkyRegion};
+
+use crate::bmoc::{BMOCpsql, VertexPSQL};
+use crate::moc::{RangeMOCPSQL, CellSelectionPSQL};
+use crate::cache::{DatumArg, with_cached_arg};
+
+// ------------------------------------------------ Polygon type ---------------------------------------------------------
+
+// Polygon that is PSQL compatible
+// The vertices are always stored in radians, whatever the unit used to create the polygon, and are validated
+// at construction time so that every polygon function (coverage, contains, MOC) gets the same polygon.
+// The polygons read from the text input or from the stored datums are validated while being deserialized
+#[derive(PostgresType, Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "RawPolygonPSQL")]
+pub struct PolygonPSQL {
+    pub vertices_rad: Vec<(f64, f64)>,
+    pub complement: bool,
+}
+
+// PolygonPSQL as given to the input functions, before its validation
+#[derive(Deserialize)]
+struct RawPolygonPSQL {
+    vertices_rad: Vec<(f64, f64)>,
+    complement: bool,
+}
+
+impl TryFrom<RawPolygonPSQL> for PolygonPSQL {
+    type Error = String;
+
+    fn try_from(raw: RawPolygonPSQL) -> Result<Self, Self::Error> {
+        PolygonPSQL::from_rad(raw.vertices_rad, raw.complement)
+    }
+}
+
+// Unit vector of a (lon, lat) position in radians
+pub fn to_xyz((lon, lat): (f64, f64)) -> [f64; 3] {
+    [lat.cos() * lon.cos(), lat.cos() * lon.sin(), lat.sin()]
+}
+
+// (lon, lat) in radians of a vector, the longitude in [0, 2pi[
+pub fn to_lon_lat(v: &[f64; 3]) -> (f64, f64) {
+    let lon = v[1].atan2(v[0]);
+    let lon = if lon < 0.0 { lon + 2.0 * std::f64::consts::PI } else { lon };
+    (lon, v[2].atan2(v[0].hypot(v[1])))
+}
+
+pub fn cross(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+pub fn dot(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+// Tests if the point p, on the great circle of the arc (a, b) of normal n = a x b, is on the arc
+fn is_on_arc(a: &[f64; 3], b: &[f64; 3], n: &[f64; 3], p: &[f64; 3]) -> bool {
+    dot(&cross(a, p), n) > 0.0 && dot(&cross(p, b), n) > 0.0
+}
+
+// Tests if the great-circle arcs (a, b) and (c, d) cross each other
+fn arcs_intersect(a: &[f64; 3], b: &[f64; 3], c: &[f64; 3], d: &[f64; 3]) -> bool {
+    let n1 = cross(a, b);
+    let n2 = cross(c, d);
+    let p = cross(&n1, &n2);
+    if dot(&p, &p) < 1e-30 {
+        // Arcs on the same great circle, considered as not crossing
+        return false;
+    }
+    let minus_p = [-p[0], -p[1], -p[2]];
+    (is_on_arc(a, b, &n1, &p) && is_on_arc(c, d, &n2, &p))
+        || (is_on_arc(a, b, &n1, &minus_p) && is_on_arc(c, d, &n2, &minus_p))
+}
+
+impl PolygonPSQL {
+    // Vertices in radians
+    pub fn from_rad(vertices_rad: Vec<(f64, f64)>, complement: bool) -> Result<Self, String> {
+        let polygon = PolygonPSQL { vertices_rad, complement };
+        polygon.validate()?;
+        Ok(polygon)
+    }
+
+    // Vertices in degrees
+    pub fn from_deg(vertices_deg: Vec<(f64, f64)>, complement: bool) -> Result<Self, String> {
+        let vertices_rad = vertices_deg
+            .into_iter()
+            .map(|(lon, lat)| (lon.to_radians(), lat.to_radians()))
+            .collect();
+        Self::from_rad(vertices_rad, complement)
+    }
+
+    // Vertices created with mgx_create_vertexpsql (in radians)
+    pub fn from_vertices(vertices: Vec<VertexPSQL>, complement: bool) -> Result<Self, String> {
+        Self::from_rad(vertices.into_iter().map(<(f64, f64)>::from).collect(), complement)
+    }
+
+    // Checks that the polygon has at least 3 valid vertices, fits in a hemisphere and doesn't self-intersect
+    pub fn validate(&self) -> Result<(), String> {
+        let n = self.vertices_rad.len();
+        if n < 3 {
+            return Err(format!("A polygon needs at least 3 vertices, {} given", n));
+        }
+        for (lon, lat) in &self.vertices_rad {
+            if !lon.is_finite() || !lat.is_finite() || lat.abs() > std::f64::consts::FRAC_PI_2 {
+                return Err(format!("Invalid vertex ({}, {}) (in radians)", lon, lat));
+            }
+        }
+        let xyz: Vec<[f64; 3]> = self.vertices_rad.iter().copied().map(to_xyz).collect();
+        for i in 0..n {
+            if dot(&xyz[i], &xyz[(i + 1) % n]) > 1.0 - 1e-15 {
+                return Err(format!("Vertices {} and {} are identical", i, (i + 1) % n));
+            }
+        }
+        // All the vertices must be on the same side of the plane orthogonal to their mean direction
+        let mean = xyz.iter().fold([0.0; 3], |acc, v| [acc[0] + v[0], acc[1] + v[1], acc[2] + v[2]]);
+        if dot(&mean, &mean) < 1e-30 || xyz.iter().any(|v| dot(v, &mean) <= 0.0) {
+            return Err(String::from("The polygon doesn't fit in a hemisphere (use its complement instead)"));
+        }
+        for i in 0..n {
+            // Non adjacent edges only
+            for j in (i + 2)..n {
+                if i == 0 && j == n - 1 {
+                    continue;
+                }
+                if arcs_intersect(&xyz[i], &xyz[(i + 1) % n], &xyz[j], &xyz[(j + 1) % n]) {
+                    return Err(format!("The polygon self-intersects (edges {} and {})", i, j));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Vertices in degrees
+    pub fn vertices_deg(&self) -> Vec<(f64, f64)> {
+        self.vertices_rad.iter().map(|(lon, lat)| (lon.to_degrees(), lat.to_degrees())).collect()
+    }
+
+    // PolygonPSQL -> skyregion Polygon
+    // The polygon was validated by its constructor : the O(n^2) checks of validate are not run again here since
+    // this is called for every coverage and recheck
+    pub fn to_sky_region(&self) -> Result<Polygon, String> {
+        Polygon::from_deg(self.vertices_deg(), self.complement).map_err(|e| e.to_string())
+    }
+
+    // Like cdshealpix::best_starting_depth but for the polygon
+    pub fn characteristic_depth(&self) -> u8 {
+        match self.to_sky_region() {
+            Ok(polygon) => polygon.characteristic_depth(),
+            Err(e) => error!("Failed to find the polygon's characteristic depth : {}", e),
+        }
+    }
+
+    // BMOC of the polygon at the given depth
+    pub fn to_bmoc(&self, depth: u8, exact_solution: bool) -> BMOC {
+        let bmoc = cdshealpix::nested::polygon_coverage(depth, self.vertices_rad.as_slice(), exact_solution);
+        if self.complement { bmoc.not() } else { bmoc }
+    }
+
+    // MOC of the polygon at the given depth
+    pub fn to_moc(&self, depth: u8, selection: CellSelection) -> RangeMOC<u64, Hpx<u64>> {
+        RangeMOC::from_polygon(self.vertices_rad.as_slice(), self.complement, depth, selection)
+    }
+}
+
+// Raises a Postgres error if the polygon is invalid
+pub fn polygon_or_error(polygon: Result<PolygonPSQL, String>) -> PolygonPSQL {
+    match polygon {
+        Ok(polygon) => polygon,
+        Err(e) => error!("Invalid polygon : {}", e),
+    }
+}
+
+// Raises a Postgres error if the depth isn't in [0, 29]
+fn depth_or_error(depth: i32) -> u8 {
+    if !(0..=29).contains(&depth) {
+        error!("The depth must be in [0, 29], {} given", depth);
+    }
+    depth as u8
+}
+
+// ------------------------------------------------ Constructors ---------------------------------------------------------
+
+// Polygon from its vertices in degrees
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_polygon_from_deg(lons_deg: Vec<f64>, lats_deg: Vec<f64>, complement: bool) -> PolygonPSQL {
+    if lons_deg.len() != lats_deg.len() {
+        error!("lons and lats must have the same length ({} != {})", lons_deg.len(), lats_deg.len());
+    }
+    polygon_or_error(PolygonPSQL::from_deg(lons_deg.into_iter().zip(lats_deg).collect(), complement))
+}
+
+// Polygon from its vertices in radians
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_polygon_from_rad(lons_rad: Vec<f64>, lats_rad: Vec<f64>, complement: bool) -> PolygonPSQL {
+    if lons_rad.len() != lats_rad.len() {
+        error!("lons and lats must have the same length ({} != {})", lons_rad.len(), lats_rad.len());
+    }
+    polygon_or_error(PolygonPSQL::from_rad(lons_rad.into_iter().zip(lats_rad).collect(), complement))
+}
+
+// Polygon from vertices created with mgx_create_vertexpsql
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_polygon_from_vertices(vertices: Vec<VertexPSQL>, complement: bool) -> PolygonPSQL {
+    polygon_or_error(PolygonPSQL::from_vertices(vertices, complement))
+}
+
+// Returns the vertices in degrees
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_polygon_vertices_deg(polygon: PolygonPSQL) -> Vec<f64> {
+    polygon.vertices_deg().into_iter().flat_map(|(lon, lat)| [lon, lat]).collect()
+}
+
+// ------------------------------------------------ Operations -----------------------------------------------------------
+
+// Like mgx_best_starting_depth but for the polygon
+#[pg_extern(immutable, parallel_safe, name = "mgx_polygon_characteristic_depth")]
+pub fn mgx_polygon_characteristic_depth_of(polygon: PolygonPSQL) -> i32 {
+    polygon.characteristic_depth() as i32
+}
+
+// Tests if the point (in degrees) is in the polygon
+// The skyregion polygon is only built once per scan when the polygon is a constant (see crate::cache)
+#[pg_extern(immutable, parallel_safe, requires = [PolygonPSQL])]
+pub fn mgx_polygon_contains(fcinfo: pg_sys::FunctionCallInfo, polygon: DatumArg<PolygonPSQL>, lon: f64, lat: f64) -> bool {
+    let build = |polygon: PolygonPSQL| match polygon.to_sky_region() {
+        Ok(polygon) => polygon,
+        Err(e) => error!("Invalid polygon : {}", e),
+    };
+    with_cached_arg(fcinfo, 0, &polygon, build, |polygon| polygon.contains(lon.to_radians(), lat.to_radians()))
+}
+
+// BMOC of the polygon
+#[pg_extern(immutable, parallel_safe, name = "mgx_bmoc_polygon_coverage")]
+pub fn mgx_bmoc_polygon_coverage_of(depth: i32, polygon: PolygonPSQL, exact_solution: bool) -> BMOCpsql {
+    polygon.to_bmoc(depth_or_error(depth), exact_solution).into()
+}
+
+// MOC of the polygon
+#[pg_extern(immutable, parallel_safe, name = "mgx_moc_from_polygon")]
+pub fn mgx_moc_from_polygon_of(polygon: PolygonPSQL, depth: i32, selection: CellSelectionPSQL) -> RangeMOCPSQL {
+    polygon.to_moc(depth_or_error(depth), selection.into()).into()
+}
@@ -0,0 +1,168 @@
+use pgrx::prelude::*; // default
+
+// Library imports
+use moc::{moc::range::{RangeMOC, CellSelection}, qty::Hpx};
+
+use crate::moc::RangeMOCPSQL;
+use crate::polygon::PolygonPSQL;
+
+// PostGIS interoperability, compiled with the `postgis` feature only.
+// The conversions go through WKT so mogipix doesn't depend on the PostGIS C API :
+//   geometry/geography -> ST_AsText -> mgx_moc_from_wkt -> RangeMOCPSQL
+//   RangeMOCPSQL -> mgx_moc_to_wkt -> ST_GeomFromText/ST_GeogFromText -> geometry/geography
+//   RegionPSQL -> mgx_region_to_wkt -> ST_GeomFromText/ST_GeogFromText -> geometry/geography
+// PostGIS must be installed in the database before mogipix since the SQL below uses its types.
+// The coordinates are (lon, lat) in degrees (SRID 4326).
+// The edges of the polygons are great-circle arcs, as for the geography type : the edges of a planar geometry are
+// not straight lines in (lon, lat), so the MOC of a large geometry polygon differs from its planar area.
+// There is no cast from geometry/geography to MOC since a MOC needs a depth : use the functions taking one.
+
+// -------------------------------------------------- WKT parsing --------------------------------------------------------
+
+// Splits "(a), (b, c), d" on the commas which are outside of any parentheses
+fn split_top_level(s: &str) -> Vec<&str> {
+    let mut parts: Vec<&str> = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(s[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(s[start..].trim());
+    parts.into_iter().filter(|part| !part.is_empty()).collect()
+}
+
+// "(content)" -> "content"
+fn strip_parentheses(s: &str) -> Result<&str, String> {
+    s.trim()
+        .strip_prefix('(')
+        .and_then(|s| s.strip_suffix(')'))
+        .ok_or_else(|| format!("Parentheses expected around '{}'", s))
+}
+
+// "lon lat, lon lat, ..." -> vertices in degrees, without the closing vertex
+fn parse_ring(s: &str) -> Result<Vec<(f64, f64)>, String> {
+    let mut vertices: Vec<(f64, f64)> = Vec::new();
+    for point in strip_parentheses(s)?.split(',') {
+        // The Z and M coordinates are ignored
+        let coords: Vec<f64> = point
+            .split_whitespace()
+            .map(|c| c.parse::<f64>().map_err(|e| format!("Invalid coordinate '{}' : {}", c, e)))
+            .collect::<Result<_, _>>()?;
+        if coords.len() < 2 {
+            return Err(format!("Invalid point '{}'", point.trim()));
+        }
+        vertices.push((coords[0], coords[1]));
+    }
+    if vertices.len() > 1 && vertices.first() == vertices.last() {
+        vertices.pop();
+    }
+    Ok(vertices)
+}
+
+// "(outer), (hole), ..." -> rings
+fn parse_polygon(s: &str) -> Result<Vec<Vec<(f64, f64)>>, String> {
+    split_top_level(strip_parentheses(s)?).into_iter().map(parse_ring).collect()
+}
+
+// WKT or EWKT POLYGON / MULTIPOLYGON -> polygons, each one being its outer ring followed by its holes
+pub fn parse_wkt_polygons(wkt: &str) -> Result<Vec<Vec<Vec<(f64, f64)>>>, String> {
+    // EWKT prefix, e.g. "SRID=4326;POLYGON(...)"
+    let wkt = match wkt.split_once(';') {
+        Some((srid, wkt)) if srid.trim().to_uppercase().starts_with("SRID=") => wkt,
+        _ => wkt,
+    };
+    let wkt = wkt.trim();
+    let (kind, body) = match wkt.find(|c: char| c == '(' || c.is_whitespace()) {
+        Some(i) => (wkt[..i].to_uppercase(), wkt[i..].trim()),
+        None => (wkt.to_uppercase(), ""),
+    };
+    if body.to_uppercase() == "EMPTY" {
+        return Ok(Vec::new());
+    }
+    match kind.as_str() {
+        "POLYGON" => Ok(vec![parse_polygon(body)?]),
+        "MULTIPOLYGON" => split_top_level(strip_parentheses(body)?).into_iter().map(parse_polygon).collect(),
+        _ => Err(format!("Unsupported WKT geometry '{}' (POLYGON or MULTIPOLYGON expected)", kind)),
+    }
+}
+
+// ------------------------------------------------- Conversions ---------------------------------------------------------
+
+// MOC of (MULTI)POLYGON given in WKT : union of the polygons, minus their holes.
+// The cells overlapping the outer rings are kept (CellSelection::All), only the cells fully inside the holes are removed
+// (CellSelection::Inside) : the cells crossed by a hole edge overlap the polygon, so the MOC covers the whole polygon
+// like the MOC of a polygon without hole.
+pub fn moc_from_wkt(wkt: &str, depth: u8) -> Result<RangeMOC<u64, Hpx<u64>>, String> {
+    let mut moc: RangeMOC<u64, Hpx<u64>> = RangeMOC::new_empty(depth);
+    for rings in parse_wkt_polygons(wkt)? {
+        let mut rings = rings.into_iter();
+        let Some(outer) = rings.next() else { continue };
+        let mut polygon_moc = PolygonPSQL::from_deg(outer, false)?.to_moc(depth, CellSelection::All);
+        for hole in rings {
+            polygon_moc = polygon_moc.minus(&PolygonPSQL::from_deg(hole, false)?.to_moc(depth, CellSelection::Inside));
+        }
+        moc = moc.or(&polygon_moc);
+    }
+    Ok(moc)
+}
+
+// MOC from a WKT POLYGON or MULTIPOLYGON, e.g. ST_AsText(geom)
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_moc_from_wkt(wkt: &str, depth: i32) -> RangeMOCPSQL {
+    if !(0..=29).contains(&depth) {
+        error!("Invalid depth {} (max 29)", depth);
+    }
+    match moc_from_wkt(wkt, depth as u8) {
+        Ok(moc) => moc.into(),
+        Err(e) => error!("Failed to create the MOC from the WKT : {}", e),
+    }
+}
+
+// ------------------------------------------------- SQL functions -------------------------------------------------------
+
+extension_sql!(
+    r#"
+-- geometry/geography -> MOC at the given depth, the edges being great-circle arcs
+CREATE FUNCTION mgx_moc_from_geometry(geom geometry, depth integer) RETURNS RangeMOCPSQL AS $$
+    SELECT mgx_moc_from_wkt(ST_AsText(geom), depth)
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT;
+
+CREATE FUNCTION mgx_moc_from_geography(geog geography, depth integer) RETURNS RangeMOCPSQL AS $$
+    SELECT mgx_moc_from_wkt(ST_AsText(geog), depth)
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT;
+
+-- MOC -> MULTIPOLYGON of its cells
+CREATE FUNCTION mgx_moc_to_geometry(moc RangeMOCPSQL) RETURNS geometry AS $$
+    SELECT ST_GeomFromText(mgx_moc_to_wkt(moc), 4326)
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT;
+
+CREATE FUNCTION mgx_moc_to_geography(moc RangeMOCPSQL) RETURNS geography AS $$
+    SELECT ST_GeogFromText(mgx_moc_to_wkt(moc))
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT;
+
+-- Region -> POLYGON or MULTIPOLYGON
+CREATE FUNCTION mgx_region_to_geometry(region RegionPSQL) RETURNS geometry AS $$
+    SELECT ST_GeomFromText(mgx_region_to_wkt(region), 4326)
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT;
+
+CREATE FUNCTION mgx_region_to_geography(region RegionPSQL) RETURNS geography AS $$
+    SELECT ST_GeogFromText(mgx_region_to_wkt(region))
+$$ LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT;
+
+-- Casts from the MOCs and the regions only : the MOC of a geometry/geography needs a depth (see the functions above)
+CREATE CAST (RangeMOCPSQL AS geometry) WITH FUNCTION mgx_moc_to_geometry(RangeMOCPSQL);
+CREATE CAST (RangeMOCPSQL AS geography) WITH FUNCTION mgx_moc_to_geography(RangeMOCPSQL);
+CREATE CAST (RegionPSQL AS geometry) WITH FUNCTION mgx_region_to_geometry(RegionPSQL);
+CREATE CAST (RegionPSQL AS geography) WITH FUNCTION mgx_region_to_geography(RegionPSQL);
+"#,
+    name = "mgx_postgis_casts",
+    requires = [mgx_moc_from_wkt, crate::export::mgx_moc_to_wkt, crate::export::mgx_region_to_wkt]
+);
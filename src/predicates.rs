@@ -0,0 +1,453 @@
+use pgrx::prelude::*; // default
+
+// Library imports
+use pgrx::datum::Range as PgRange;
+use cdshealpix::nested::bmoc::{BMOC, Status};
+use skyregion::{
+    regions::{
+        cone::Cone,
+        ellipse::EllipticalCone,
+        zone::Zone,
+        polygon::Polygon,
+        ring::Ring,
+    },
+    SkyRegion
+};
+
+use crate::bmoc::{VertexPSQL, bmoc_to_depth_29_ranges};
+use crate::cache::{DatumArg, with_cached, is_arg_stable};
+use crate::polygon::{PolygonPSQL, polygon_or_error};
+
+// ------------------------------------------ Coverage + exact recheck engine --------------------------------------------
+
+// The region predicates mgx_in_* are SQL functions (see the end of this file) combining :
+//   - an index condition : mgx_hash_range(29, lon, lat) <@ <ranges of the BMOC coverage of the region>
+//     The ranges are computed once by the planner since the region parameters are constants.
+//   - a recheck : the points in a full cell of the coverage are accepted directly,
+//     the points in a partial cell are tested with the exact skyregion shape.
+
+// ------------------------------------------------ Coverage depth -------------------------------------------------------
+
+// A deep coverage has more ranges (one index scan each) but fewer points to recheck in its partial cells.
+// The depth minimizes : number of ranges x RANGE_SCAN_COST + rechecked rows x RECHECK_COST, the rows being uniformly
+// spread on the sky. The cost is computed from the characteristic depth of the region to MAX_DELTA_DEPTH deeper
+// and the search stops as soon as the cost increases.
+// The depth can be forced with the GUC mogipix.coverage_depth, or set relatively to the characteristic depth with
+// mogipix.coverage_delta_depth.
+
+// Cost of an index range scan, in exact recheck units
+const RANGE_SCAN_COST: f64 = 100.0;
+// Cost of the exact test of a row
+const RECHECK_COST: f64 = 1.0;
+// Maximum depth added to the characteristic depth of a region
+const MAX_DELTA_DEPTH: u8 = 8;
+// Number of rows assumed by the region predicates : the mgx_in_* functions are inlined in the queries and don't know
+// the table they are applied to, so their coverage depth is always chosen for a table of 10^7 rows.
+// The searches on the registered tables (see crate::search) and mgx_optimal_coverage_depth(region, table) use the
+// reltuples of the table instead.
+pub const DEFAULT_N_ROWS: f64 = 1e7;
+
+// Cost of a query using the coverage on a table of `n_rows` rows
+pub fn coverage_cost(bmoc: &BMOC, n_rows: f64) -> f64 {
+    let n_ranges = bmoc.to_ranges().len() as f64;
+    let partial_fraction: f64 = bmoc
+        .entries
+        .iter()
+        .map(|raw| bmoc.from_raw_value(*raw))
+        .filter(|cell| !cell.is_full)
+        .map(|cell| 1.0 / cdshealpix::nested::n_hash(cell.depth) as f64)
+        .sum();
+    n_ranges * RANGE_SCAN_COST + n_rows * partial_fraction * RECHECK_COST
+}
+
+// Best depth, at most `max_depth`, and coverage of a region of characteristic depth `characteristic_depth`
+// built with `build`
+pub fn optimal_coverage(characteristic_depth: u8, max_depth: u8, n_rows: f64, build: impl Fn(u8) -> BMOC) -> (u8, BMOC) {
+    if let Some(depth) = crate::guc::coverage_depth_override() {
+        let depth = depth.min(max_depth);
+        return (depth, build(depth));
+    }
+    if let Some(delta_depth) = crate::guc::coverage_delta_depth_override() {
+        let depth = characteristic_depth.saturating_add(delta_depth).min(max_depth);
+        return (depth, build(depth));
+    }
+    let first_depth = characteristic_depth.min(max_depth);
+    let last_depth = (characteristic_depth + MAX_DELTA_DEPTH).min(max_depth);
+    let mut best = (first_depth, build(first_depth));
+    let mut best_cost = coverage_cost(&best.1, n_rows);
+    for depth in (first_depth + 1)..=last_depth {
+        let bmoc = build(depth);
+        let cost = coverage_cost(&bmoc, n_rows);
+        if cost >= best_cost {
+            break;
+        }
+        best = (depth, bmoc);
+        best_cost = cost;
+    }
+    best
+}
+
+// Same as optimal_coverage, the coverage only
+pub fn coverage_bmoc(characteristic_depth: u8, max_depth: u8, n_rows: f64, build: impl Fn(u8) -> BMOC) -> BMOC {
+    optimal_coverage(characteristic_depth, max_depth, n_rows, build).1
+}
+
+// Exact test used for the points in the partial cells of a coverage
+pub trait Recheck {
+    fn recheck(&self, lon_rad: f64, lat_rad: f64) -> bool;
+}
+
+impl Recheck for Cone {
+    fn recheck(&self, lon_rad: f64, lat_rad: f64) -> bool { self.contains(lon_rad, lat_rad) }
+}
+
+impl Recheck for EllipticalCone {
+    fn recheck(&self, lon_rad: f64, lat_rad: f64) -> bool { self.contains(lon_rad, lat_rad) }
+}
+
+impl Recheck for Zone {
+    fn recheck(&self, lon_rad: f64, lat_rad: f64) -> bool { self.contains(lon_rad, lat_rad) }
+}
+
+impl Recheck for Polygon {
+    fn recheck(&self, lon_rad: f64, lat_rad: f64) -> bool { self.contains(lon_rad, lat_rad) }
+}
+
+impl Recheck for Ring {
+    fn recheck(&self, lon_rad: f64, lat_rad: f64) -> bool { self.contains(lon_rad, lat_rad) }
+}
+
+// BMOC coverage of a region and the region itself
+pub struct Coverage<R> {
+    pub bmoc: BMOC,
+    pub region: R,
+}
+
+impl<R: Recheck> Coverage<R> {
+    // Tests if the point is in the region, only calling the exact test for the points in partial cells
+    pub fn contains(&self, lon_rad: f64, lat_rad: f64) -> bool {
+        match self.bmoc.test_coo(lon_rad, lat_rad) {
+            Status::IN => true,
+            Status::OUT => false,
+            Status::UNKNOWN => self.region.recheck(lon_rad, lat_rad),
+        }
+    }
+
+    // Ranges of the coverage at the depth of the hash indexes
+    pub fn ranges(&self) -> Vec<PgRange<i64>> {
+        bmoc_to_depth_29_ranges(&self.bmoc)
+    }
+}
+
+// ------------------------------------------------ Coverages of the regions ---------------------------------------------
+
+pub fn cone_coverage(lon_deg: f64, lat_deg: f64, radius_deg: f64) -> Option<Coverage<Cone>> {
+    let region = Cone::from_deg(lon_deg, lat_deg, radius_deg).ok()?;
+    let bmoc = coverage_bmoc(cdshealpix::best_starting_depth(radius_deg.to_radians()), 29, DEFAULT_N_ROWS, |depth| {
+        cdshealpix::nested::cone_coverage_approx(depth, lon_deg.to_radians(), lat_deg.to_radians(), radius_deg.to_radians())
+    });
+    Some(Coverage { bmoc, region })
+}
+
+pub fn elliptical_cone_coverage(lon_deg: f64, lat_deg: f64, a_deg: f64, b_deg: f64, pa_deg: f64) -> Option<Coverage<EllipticalCone>> {
+    let region = EllipticalCone::from_deg(lon_deg, lat_deg, a_deg, b_deg, pa_deg).ok()?;
+    let bmoc = coverage_bmoc(cdshealpix::best_starting_depth(a_deg.to_radians()), 29, DEFAULT_N_ROWS, |depth| {
+        cdshealpix::nested::elliptical_cone_coverage(depth, lon_deg.to_radians(), lat_deg.to_radians(), a_deg.to_radians(), b_deg.to_radians(), pa_deg.to_radians())
+    });
+    Some(Coverage { bmoc, region })
+}
+
+pub fn zone_coverage(lon_min_deg: f64, lat_min_deg: f64, lon_max_deg: f64, lat_max_deg: f64) -> Option<Coverage<Zone>> {
+    let region = Zone::from_deg(lon_min_deg, lat_min_deg, lon_max_deg, lat_max_deg).ok()?;
+    let half_size_deg = 0.5 * (lon_max_deg - lon_min_deg).abs().max((lat_max_deg - lat_min_deg).abs());
+    let bmoc = coverage_bmoc(cdshealpix::best_starting_depth(half_size_deg.to_radians()), 29, DEFAULT_N_ROWS, |depth| {
+        cdshealpix::nested::zone_coverage(depth, lon_min_deg.to_radians(), lat_min_deg.to_radians(), lon_max_deg.to_radians(), lat_max_deg.to_radians())
+    });
+    Some(Coverage { bmoc, region })
+}
+
+pub fn polygon_coverage(polygon: &PolygonPSQL, exact_solution: bool) -> Option<Coverage<Polygon>> {
+    let region = polygon.to_sky_region().ok()?;
+    let bmoc = coverage_bmoc(region.characteristic_depth(), 29, DEFAULT_N_ROWS, |depth| polygon.to_bmoc(depth, exact_solution));
+    Some(Coverage { bmoc, region })
+}
+
+pub fn box_coverage(lon_deg: f64, lat_deg: f64, a_deg: f64, b_deg: f64, pa_deg: f64) -> Option<Coverage<Polygon>> {
+    let region = Polygon::from_box_deg(lon_deg, lat_deg, a_deg, b_deg, pa_deg).ok()?;
+    let bmoc = coverage_bmoc(cdshealpix::best_starting_depth(a_deg.max(b_deg).to_radians()), 29, DEFAULT_N_ROWS, |depth| {
+        cdshealpix::nested::box_coverage(depth, lon_deg.to_radians(), lat_deg.to_radians(), a_deg.to_radians(), b_deg.to_radians(), pa_deg.to_radians())
+    });
+    Some(Coverage { bmoc, region })
+}
+
+pub fn ring_coverage(lon_deg: f64, lat_deg: f64, r_min_deg: f64, r_max_deg: f64) -> Option<Coverage<Ring>> {
+    let region = Ring::from_deg(lon_deg, lat_deg, r_min_deg, r_max_deg).ok()?;
+    let bmoc = coverage_bmoc(cdshealpix::best_starting_depth(r_max_deg.to_radians()), 29, DEFAULT_N_ROWS, |depth| {
+        cdshealpix::nested::ring_coverage_approx(depth, lon_deg.to_radians(), lat_deg.to_radians(), r_min_deg.to_radians(), r_max_deg.to_radians())
+    });
+    Some(Coverage { bmoc, region })
+}
+
+// Ranges of a coverage, empty if the region is invalid
+fn coverage_ranges<R: Recheck>(coverage: Option<Coverage<R>>) -> Vec<PgRange<i64>> {
+    match coverage {
+        Some(coverage) => coverage.ranges(),
+        None => Vec::new(),
+    }
+}
+
+// Recheck of a point with a (cached) coverage, false if the region is invalid
+fn coverage_contains<R: Recheck>(coverage: &Option<Coverage<R>>, test_lon_deg: f64, test_lat_deg: f64) -> bool {
+    match coverage {
+        Some(coverage) => coverage.contains(test_lon_deg.to_radians(), test_lat_deg.to_radians()),
+        None => false,
+    }
+}
+
+// ---------------------------------------------------- Cone -------------------------------------------------------------
+
+#[pg_extern(stable, parallel_safe)]
+pub fn mgx_cone_coverage_ranges(lon_deg: f64, lat_deg: f64, radius_deg: f64) -> Vec<PgRange<i64>> {
+    coverage_ranges(cone_coverage(lon_deg, lat_deg, radius_deg))
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub fn mgx_cone_recheck(
+    fcinfo: pg_sys::FunctionCallInfo,
+    lon_deg: f64,
+    lat_deg: f64,
+    radius_deg: f64,
+    test_lon_deg: f64,
+    test_lat_deg: f64,
+) -> bool {
+    let key = (lon_deg, lat_deg, radius_deg);
+    with_cached(fcinfo, true, key, || cone_coverage(lon_deg, lat_deg, radius_deg), |coverage| {
+        coverage_contains(coverage, test_lon_deg, test_lat_deg)
+    })
+}
+
+// ---------------------------------------------- Elliptical cone --------------------------------------------------------
+
+#[pg_extern(stable, parallel_safe)]
+pub fn mgx_elliptical_cone_coverage_ranges(lon_deg: f64, lat_deg: f64, a_deg: f64, b_deg: f64, pa_deg: f64) -> Vec<PgRange<i64>> {
+    coverage_ranges(elliptical_cone_coverage(lon_deg, lat_deg, a_deg, b_deg, pa_deg))
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub fn mgx_elliptical_cone_recheck(
+    fcinfo: pg_sys::FunctionCallInfo,
+    lon_deg: f64,
+    lat_deg: f64,
+    a_deg: f64,
+    b_deg: f64,
+    pa_deg: f64,
+    test_lon_deg: f64,
+    test_lat_deg: f64,
+) -> bool {
+    let key = (lon_deg, lat_deg, a_deg, b_deg, pa_deg);
+    with_cached(fcinfo, true, key, || elliptical_cone_coverage(lon_deg, lat_deg, a_deg, b_deg, pa_deg), |coverage| {
+        coverage_contains(coverage, test_lon_deg, test_lat_deg)
+    })
+}
+
+// ---------------------------------------------------- Zone -------------------------------------------------------------
+
+#[pg_extern(stable, parallel_safe)]
+pub fn mgx_zone_coverage_ranges(lon_min_deg: f64, lat_min_deg: f64, lon_max_deg: f64, lat_max_deg: f64) -> Vec<PgRange<i64>> {
+    coverage_ranges(zone_coverage(lon_min_deg, lat_min_deg, lon_max_deg, lat_max_deg))
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub fn mgx_zone_recheck(
+    fcinfo: pg_sys::FunctionCallInfo,
+    lon_min_deg: f64,
+    lat_min_deg: f64,
+    lon_max_deg: f64,
+    lat_max_deg: f64,
+    test_lon_deg: f64,
+    test_lat_deg: f64,
+) -> bool {
+    let key = (lon_min_deg, lat_min_deg, lon_max_deg, lat_max_deg);
+    with_cached(fcinfo, true, key, || zone_coverage(lon_min_deg, lat_min_deg, lon_max_deg, lat_max_deg), |coverage| {
+        coverage_contains(coverage, test_lon_deg, test_lat_deg)
+    })
+}
+
+// --------------------------------------------------- Polygon -----------------------------------------------------------
+
+// The vertices are created with mgx_create_vertexpsql
+#[pg_extern(stable, parallel_safe)]
+pub fn mgx_polygon_coverage_ranges(vertices: Vec<VertexPSQL>, exact_solution: bool, complement: bool) -> Vec<PgRange<i64>> {
+    let polygon = polygon_or_error(PolygonPSQL::from_vertices(vertices, complement));
+    coverage_ranges(polygon_coverage(&polygon, exact_solution))
+}
+
+// The vertices array is keyed on its datum pointer, so it is only decoded once per scan when it is a constant
+#[pg_extern(stable, parallel_safe, requires = [VertexPSQL])]
+pub fn mgx_polygon_recheck(
+    fcinfo: pg_sys::FunctionCallInfo,
+    vertices: DatumArg<Vec<VertexPSQL>>,
+    exact_solution: bool,
+    complement: bool,
+    test_lon_deg: f64,
+    test_lat_deg: f64,
+) -> bool {
+    let key = (vertices.datum().value(), exact_solution, complement);
+    let build = || polygon_coverage(&polygon_or_error(PolygonPSQL::from_vertices(vertices.decode(), complement)), exact_solution);
+    with_cached(fcinfo, is_arg_stable(fcinfo, 0), key, build, |coverage| {
+        coverage_contains(coverage, test_lon_deg, test_lat_deg)
+    })
+}
+
+#[pg_extern(stable, parallel_safe, name = "mgx_polygon_coverage_ranges")]
+pub fn mgx_polygonpsql_coverage_ranges(polygon: PolygonPSQL, exact_solution: bool) -> Vec<PgRange<i64>> {
+    coverage_ranges(polygon_coverage(&polygon, exact_solution))
+}
+
+#[pg_extern(stable, parallel_safe, name = "mgx_polygon_recheck", requires = [PolygonPSQL])]
+pub fn mgx_polygonpsql_recheck(
+    fcinfo: pg_sys::FunctionCallInfo,
+    polygon: DatumArg<PolygonPSQL>,
+    exact_solution: bool,
+    test_lon_deg: f64,
+    test_lat_deg: f64,
+) -> bool {
+    let key = (polygon.datum().value(), exact_solution);
+    let build = || polygon_coverage(&polygon.decode(), exact_solution);
+    with_cached(fcinfo, is_arg_stable(fcinfo, 0), key, build, |coverage| {
+        coverage_contains(coverage, test_lon_deg, test_lat_deg)
+    })
+}
+
+// ----------------------------------------------------- Box -------------------------------------------------------------
+
+#[pg_extern(stable, parallel_safe)]
+pub fn mgx_box_coverage_ranges(lon_deg: f64, lat_deg: f64, a_deg: f64, b_deg: f64, pa_deg: f64) -> Vec<PgRange<i64>> {
+    coverage_ranges(box_coverage(lon_deg, lat_deg, a_deg, b_deg, pa_deg))
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub fn mgx_box_recheck(
+    fcinfo: pg_sys::FunctionCallInfo,
+    lon_deg: f64,
+    lat_deg: f64,
+    a_deg: f64,
+    b_deg: f64,
+    pa_deg: f64,
+    test_lon_deg: f64,
+    test_lat_deg: f64,
+) -> bool {
+    let key = (lon_deg, lat_deg, a_deg, b_deg, pa_deg);
+    with_cached(fcinfo, true, key, || box_coverage(lon_deg, lat_deg, a_deg, b_deg, pa_deg), |coverage| {
+        coverage_contains(coverage, test_lon_deg, test_lat_deg)
+    })
+}
+
+// ---------------------------------------------------- Ring -------------------------------------------------------------
+
+#[pg_extern(stable, parallel_safe)]
+pub fn mgx_ring_coverage_ranges(lon_deg: f64, lat_deg: f64, r_min_deg: f64, r_max_deg: f64) -> Vec<PgRange<i64>> {
+    coverage_ranges(ring_coverage(lon_deg, lat_deg, r_min_deg, r_max_deg))
+}
+
+#[pg_extern(stable, parallel_safe)]
+pub fn mgx_ring_recheck(
+    fcinfo: pg_sys::FunctionCallInfo,
+    lon_deg: f64,
+    lat_deg: f64,
+    r_min_deg: f64,
+    r_max_deg: f64,
+    test_lon_deg: f64,
+    test_lat_deg: f64,
+) -> bool {
+    let key = (lon_deg, lat_deg, r_min_deg, r_max_deg);
+    with_cached(fcinfo, true, key, || ring_coverage(lon_deg, lat_deg, r_min_deg, r_max_deg), |coverage| {
+        coverage_contains(coverage, test_lon_deg, test_lat_deg)
+    })
+}
+
+// ---------------------------------------------- Index-supporting predicates --------------------------------------------
+
+// LANGUAGE sql functions so the planner inlines them and uses the GiST index on mgx_hash_range(29, lon, lat)
+// The depth of their coverages is chosen for a table of DEFAULT_N_ROWS rows, whatever the table they are applied to
+// They are stable, like the coverage_ranges and recheck functions above, since mogipix.coverage_depth and
+// mogipix.coverage_delta_depth change their coverages
+extension_sql!(
+    r#"
+-- Returns true if the point (test_lon_deg, test_lat_deg) is in the cone
+CREATE FUNCTION mgx_in_cone(
+    lon_deg double precision, lat_deg double precision, radius_deg double precision,
+    test_lon_deg double precision, test_lat_deg double precision)
+RETURNS boolean AS $$
+    SELECT mgx_hash_range(29, test_lon_deg, test_lat_deg) <@ int8multirange(VARIADIC mgx_cone_coverage_ranges(lon_deg, lat_deg, radius_deg))
+       AND mgx_cone_recheck(lon_deg, lat_deg, radius_deg, test_lon_deg, test_lat_deg)
+$$ LANGUAGE sql STABLE PARALLEL SAFE;
+
+-- Returns true if the point (test_lon_deg, test_lat_deg) is in the elliptical cone
+CREATE FUNCTION mgx_in_elliptical_cone(
+    lon_deg double precision, lat_deg double precision, a_deg double precision, b_deg double precision, pa_deg double precision,
+    test_lon_deg double precision, test_lat_deg double precision)
+RETURNS boolean AS $$
+    SELECT mgx_hash_range(29, test_lon_deg, test_lat_deg) <@ int8multirange(VARIADIC mgx_elliptical_cone_coverage_ranges(lon_deg, lat_deg, a_deg, b_deg, pa_deg))
+       AND mgx_elliptical_cone_recheck(lon_deg, lat_deg, a_deg, b_deg, pa_deg, test_lon_deg, test_lat_deg)
+$$ LANGUAGE sql STABLE PARALLEL SAFE;
+
+-- Returns true if the point (test_lon_deg, test_lat_deg) is in the zone
+CREATE FUNCTION mgx_in_zone(
+    lon_min_deg double precision, lat_min_deg double precision, lon_max_deg double precision, lat_max_deg double precision,
+    test_lon_deg double precision, test_lat_deg double precision)
+RETURNS boolean AS $$
+    SELECT mgx_hash_range(29, test_lon_deg, test_lat_deg) <@ int8multirange(VARIADIC mgx_zone_coverage_ranges(lon_min_deg, lat_min_deg, lon_max_deg, lat_max_deg))
+       AND mgx_zone_recheck(lon_min_deg, lat_min_deg, lon_max_deg, lat_max_deg, test_lon_deg, test_lat_deg)
+$$ LANGUAGE sql STABLE PARALLEL SAFE;
+
+-- Returns true if the point (test_lon_deg, test_lat_deg) is in the polygon
+-- The vertices are created with mgx_create_vertexpsql and aren't included in the polygon
+CREATE FUNCTION mgx_in_polygon(
+    vertices vertexpsql[], exact_solution boolean, complement boolean,
+    test_lon_deg double precision, test_lat_deg double precision)
+RETURNS boolean AS $$
+    SELECT mgx_hash_range(29, test_lon_deg, test_lat_deg) <@ int8multirange(VARIADIC mgx_polygon_coverage_ranges(vertices, exact_solution, complement))
+       AND mgx_polygon_recheck(vertices, exact_solution, complement, test_lon_deg, test_lat_deg)
+$$ LANGUAGE sql STABLE PARALLEL SAFE;
+
+-- Returns true if the point (test_lon_deg, test_lat_deg) is in the polygon
+-- The polygon is created with mgx_polygon_from_deg, mgx_polygon_from_rad or mgx_polygon_from_vertices
+CREATE FUNCTION mgx_in_polygon(
+    polygon PolygonPSQL, exact_solution boolean,
+    test_lon_deg double precision, test_lat_deg double precision)
+RETURNS boolean AS $$
+    SELECT mgx_hash_range(29, test_lon_deg, test_lat_deg) <@ int8multirange(VARIADIC mgx_polygon_coverage_ranges(polygon, exact_solution))
+       AND mgx_polygon_recheck(polygon, exact_solution, test_lon_deg, test_lat_deg)
+$$ LANGUAGE sql STABLE PARALLEL SAFE;
+
+-- Returns true if the point (test_lon_deg, test_lat_deg) is in the box
+CREATE FUNCTION mgx_in_box(
+    lon_deg double precision, lat_deg double precision, a_deg double precision, b_deg double precision, pa_deg double precision,
+    test_lon_deg double precision, test_lat_deg double precision)
+RETURNS boolean AS $$
+    SELECT mgx_hash_range(29, test_lon_deg, test_lat_deg) <@ int8multirange(VARIADIC mgx_box_coverage_ranges(lon_deg, lat_deg, a_deg, b_deg, pa_deg))
+       AND mgx_box_recheck(lon_deg, lat_deg, a_deg, b_deg, pa_deg, test_lon_deg, test_lat_deg)
+$$ LANGUAGE sql STABLE PARALLEL SAFE;
+
+-- Returns true if the point (test_lon_deg, test_lat_deg) is in the ring
+CREATE FUNCTION mgx_in_ring(
+    lon_deg double precision, lat_deg double precision, r_min_deg double precision, r_max_deg double precision,
+    test_lon_deg double precision, test_lat_deg double precision)
+RETURNS boolean AS $$
+    SELECT mgx_hash_range(29, test_lon_deg, test_lat_deg) <@ int8multirange(VARIADIC mgx_ring_coverage_ranges(lon_deg, lat_deg, r_min_deg, r_max_deg))
+       AND mgx_ring_recheck(lon_deg, lat_deg, r_min_deg, r_max_deg, test_lon_deg, test_lat_deg)
+$$ LANGUAGE sql STABLE PARALLEL SAFE;
+"#,
+    name = "mgx_in_region_predicates",
+    requires = [
+        crate::mgx_hash_range,
+        mgx_cone_coverage_ranges, mgx_cone_recheck,
+        mgx_elliptical_cone_coverage_ranges, mgx_elliptical_cone_recheck,
+        mgx_zone_coverage_ranges, mgx_zone_recheck,
+        mgx_polygon_coverage_ranges, mgx_polygon_recheck,
+        mgx_polygonpsql_coverage_ranges, mgx_polygonpsql_recheck,
+        mgx_box_coverage_ranges, mgx_box_recheck,
+        mgx_ring_coverage_ranges, mgx_ring_recheck,
+    ]
+);
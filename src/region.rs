@@ -0,0 +1,545 @@
+use pgrx::prelude::*; // default
+
+// Library imports
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use cdshealpix::nested::bmoc::BMOC;
+use moc::{
+    moc::range::{RangeMOC, CellSelection},
+    qty::Hpx,
+};
+use skyregion::{
+    regions::{
+        cone::Cone,
+        ellipse::EllipticalCone,
+        zone::Zone,
+        polygon::Polygon,
+        ring::Ring,
+    },
+    SkyRegion
+};
+use stcs::Stc;
+use nom::{error::{convert_error, VerboseError}, Err as NomErr};
+
+use crate::bmoc::{BMOCpsql, VertexPSQL};
+use crate::moc::RangeMOCPSQL;
+use crate::cache::{DatumArg, with_cached_arg};
+use crate::predicates::{self, Coverage, Recheck};
+use crate::polygon::{PolygonPSQL, polygon_or_error};
+
+// ------------------------------------------------ Region type ----------------------------------------------------------
+
+// Sky region that is PSQL compatible, the angles are in degrees except the vertices of the polygons which are stored
+// in radians (see PolygonPSQL)
+#[derive(PostgresType, Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum RegionPSQL {
+    Cone { lon: f64, lat: f64, radius: f64 },
+    EllipticalCone { lon: f64, lat: f64, a: f64, b: f64, pa: f64 },
+    Zone { lon_min: f64, lat_min: f64, lon_max: f64, lat_max: f64 },
+    Polygon(PolygonPSQL),
+    Box { lon: f64, lat: f64, a: f64, b: f64, pa: f64 },
+    Ring { lon: f64, lat: f64, r_min: f64, r_max: f64 },
+    Union(Vec<RegionPSQL>),
+    Intersection(Vec<RegionPSQL>),
+    Not(Box<RegionPSQL>),
+}
+
+// The skyregion shape corresponding to a RegionPSQL
+pub enum SkyRegionShape {
+    Cone(Cone),
+    EllipticalCone(EllipticalCone),
+    Zone(Zone),
+    Polygon(Polygon),
+    Ring(Ring),
+    Union(Vec<SkyRegionShape>),
+    Intersection(Vec<SkyRegionShape>),
+    Not(Box<SkyRegionShape>),
+}
+
+impl Recheck for SkyRegionShape {
+    fn recheck(&self, lon_rad: f64, lat_rad: f64) -> bool {
+        match self {
+            SkyRegionShape::Cone(cone) => cone.contains(lon_rad, lat_rad),
+            SkyRegionShape::EllipticalCone(elliptical_cone) => elliptical_cone.contains(lon_rad, lat_rad),
+            SkyRegionShape::Zone(zone) => zone.contains(lon_rad, lat_rad),
+            SkyRegionShape::Polygon(polygon) => polygon.contains(lon_rad, lat_rad),
+            SkyRegionShape::Ring(ring) => ring.contains(lon_rad, lat_rad),
+            SkyRegionShape::Union(shapes) => shapes.iter().any(|shape| shape.recheck(lon_rad, lat_rad)),
+            SkyRegionShape::Intersection(shapes) => shapes.iter().all(|shape| shape.recheck(lon_rad, lat_rad)),
+            SkyRegionShape::Not(shape) => !shape.recheck(lon_rad, lat_rad),
+        }
+    }
+}
+
+impl RegionPSQL {
+    // RegionPSQL -> skyregion shape, fails if the parameters are invalid
+    pub fn to_sky_region(&self) -> Result<SkyRegionShape, String> {
+        match self {
+            RegionPSQL::Cone { lon, lat, radius } =>
+                Cone::from_deg(*lon, *lat, *radius).map(SkyRegionShape::Cone).map_err(|e| e.to_string()),
+            RegionPSQL::EllipticalCone { lon, lat, a, b, pa } =>
+                EllipticalCone::from_deg(*lon, *lat, *a, *b, *pa).map(SkyRegionShape::EllipticalCone).map_err(|e| e.to_string()),
+            RegionPSQL::Zone { lon_min, lat_min, lon_max, lat_max } =>
+                Zone::from_deg(*lon_min, *lat_min, *lon_max, *lat_max).map(SkyRegionShape::Zone).map_err(|e| e.to_string()),
+            RegionPSQL::Polygon(polygon) =>
+                polygon.to_sky_region().map(SkyRegionShape::Polygon),
+            RegionPSQL::Box { lon, lat, a, b, pa } =>
+                Polygon::from_box_deg(*lon, *lat, *a, *b, *pa).map(SkyRegionShape::Polygon).map_err(|e| e.to_string()),
+            RegionPSQL::Ring { lon, lat, r_min, r_max } =>
+                Ring::from_deg(*lon, *lat, *r_min, *r_max).map(SkyRegionShape::Ring).map_err(|e| e.to_string()),
+            RegionPSQL::Union(regions) => sky_regions(regions).map(SkyRegionShape::Union),
+            RegionPSQL::Intersection(regions) => sky_regions(regions).map(SkyRegionShape::Intersection),
+            RegionPSQL::Not(region) => region.to_sky_region().map(|shape| SkyRegionShape::Not(Box::new(shape))),
+        }
+    }
+
+    // Box given by its full width (along the longitude) and height, without position angle (STC-S and ADQL boxes)
+    pub fn box_from_size(lon: f64, lat: f64, width: f64, height: f64) -> Self {
+        if width >= height {
+            RegionPSQL::Box { lon, lat, a: 0.5 * width, b: 0.5 * height, pa: 90.0 }
+        } else {
+            RegionPSQL::Box { lon, lat, a: 0.5 * height, b: 0.5 * width, pa: 0.0 }
+        }
+    }
+
+    // Checks the parameters of the region, raises a Postgres error if they are invalid
+    pub fn validated(self) -> Self {
+        if let Err(e) = self.to_sky_region() {
+            error!("Invalid region {:?} : {}", self, e);
+        }
+        self
+    }
+
+    // Coverage used by the coverage + exact recheck engine (see crate::predicates)
+    pub fn coverage(&self) -> Option<Coverage<SkyRegionShape>> {
+        fn wrap<R>(coverage: Option<Coverage<R>>, shape: impl FnOnce(R) -> SkyRegionShape) -> Option<Coverage<SkyRegionShape>> {
+            coverage.map(|c| Coverage { bmoc: c.bmoc, region: shape(c.region) })
+        }
+        match self {
+            RegionPSQL::Cone { lon, lat, radius } =>
+                wrap(predicates::cone_coverage(*lon, *lat, *radius), SkyRegionShape::Cone),
+            RegionPSQL::EllipticalCone { lon, lat, a, b, pa } =>
+                wrap(predicates::elliptical_cone_coverage(*lon, *lat, *a, *b, *pa), SkyRegionShape::EllipticalCone),
+            RegionPSQL::Zone { lon_min, lat_min, lon_max, lat_max } =>
+                wrap(predicates::zone_coverage(*lon_min, *lat_min, *lon_max, *lat_max), SkyRegionShape::Zone),
+            RegionPSQL::Polygon(polygon) =>
+                wrap(predicates::polygon_coverage(polygon, false), SkyRegionShape::Polygon),
+            RegionPSQL::Box { lon, lat, a, b, pa } =>
+                wrap(predicates::box_coverage(*lon, *lat, *a, *b, *pa), SkyRegionShape::Polygon),
+            RegionPSQL::Ring { lon, lat, r_min, r_max } =>
+                wrap(predicates::ring_coverage(*lon, *lat, *r_min, *r_max), SkyRegionShape::Ring),
+            RegionPSQL::Union(_) | RegionPSQL::Intersection(_) | RegionPSQL::Not(_) => {
+                let region = self.to_sky_region().ok()?;
+                let bmoc = predicates::coverage_bmoc(self.characteristic_depth(), 29, predicates::DEFAULT_N_ROWS, |depth| self.to_bmoc(depth));
+                Some(Coverage { bmoc, region })
+            }
+        }
+    }
+
+    // Depth at which the cells have the size of the region (same values as the coverages of crate::predicates)
+    pub fn characteristic_depth(&self) -> u8 {
+        match self {
+            RegionPSQL::Cone { radius, .. } => cdshealpix::best_starting_depth(radius.to_radians()),
+            RegionPSQL::EllipticalCone { a, .. } => cdshealpix::best_starting_depth(a.to_radians()),
+            RegionPSQL::Zone { lon_min, lat_min, lon_max, lat_max } => {
+                let half_size = 0.5 * (lon_max - lon_min).abs().max((lat_max - lat_min).abs());
+                cdshealpix::best_starting_depth(half_size.to_radians())
+            },
+            RegionPSQL::Polygon(polygon) => polygon.characteristic_depth(),
+            RegionPSQL::Box { a, b, .. } => cdshealpix::best_starting_depth(a.max(*b).to_radians()),
+            RegionPSQL::Ring { r_max, .. } => cdshealpix::best_starting_depth(r_max.to_radians()),
+            // A union is as large as its largest region, an intersection as small as its smallest one
+            RegionPSQL::Union(regions) => regions.iter().map(|region| region.characteristic_depth()).min().unwrap_or(0),
+            RegionPSQL::Intersection(regions) => regions.iter().map(|region| region.characteristic_depth()).max().unwrap_or(0),
+            RegionPSQL::Not(_) => 0,
+        }
+    }
+
+    // Coverage depth, at most `max_depth`, minimizing the cost of a query on a table of `n_rows` rows
+    pub fn optimal_coverage_depth(&self, n_rows: f64, max_depth: u8) -> u8 {
+        predicates::optimal_coverage(self.characteristic_depth(), max_depth, n_rows, |depth| self.to_bmoc(depth)).0
+    }
+
+    // BMOC of the region at the given depth
+    pub fn to_bmoc(&self, depth: u8) -> BMOC {
+        match self {
+            RegionPSQL::Cone { lon, lat, radius } =>
+                cdshealpix::nested::cone_coverage_approx(depth, lon.to_radians(), lat.to_radians(), radius.to_radians()),
+            RegionPSQL::EllipticalCone { lon, lat, a, b, pa } =>
+                cdshealpix::nested::elliptical_cone_coverage(depth, lon.to_radians(), lat.to_radians(), a.to_radians(), b.to_radians(), pa.to_radians()),
+            RegionPSQL::Zone { lon_min, lat_min, lon_max, lat_max } =>
+                cdshealpix::nested::zone_coverage(depth, lon_min.to_radians(), lat_min.to_radians(), lon_max.to_radians(), lat_max.to_radians()),
+            RegionPSQL::Polygon(polygon) => polygon.to_bmoc(depth, false),
+            RegionPSQL::Box { lon, lat, a, b, pa } =>
+                cdshealpix::nested::box_coverage(depth, lon.to_radians(), lat.to_radians(), a.to_radians(), b.to_radians(), pa.to_radians()),
+            RegionPSQL::Ring { lon, lat, r_min, r_max } =>
+                cdshealpix::nested::ring_coverage_approx(depth, lon.to_radians(), lat.to_radians(), r_min.to_radians(), r_max.to_radians()),
+            RegionPSQL::Union(regions) =>
+                regions.iter().fold(BMOC::new_empty(depth), |bmoc, region| bmoc.or(&region.to_bmoc(depth))),
+            RegionPSQL::Intersection(regions) =>
+                regions.iter().fold(BMOC::new_allsky(depth), |bmoc, region| bmoc.and(&region.to_bmoc(depth))),
+            RegionPSQL::Not(region) => region.to_bmoc(depth).not(),
+        }
+    }
+
+    // MOC of the region at the given depth (cells overlapping the region)
+    pub fn to_moc(&self, depth: u8) -> RangeMOC<u64, Hpx<u64>> {
+        self.cells_moc(depth, false)
+    }
+
+    // MOC of the cells overlapping the region, or of the cells inside it if `inside` (the cells overlapping the
+    // complement of a region are the ones that aren't inside the region)
+    fn cells_moc(&self, depth: u8, inside: bool) -> RangeMOC<u64, Hpx<u64>> {
+        // Same default as the moc CLI for the depth used to test the cells overlap
+        const DELTA_DEPTH: u8 = 2;
+        let selection = || if inside { CellSelection::Inside } else { CellSelection::All };
+        match self {
+            RegionPSQL::Cone { lon, lat, radius } =>
+                RangeMOC::from_cone(lon.to_radians(), lat.to_radians(), radius.to_radians(), depth, DELTA_DEPTH, selection()),
+            RegionPSQL::EllipticalCone { lon, lat, a, b, pa } =>
+                RangeMOC::from_elliptical_cone(lon.to_radians(), lat.to_radians(), a.to_radians(), b.to_radians(), pa.to_radians(), depth, DELTA_DEPTH, selection()),
+            RegionPSQL::Zone { lon_min, lat_min, lon_max, lat_max } =>
+                RangeMOC::from_zone(lon_min.to_radians(), lat_min.to_radians(), lon_max.to_radians(), lat_max.to_radians(), depth, selection()),
+            RegionPSQL::Polygon(polygon) => polygon.to_moc(depth, selection()),
+            RegionPSQL::Box { lon, lat, a, b, pa } =>
+                RangeMOC::from_box(lon.to_radians(), lat.to_radians(), a.to_radians(), b.to_radians(), pa.to_radians(), depth, selection()),
+            RegionPSQL::Ring { lon, lat, r_min, r_max } =>
+                RangeMOC::from_ring(lon.to_radians(), lat.to_radians(), r_min.to_radians(), r_max.to_radians(), depth, DELTA_DEPTH, selection()),
+            RegionPSQL::Union(regions) =>
+                regions.iter().fold(RangeMOC::new_empty(depth), |moc, region| moc.or(&region.cells_moc(depth, inside))),
+            RegionPSQL::Intersection(regions) =>
+                regions.iter().fold(RangeMOC::new_full(depth), |moc, region| moc.and(&region.cells_moc(depth, inside))),
+            RegionPSQL::Not(region) => region.cells_moc(depth, !inside).not(),
+        }
+    }
+}
+
+// Shapes of the regions of a union or an intersection, which can't be empty
+fn sky_regions(regions: &[RegionPSQL]) -> Result<Vec<SkyRegionShape>, String> {
+    if regions.is_empty() {
+        return Err(String::from("Unions and intersections need at least one region"));
+    }
+    regions.iter().map(|region| region.to_sky_region()).collect()
+}
+
+// -------------------------------------------------- Geometry -----------------------------------------------------------
+
+// Position at the angular distance `distance` of (lon, lat) in the direction `bearing` (from the north towards the east),
+// all the angles being in radians
+pub fn destination(lon: f64, lat: f64, bearing: f64, distance: f64) -> (f64, f64) {
+    let (sin_lat, cos_lat) = lat.sin_cos();
+    let (sin_d, cos_d) = distance.sin_cos();
+    let sin_lat2 = (sin_lat * cos_d + cos_lat * sin_d * bearing.cos()).clamp(-1.0, 1.0);
+    let lon2 = lon + (bearing.sin() * sin_d * cos_lat).atan2(cos_d - sin_lat * sin_lat2);
+    (lon2.rem_euclid(2.0 * std::f64::consts::PI), sin_lat2.asin())
+}
+
+// Angular distance in radians from the center of the elliptical cone of semi-axes a and b (radians) to its boundary,
+// in the direction making the angle `angle` with the major axis. The ellipse is the one of semi-axes sin(a) and sin(b)
+// in the orthographic projection centred on the cone.
+pub fn ellipse_distance(a: f64, b: f64, angle: f64) -> f64 {
+    let (sin_a, sin_b) = (a.sin(), b.sin());
+    (sin_a * sin_b / (sin_b * angle.cos()).hypot(sin_a * angle.sin())).min(1.0).asin()
+}
+
+// `n_vertices` vertices in radians of the boundary of the elliptical cone of center (lon, lat), semi-axes a and b and
+// position angle pa, in degrees
+pub fn ellipse_vertices(lon: f64, lat: f64, a: f64, b: f64, pa: f64, n_vertices: usize) -> Vec<(f64, f64)> {
+    (0..n_vertices)
+        .map(|i| {
+            let angle = 2.0 * std::f64::consts::PI * i as f64 / n_vertices as f64;
+            let distance = ellipse_distance(a.to_radians(), b.to_radians(), angle);
+            destination(lon.to_radians(), lat.to_radians(), pa.to_radians() + angle, distance)
+        })
+        .collect()
+}
+
+// Vertices in radians of the box of center (lon, lat), semi-axes a and b and position angle pa, in degrees.
+// The sides are the great-circle arcs at the angular distance a from the center along the major axis and b along
+// the minor axis : the vertices are those of a rectangle of the gnomonic projection centred on the box.
+pub fn box_vertices(lon: f64, lat: f64, a: f64, b: f64, pa: f64) -> Vec<(f64, f64)> {
+    let (tan_a, tan_b) = (a.to_radians().tan(), b.to_radians().tan());
+    let (sin_pa, cos_pa) = pa.to_radians().sin_cos();
+    [(1.0, 1.0), (1.0, -1.0), (-1.0, -1.0), (-1.0, 1.0)]
+        .iter()
+        .map(|(sa, sb)| {
+            // (east, north) coordinates of the vertex in the projection plane
+            let x = sa * tan_a * sin_pa + sb * tan_b * cos_pa;
+            let y = sa * tan_a * cos_pa - sb * tan_b * sin_pa;
+            destination(lon.to_radians(), lat.to_radians(), x.atan2(y), x.hypot(y).atan())
+        })
+        .collect()
+}
+
+// ------------------------------------------------ Constructors ---------------------------------------------------------
+
+// Cone
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_region_cone(lon: f64, lat: f64, radius: f64) -> RegionPSQL {
+    RegionPSQL::Cone { lon, lat, radius }.validated()
+}
+
+// Elliptical cone
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_region_elliptical_cone(lon: f64, lat: f64, a: f64, b: f64, pa: f64) -> RegionPSQL {
+    RegionPSQL::EllipticalCone { lon, lat, a, b, pa }.validated()
+}
+
+// Zone
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_region_zone(lon_min: f64, lat_min: f64, lon_max: f64, lat_max: f64) -> RegionPSQL {
+    RegionPSQL::Zone { lon_min, lat_min, lon_max, lat_max }.validated()
+}
+
+// Polygon
+// The vertices are created with mgx_create_vertexpsql
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_region_polygon(vertices: Vec<VertexPSQL>, complement: bool) -> RegionPSQL {
+    RegionPSQL::Polygon(polygon_or_error(PolygonPSQL::from_vertices(vertices, complement)))
+}
+
+// Polygon created with mgx_polygon_from_deg, mgx_polygon_from_rad or mgx_polygon_from_vertices
+#[pg_extern(immutable, parallel_safe, name = "mgx_region_polygon")]
+pub fn mgx_region_from_polygon(polygon: PolygonPSQL) -> RegionPSQL {
+    RegionPSQL::Polygon(polygon)
+}
+
+// Box
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_region_box(lon: f64, lat: f64, a: f64, b: f64, pa: f64) -> RegionPSQL {
+    RegionPSQL::Box { lon, lat, a, b, pa }.validated()
+}
+
+// Ring
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_region_ring(lon: f64, lat: f64, r_min: f64, r_max: f64) -> RegionPSQL {
+    RegionPSQL::Ring { lon, lat, r_min, r_max }.validated()
+}
+
+// ------------------------------------------------- STC-S ---------------------------------------------------------------
+
+// Frames in which the coordinates can be used as ICRS coordinates
+pub const SUPPORTED_FRAMES: [&str; 4] = ["ICRS", "FK5", "J2000", "UNKNOWNFRAME"];
+
+// Shapes of the space sub-phrase of an STC-S string, the keys of the shapes in the serialized AST
+const STCS_SHAPES: [&str; 9] = ["AllSky", "Circle", "Ellipse", "Box", "Polygon", "Convex", "Union", "Intersection", "Not"];
+
+// Parses the STC-S string and checks that all its shapes are given in a supported frame
+pub fn parse_stcs(stcs: &str) -> Result<Stc, String> {
+    let stc = match Stc::parse::<VerboseError<&str>>(stcs.trim()) {
+        Ok((rem, stc)) if rem.trim().is_empty() => stc,
+        Ok((rem, _)) => return Err(format!("Unexpected end of the STC-S string : '{}'", rem)),
+        Err(NomErr::Error(e)) | Err(NomErr::Failure(e)) => return Err(convert_error(stcs.trim(), e)),
+        Err(NomErr::Incomplete(_)) => return Err(String::from("Incomplete STC-S string")),
+    };
+    check_stcs_frames(&space_ast(&stc)?)?;
+    Ok(stc)
+}
+
+// Space sub-phrase of the parsed STC-S string, as its serde representation : each shape is an object with a single key,
+// the name of the shape, whose value holds its parameters
+fn space_ast(stc: &Stc) -> Result<Value, String> {
+    match serde_json::to_value(stc).map_err(|e| e.to_string())?.get("space") {
+        Some(space) if !space.is_null() => Ok(space.clone()),
+        _ => Err(String::from("The STC-S string has no space sub-phrase")),
+    }
+}
+
+// Name of a serialized frame : a unit variant is serialized as a string, a variant with an equinox as an object
+fn frame_name(frame: &Value) -> String {
+    let name = match frame {
+        Value::String(name) => name.as_str(),
+        Value::Object(fields) => fields.keys().next().map(String::as_str).unwrap_or_default(),
+        _ => "",
+    };
+    name.replace('_', "").to_uppercase()
+}
+
+// Error if a shape of the AST is given in a frame that isn't supported
+fn check_stcs_frames(ast: &Value) -> Result<(), String> {
+    match ast {
+        Value::Object(fields) => {
+            if let Some(frame) = fields.get("frame") {
+                let name = frame_name(frame);
+                if !SUPPORTED_FRAMES.contains(&name.as_str()) {
+                    return Err(format!("Unsupported frame '{}' (supported frames : {})", name, SUPPORTED_FRAMES.join(", ")));
+                }
+            }
+            fields.values().try_for_each(check_stcs_frames)
+        }
+        Value::Array(values) => values.iter().try_for_each(check_stcs_frames),
+        _ => Ok(()),
+    }
+}
+
+// Name and parameters of a shape of the AST, None if the value isn't a shape
+fn stcs_shape(ast: &Value) -> Option<(&str, &Value)> {
+    match ast.as_object() {
+        Some(fields) if fields.len() == 1 => fields
+            .iter()
+            .next()
+            .filter(|(name, _)| STCS_SHAPES.contains(&name.as_str()))
+            .map(|(name, params)| (name.as_str(), params)),
+        _ => None,
+    }
+}
+
+// Numbers of the parameter `name` of a shape, the positions being flattened in (lon, lat) pairs
+fn stcs_numbers(shape: &str, params: &Value, name: &str) -> Result<Vec<f64>, String> {
+    fn flatten(value: &Value, numbers: &mut Vec<f64>) -> bool {
+        match value {
+            Value::Number(number) => number.as_f64().map(|x| numbers.push(x)).is_some(),
+            Value::Array(values) => values.iter().all(|value| flatten(value, numbers)),
+            _ => false,
+        }
+    }
+    let mut numbers = Vec::new();
+    match params.get(name) {
+        Some(value) if flatten(value, &mut numbers) => Ok(numbers),
+        _ => Err(format!("Missing parameter '{}' in the STC-S {}", name, shape)),
+    }
+}
+
+// Parameter `name` of a shape, which must have N values
+fn stcs_params<const N: usize>(shape: &str, params: &Value, name: &str) -> Result<[f64; N], String> {
+    let numbers = stcs_numbers(shape, params, name)?;
+    numbers
+        .try_into()
+        .map_err(|numbers: Vec<f64>| format!("{} expects {} values for '{}', {} given", shape, N, name, numbers.len()))
+}
+
+// Regions of a compound shape : the shapes found in its parameters, in the order of the STC-S string
+fn stcs_children<'a>(params: &'a Value, children: &mut Vec<&'a Value>) {
+    match params {
+        value if stcs_shape(value).is_some() => children.push(value),
+        Value::Object(fields) => fields.values().for_each(|value| stcs_children(value, children)),
+        Value::Array(values) => values.iter().for_each(|value| stcs_children(value, children)),
+        _ => {}
+    }
+}
+
+// RegionPSQL of a shape of the AST
+fn region_from_ast(ast: &Value) -> Result<RegionPSQL, String> {
+    let (shape, params) = stcs_shape(ast).ok_or_else(|| format!("Unexpected STC-S element {}", ast))?;
+    let compound = |min_len: usize| -> Result<Vec<RegionPSQL>, String> {
+        let mut children = Vec::new();
+        stcs_children(params, &mut children);
+        let regions = children.into_iter().map(region_from_ast).collect::<Result<Vec<RegionPSQL>, String>>()?;
+        if regions.len() < min_len {
+            return Err(format!("{} expects at least {} regions, {} given", shape, min_len, regions.len()));
+        }
+        Ok(regions)
+    };
+    let region = match shape {
+        "AllSky" => RegionPSQL::Zone { lon_min: 0.0, lat_min: -90.0, lon_max: 360.0, lat_max: 90.0 },
+        "Circle" => {
+            let [lon, lat] = stcs_params(shape, params, "pos")?;
+            let [radius] = stcs_params(shape, params, "radius")?;
+            RegionPSQL::Cone { lon, lat, radius }
+        }
+        "Ellipse" => {
+            let [lon, lat] = stcs_params(shape, params, "pos")?;
+            let [a] = stcs_params(shape, params, "radius_a")?;
+            let [b] = stcs_params(shape, params, "radius_b")?;
+            let [pa] = stcs_params(shape, params, "pa")?;
+            RegionPSQL::EllipticalCone { lon, lat, a, b, pa }
+        }
+        "Box" => {
+            let [lon, lat] = stcs_params(shape, params, "pos")?;
+            let [width, height] = stcs_params(shape, params, "bsize")?;
+            if params.get("pa").and_then(Value::as_f64).is_some_and(|pa| pa != 0.0) {
+                return Err(String::from("Rotated STC-S boxes aren't supported"));
+            }
+            RegionPSQL::box_from_size(lon, lat, width, height)
+        }
+        "Polygon" => {
+            let params = stcs_numbers(shape, params, "pos")?;
+            if params.len() < 6 || params.len() % 2 != 0 {
+                return Err(format!("Polygon expects an even number (>= 6) of parameters, {} given", params.len()));
+            }
+            let vertices = params.chunks(2).map(|v| (v[0], v[1])).collect();
+            RegionPSQL::Polygon(PolygonPSQL::from_deg(vertices, false)?)
+        }
+        "Union" => RegionPSQL::Union(compound(2)?),
+        "Intersection" => RegionPSQL::Intersection(compound(2)?),
+        "Not" => match compound(1)?.as_slice() {
+            [region] => RegionPSQL::Not(Box::new(region.clone())),
+            regions => return Err(format!("Not expects a single region, {} given", regions.len())),
+        },
+        _ => return Err(format!("Unsupported STC-S shape '{}'", shape)),
+    };
+    Ok(region)
+}
+
+// STC-S string -> RegionPSQL, the compound shapes (Union, Intersection, Not) giving compound regions
+// e.g. "Circle ICRS 10.68 41.27 0.5" or "Intersection ICRS (Circle 10 20 5 Not (Polygon 9 19 11 19 10 21))"
+pub fn region_from_stcs(stcs: &str) -> Result<RegionPSQL, String> {
+    let stc = parse_stcs(stcs)?;
+    let region = region_from_ast(&space_ast(&stc)?)?;
+    region.to_sky_region()?;
+    Ok(region)
+}
+
+// STC-S -> RegionPSQL
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_region_from_stcs(stcs: &str) -> RegionPSQL {
+    match region_from_stcs(stcs) {
+        Ok(region) => region,
+        Err(e) => error!("Failed to parse the STC-S region : {}", e),
+    }
+}
+
+// ------------------------------------------------ Operations -----------------------------------------------------------
+
+// Tests if the point is in the region
+// The skyregion shape is only built once per scan when the region is a constant (see crate::cache)
+#[pg_extern(immutable, parallel_safe, requires = [RegionPSQL])]
+pub fn mgx_region_contains(fcinfo: pg_sys::FunctionCallInfo, region: DatumArg<RegionPSQL>, lon: f64, lat: f64) -> bool {
+    with_cached_arg(fcinfo, 0, &region, |region| region.to_sky_region().ok(), |shape| {
+        match shape {
+            Some(shape) => shape.recheck(lon.to_radians(), lat.to_radians()),
+            None => false,
+        }
+    })
+}
+
+// RegionPSQL -> RangeMOCPSQL
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_region_to_moc(region: RegionPSQL, depth: i32) -> RangeMOCPSQL {
+    region.to_moc(depth as u8).into()
+}
+
+// RegionPSQL -> BMOCpsql
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_region_to_bmoc(region: RegionPSQL, depth: i32) -> BMOCpsql {
+    region.to_bmoc(depth as u8).into()
+}
+
+// Coverage depth minimizing (number of ranges x scan cost + recheck cost) for a table of n_rows rows
+// (mogipix.coverage_depth, if set, takes precedence)
+#[pg_extern(stable, parallel_safe)]
+pub fn mgx_optimal_coverage_depth(region: RegionPSQL, n_rows: i64, max_depth: default!(i32, 29)) -> i32 {
+    if !(0..=29).contains(&max_depth) {
+        error!("max_depth must be in [0, 29], {} given", max_depth);
+    }
+    region.optimal_coverage_depth(n_rows.max(0) as f64, max_depth as u8) as i32
+}
+
+extension_sql!(
+    r#"
+-- Same as mgx_optimal_coverage_depth with the number of rows estimated by the last ANALYZE of the table
+-- (10 million if it was never analyzed), the depth being at most the depth of the table if it is registered
+-- in mgx_indexed_tables
+CREATE FUNCTION mgx_optimal_coverage_depth(region RegionPSQL, tbl regclass)
+RETURNS integer AS $$
+    SELECT mgx_optimal_coverage_depth(
+        region,
+        (CASE WHEN c.reltuples < 0 THEN 10000000 ELSE c.reltuples END)::bigint,
+        COALESCE((SELECT depth FROM mgx_indexed_tables WHERE table_name = tbl), 29)
+    )
+    FROM pg_class c WHERE c.oid = tbl;
+$$ LANGUAGE sql STABLE;
+"#,
+    name = "mgx_optimal_coverage_depth_table",
+    requires = [mgx_optimal_coverage_depth, "mgx_indexed_tables"]
+);
@@ -0,0 +1,250 @@
+use pgrx::prelude::*; // default
+
+// Library imports
+use cdshealpix::nested::bmoc::BMOC;
+
+use crate::catalog::{IndexedTable, quote_ident, quote_literal, registered_table};
+use crate::predicates::{coverage_bmoc, DEFAULT_N_ROWS};
+use crate::region::RegionPSQL;
+use crate::guc::{angle_to_deg, exact_recheck};
+
+// Searches on the tables registered in mgx_indexed_tables (see crate::catalog).
+// The query is built in Rust and run by a plpgsql wrapper (RETURN QUERY EXECUTE) : it is a UNION ALL of one
+// btree range scan per range of the BMOC coverage of the region, on the hash of the table. The coverage is degraded
+// until it has at most MAX_RANGE_SCANS ranges, whatever the depth chosen by the cost model or the settings.
+// The rows of the full cells are returned directly, the rows of the partial cells are rechecked with the exact shape.
+// The crossmatch joins two registered tables, the rows of the second table being found by range scans on its hash.
+// The frame of the searched region (ICRS by default) must be the one of the registered tables.
+
+// ------------------------------------------------ Query builder --------------------------------------------------------
+
+// Maximum number of range scans of a search query
+pub const MAX_RANGE_SCANS: usize = 256;
+
+// Range [start, end[ of hashes at the table depth and its flag
+#[derive(Debug, PartialEq)]
+pub struct HashRange {
+    pub start: u64,
+    pub end: u64,
+    pub is_full: bool,
+}
+
+// Ranges of the BMOC at `depth` (>= the BMOC depth), the contiguous ranges with the same flag being merged
+pub fn bmoc_hash_ranges(bmoc: &BMOC, depth: u8) -> Vec<HashRange> {
+    let mut ranges: Vec<HashRange> = Vec::new();
+    for raw in bmoc.entries.iter() {
+        let cell = bmoc.from_raw_value(*raw);
+        let shift = 2 * (depth - cell.depth) as u32;
+        let (start, end) = (cell.hash << shift, (cell.hash + 1) << shift);
+        match ranges.last_mut() {
+            Some(last) if last.end == start && last.is_full == cell.is_full => last.end = end,
+            _ => ranges.push(HashRange { start, end, is_full: cell.is_full }),
+        }
+    }
+    ranges
+}
+
+// UNION ALL of the range scans, `recheck` being the SQL condition applied to the rows of the partial cells
+pub fn range_scans_query(table: &IndexedTable, ranges: &[HashRange], recheck: &str) -> String {
+    if ranges.is_empty() {
+        return format!("SELECT t.* FROM {} t WHERE false", table.table);
+    }
+    let hash = table.hash_expression();
+    ranges
+        .iter()
+        .map(|r| {
+            let condition = format!("{hash} >= {} AND {hash} < {}", r.start, r.end);
+            if r.is_full {
+                format!("SELECT t.* FROM {} t WHERE {condition}", table.table)
+            } else {
+                format!("SELECT t.* FROM {} t WHERE {condition} AND {recheck}", table.table)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\nUNION ALL\n")
+}
+
+// Query of the cone search on the registered table, the angles being in mogipix.angle_unit
+// The search is stable, so it can read the settings (see crate::guc)
+pub fn cone_search_query(table: &IndexedTable, lon: f64, lat: f64, radius: f64) -> String {
+    let (lon_deg, lat_deg, radius_deg) = (angle_to_deg(lon), angle_to_deg(lat), angle_to_deg(radius));
+    if !(lon_deg.is_finite() && lat_deg.is_finite() && radius_deg.is_finite()) {
+        error!("Invalid cone ({}, {}, {})", lon, lat, radius);
+    }
+    let depth = table.depth as u8;
+    let n_rows = table.n_rows().unwrap_or(DEFAULT_N_ROWS);
+    let bmoc = search_coverage(cdshealpix::best_starting_depth(radius_deg.to_radians()), depth, n_rows, |bmoc_depth| {
+        cdshealpix::nested::cone_coverage_approx(bmoc_depth, lon_deg.to_radians(), lat_deg.to_radians(), radius_deg.to_radians())
+    });
+    let recheck = format!(
+        "mgx_skyregion_cone_contains({lon_deg}, {lat_deg}, {radius_deg}, {}, {})",
+        quote_ident(&table.lon_column),
+        quote_ident(&table.lat_column)
+    );
+    range_scans_query(table, &search_ranges(&bmoc, depth), &recheck)
+}
+
+// Coverage of a region at most at the depth of the table, with at most MAX_RANGE_SCANS ranges at this depth
+fn search_coverage(characteristic_depth: u8, depth: u8, n_rows: f64, build: impl Fn(u8) -> BMOC) -> BMOC {
+    let mut bmoc = coverage_bmoc(characteristic_depth, depth, n_rows, &build);
+    while bmoc.get_depth_max() > 0 && bmoc_hash_ranges(&bmoc, depth).len() > MAX_RANGE_SCANS {
+        bmoc = build(bmoc.get_depth_max() - 1);
+    }
+    bmoc
+}
+
+// Ranges of the coverage at the depth of the table, all full if mogipix.exact_recheck is off
+fn search_ranges(bmoc: &BMOC, depth: u8) -> Vec<HashRange> {
+    let mut ranges = bmoc_hash_ranges(bmoc, depth);
+    if !exact_recheck() {
+        ranges.iter_mut().for_each(|range| range.is_full = true);
+    }
+    ranges
+}
+
+// Query of the search of the rows of the registered table in the region
+pub fn region_search_query(table: &IndexedTable, region: &RegionPSQL) -> String {
+    if let Err(e) = region.to_sky_region() {
+        error!("Invalid region {:?} : {}", region, e);
+    }
+    let depth = table.depth as u8;
+    let n_rows = table.n_rows().unwrap_or(DEFAULT_N_ROWS);
+    let bmoc = search_coverage(region.characteristic_depth(), depth, n_rows, |bmoc_depth| region.to_bmoc(bmoc_depth));
+    let region_json = match serde_json::to_string(region) {
+        Ok(json) => json,
+        Err(e) => error!("Failed to write the region {:?} : {}", region, e),
+    };
+    let recheck = format!(
+        "mgx_region_contains({}::RegionPSQL, {}, {})",
+        quote_literal(&region_json),
+        quote_ident(&table.lon_column),
+        quote_ident(&table.lat_column)
+    );
+    range_scans_query(table, &search_ranges(&bmoc, depth), &recheck)
+}
+
+// Query of the crossmatch of the registered tables : the pairs of rows (a.*, b.*, distance in degrees) closer than
+// `radius_deg`. The rows of `table_b` close to a row of `table_a` are found by range scans on the hash of `table_b`
+// (ranges given by mgx_crossmatch_ranges).
+pub fn crossmatch_query(table_a: &IndexedTable, table_b: &IndexedTable, radius_deg: f64) -> String {
+    if radius_deg.is_nan() || radius_deg <= 0.0 || radius_deg > 180.0 {
+        error!("Invalid crossmatch radius {} (in degrees)", radius_deg);
+    }
+    if !table_a.frame.eq_ignore_ascii_case(&table_b.frame) {
+        error!("The coordinates of {} ({}) and {} ({}) aren't in the same frame", table_a.table, table_a.frame, table_b.table, table_b.frame);
+    }
+    let (lon_a, lat_a) = (quote_ident(&table_a.lon_column), quote_ident(&table_a.lat_column));
+    let (lon_b, lat_b) = (quote_ident(&table_b.lon_column), quote_ident(&table_b.lat_column));
+    format!(
+        "SELECT a.*, t.*, mgx_adql_distance(a.{lon_a}, a.{lat_a}, t.{lon_b}, t.{lat_b}) AS distance
+         FROM {} a
+         CROSS JOIN LATERAL mgx_crossmatch_ranges(a.{lon_a}, a.{lat_a}, {radius_deg}, {}) r
+         JOIN {} t ON {hash} >= r.hash_start AND {hash} < r.hash_end
+         WHERE mgx_adql_distance(a.{lon_a}, a.{lat_a}, t.{lon_b}, t.{lat_b}) <= {radius_deg}",
+        table_a.table,
+        table_b.depth,
+        table_b.table,
+        hash = table_b.hash_expression_of("t"),
+    )
+}
+
+// Ranges of hashes at `depth` of the cells overlapping the cone, used by the crossmatch query
+#[pg_extern(immutable, parallel_safe)]
+pub fn mgx_crossmatch_ranges(
+    lon_deg: f64,
+    lat_deg: f64,
+    radius_deg: f64,
+    depth: i32,
+) -> TableIterator<'static, (name!(hash_start, i64), name!(hash_end, i64))> {
+    if !(0..=29).contains(&depth) {
+        error!("Invalid depth {} (max 29)", depth);
+    }
+    let depth = depth as u8;
+    let bmoc_depth = cdshealpix::best_starting_depth(radius_deg.to_radians()).min(depth);
+    let bmoc = cdshealpix::nested::cone_coverage_approx(bmoc_depth, lon_deg.to_radians(), lat_deg.to_radians(), radius_deg.to_radians());
+    // The ranges of the BMOC are at its own depth
+    let shift = 2 * (depth - bmoc.get_depth_max()) as u32;
+    TableIterator::new(bmoc.to_ranges().iter().map(move |r| {
+        ((r.start << shift) as i64, (r.end << shift) as i64)
+    }).collect::<Vec<(i64, i64)>>())
+}
+
+// SQL query of the cone search on a table registered in mgx_indexed_tables, used by mgx_cone_search
+// The angles are in mogipix.angle_unit
+#[pg_extern(stable)]
+pub fn mgx_cone_search_query(table_name: &str, lon: f64, lat: f64, radius: f64, frame: default!(&str, "'ICRS'")) -> String {
+    let table = registered_table(table_name);
+    table.check_frame(frame);
+    cone_search_query(&table, lon, lat, radius)
+}
+
+// SQL query of the region search on a table registered in mgx_indexed_tables, used by mgx_region_search
+#[pg_extern(stable, requires = [RegionPSQL])]
+pub fn mgx_region_search_query(table_name: &str, region: RegionPSQL, frame: default!(&str, "'ICRS'")) -> String {
+    let table = registered_table(table_name);
+    table.check_frame(frame);
+    region_search_query(&table, &region)
+}
+
+// SQL query of the crossmatch of two tables registered in mgx_indexed_tables, used by mgx_crossmatch
+// The radius is in mogipix.angle_unit
+#[pg_extern(stable)]
+pub fn mgx_crossmatch_query(table_a: &str, table_b: &str, radius: f64) -> String {
+    crossmatch_query(&registered_table(table_a), &registered_table(table_b), angle_to_deg(radius))
+}
+
+// ------------------------------------------------- SQL functions -------------------------------------------------------
+
+extension_sql!(
+    r#"
+-- Rows of the registered table in the cone, the angles being in mogipix.angle_unit (degrees by default), e.g.
+-- SELECT * FROM mgx_cone_search('hip_table', 0.01814144, 3.94648893, 5.64323) AS t(hip bigint, vmag double precision, raicrs double precision, deicrs double precision);
+-- The frame of the cone must be the one of the table
+CREATE FUNCTION mgx_cone_search(tbl regclass, lon double precision, lat double precision, radius double precision, frame text DEFAULT 'ICRS')
+RETURNS SETOF record AS $$
+BEGIN
+    RETURN QUERY EXECUTE mgx_cone_search_query(tbl::text, lon, lat, radius, frame);
+END;
+$$ LANGUAGE plpgsql STABLE;
+
+-- Same as mgx_cone_search but the rows have the type of the table, so no column list is needed, e.g.
+-- SELECT * FROM mgx_cone_search_typed(NULL::hip_table, 0.01814144, 3.94648893, 5.64323);
+CREATE FUNCTION mgx_cone_search_typed(tbl anyelement, lon double precision, lat double precision, radius double precision, frame text DEFAULT 'ICRS')
+RETURNS SETOF anyelement AS $$
+BEGIN
+    RETURN QUERY EXECUTE mgx_cone_search_query(pg_typeof(tbl)::text, lon, lat, radius, frame);
+END;
+$$ LANGUAGE plpgsql STABLE;
+
+-- Rows of the registered table in the region, e.g.
+-- SELECT * FROM mgx_region_search_typed(NULL::hip_table, mgx_region_box(10, 20, 2, 1, 30));
+CREATE FUNCTION mgx_region_search(tbl regclass, region RegionPSQL, frame text DEFAULT 'ICRS')
+RETURNS SETOF record AS $$
+BEGIN
+    RETURN QUERY EXECUTE mgx_region_search_query(tbl::text, region, frame);
+END;
+$$ LANGUAGE plpgsql STABLE;
+
+CREATE FUNCTION mgx_region_search_typed(tbl anyelement, region RegionPSQL, frame text DEFAULT 'ICRS')
+RETURNS SETOF anyelement AS $$
+BEGIN
+    RETURN QUERY EXECUTE mgx_region_search_query(pg_typeof(tbl)::text, region, frame);
+END;
+$$ LANGUAGE plpgsql STABLE;
+
+-- Pairs of rows of the registered tables closer than the radius (in mogipix.angle_unit) : the columns of tbl_a,
+-- the columns of tbl_b and the distance in degrees, e.g.
+-- SELECT * FROM mgx_crossmatch('hip_table', 'tyc2', 1.0 / 3600) AS t(hip bigint, ..., distance double precision);
+CREATE FUNCTION mgx_crossmatch(tbl_a regclass, tbl_b regclass, radius double precision)
+RETURNS SETOF record AS $$
+BEGIN
+    RETURN QUERY EXECUTE mgx_crossmatch_query(tbl_a::text, tbl_b::text, radius);
+END;
+$$ LANGUAGE plpgsql STABLE;
+"#,
+    name = "mgx_cone_search",
+    requires = [
+        mgx_cone_search_query, mgx_region_search_query, mgx_crossmatch_query, mgx_crossmatch_ranges,
+        crate::bmoc::mgx_skyregion_cone_contains, crate::region::mgx_region_contains, crate::adql::mgx_adql_distance_deg,
+    ]
+);
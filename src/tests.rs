@@ -10,6 +10,7 @@ mod tests {
   use pgrx::datum::Range;
   use cdshealpix::nested::n_hash;
   use crate::bmoc::BMOCpsql;
+  use cdshealpix::nested::bmoc::{BMOC, BMOCBuilderUnsafe};
   use crate::moc::*;
   use moc::elemset::range::MocRanges;
   use moc::qty::Hpx;
@@ -225,4 +226,811 @@ mod tests {
 
     assert_eq!(crate::bmoc::mgx_bmoc_or(bmoc_1, bmoc_2), bmoc_res);
   }
+
+  // Deterministic pseudo-random generator (xorshift64) for the conversion tests
+  fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+  }
+
+  #[pg_test]
+  fn test_u64_i64_round_trip() {
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut values: Vec<u64> = vec![0, 1, (1 << 63) - 1, 1 << 63, u64::MAX];
+    for _ in 0..10_000 {
+      values.push(xorshift(&mut state));
+    }
+    let values_i64 = crate::convert::u64s_to_i64s(values.clone());
+    assert!(values_i64.iter().any(|v| *v < 0));
+    assert_eq!(crate::convert::i64s_to_u64s(values_i64), values);
+
+    let ranges: Vec<std::ops::Range<u64>> = values.chunks(2).map(|r| r[0].min(r[1])..r[0].max(r[1])).collect();
+    assert_eq!(crate::convert::i64_ranges_to_u64(crate::convert::u64_ranges_to_i64(ranges.clone())), ranges);
+  }
+
+  #[pg_test]
+  // At depth 29 the BMOC raw values of the last base cells have their sign bit set once stored as bigints
+  fn test_bmoc_round_trip_depth_29() {
+    let n: u64 = n_hash(29);
+    let mut state: u64 = 0x2545_F491_4F6C_DD1D;
+    let mut hashes: Vec<u64> = vec![0, 1, n / 2, n - 2, n - 1];
+    for _ in 0..1_000 {
+      hashes.push(xorshift(&mut state) % n);
+    }
+    hashes.sort_unstable();
+    hashes.dedup();
+
+    let mut builder = BMOCBuilderUnsafe::new(29, hashes.len());
+    for (i, hash) in hashes.iter().enumerate() {
+      builder.push(29, *hash, i % 2 == 0);
+    }
+    let bmoc: BMOC = builder.to_bmoc();
+    let entries: Vec<u64> = bmoc.entries.to_vec();
+
+    let bmoc_psql: BMOCpsql = bmoc.into();
+    assert!(bmoc_psql.entries.iter().any(|v| *v < 0));
+    let bmoc_back: BMOC = bmoc_psql.into();
+    assert_eq!(bmoc_back.entries.to_vec(), entries);
+  }
+
+  #[pg_test]
+  fn test_range_moc_round_trip_depth_29() {
+    let n: i64 = n_hash(29) as i64;
+    let ranges = vec![0..1, (n / 2)..(n / 2 + 10), (n - 10)..n];
+    let moc_psql = RangeMOCPSQL { depth_max: 29, ranges: ranges.clone() };
+    let moc: RangeMOC<u64, Hpx<u64>> = moc_psql.into();
+    let moc_back: RangeMOCPSQL = moc.into();
+    assert_eq!(moc_back.ranges, ranges);
+
+    // The depth 29 hashes are < 2^63 but the ranges conversions must also keep the values with the bit 63 set
+    let high: Vec<std::ops::Range<u64>> = vec![((1 << 63) - 1)..(1 << 63), (1 << 63)..((1 << 63) + 10), (u64::MAX - 5)..u64::MAX];
+    let high_i64 = crate::convert::u64_ranges_to_i64(high.clone());
+    assert_eq!(high_i64[0], i64::MAX..i64::MIN);
+    assert!(high_i64[1].start < 0 && high_i64[2].end == -1);
+    assert_eq!(crate::convert::i64_ranges_to_u64(high_i64), high);
+    let values: Vec<u64> = vec![1 << 63, (1 << 63) | 1, u64::MAX - 1, u64::MAX];
+    let values_i64 = crate::convert::u64s_to_i64s(values.clone());
+    assert_eq!(values_i64, vec![i64::MIN, i64::MIN + 1, -2, -1]);
+    assert_eq!(crate::convert::i64s_to_u64s(values_i64), values);
+  }
+
+  #[pg_test]
+  // The cached (constant argument) and uncached (per-row argument) calls must give the results of direct calls
+  fn test_cached_contains() {
+    use crate::bmoc::{mgx_bmoc_cone_coverage_approx, mgx_bmoc_contains_bool};
+    use crate::moc::mgx_is_in_moc;
+    Spi::run("CREATE TABLE pts AS SELECT i, (i * 7.3) % 360 AS lon, ((i * 3.1) % 180) - 90 AS lat FROM generate_series(1, 500) i").unwrap();
+    Spi::run("ALTER TABLE pts ALTER COLUMN lon TYPE float8, ALTER COLUMN lat TYPE float8").unwrap();
+    Spi::run(
+      "CREATE TABLE shapes AS SELECT id, mgx_bmoc_cone_coverage_approx(6, lon, lat, 30) AS bmoc,
+              mgx_moc_from_cone(lon, lat, 30, 6, 2, 'All') AS moc
+       FROM (VALUES (1, 100.0::float8, 10.0::float8), (2, 250.0::float8, -40.0::float8)) AS c(id, lon, lat)"
+    ).unwrap();
+    let (lons, lats) = Spi::get_two::<Vec<f64>, Vec<f64>>(
+      "SELECT array_agg(lon ORDER BY i), array_agg(lat ORDER BY i) FROM pts"
+    ).unwrap();
+    let (lons, lats) = (lons.unwrap(), lats.unwrap());
+
+    for (id, lon, lat) in [(1, 100.0, 10.0), (2, 250.0, -40.0)] {
+      let expected_bmoc: Vec<bool> = lons.iter().zip(lats.iter())
+        .map(|(l, b)| mgx_bmoc_contains_bool(std::ptr::null_mut(), mgx_bmoc_cone_coverage_approx(6, lon, lat, 30.0).into(), *l, *b))
+        .collect();
+      let expected_moc: Vec<bool> = lons.iter().zip(lats.iter())
+        .map(|(l, b)| mgx_is_in_moc(std::ptr::null_mut(), mgx_moc_from_cone(lon, lat, 30.0, 6, 2, CellSelectionPSQL::All).into(), *l, *b))
+        .collect();
+      assert!(expected_bmoc.iter().any(|v| *v) && expected_bmoc.iter().any(|v| !*v));
+
+      // Constant argument : cached
+      let constant = Spi::get_two::<Vec<bool>, Vec<bool>>(&format!(
+        "SELECT array_agg(mgx_bmoc_contains_bool(mgx_bmoc_cone_coverage_approx(6, {lon}, {lat}, 30), lon, lat) ORDER BY i),
+                array_agg(mgx_is_in_moc(mgx_moc_from_cone({lon}, {lat}, 30, 6, 2, 'All'), lon, lat) ORDER BY i)
+         FROM pts"
+      )).unwrap();
+      assert_eq!(constant, (Some(expected_bmoc.clone()), Some(expected_moc.clone())));
+
+      // Per-row argument, alternating between the two shapes : not cached
+      let per_row = Spi::get_two::<Vec<bool>, Vec<bool>>(&format!(
+        "SELECT array_agg(mgx_bmoc_contains_bool(s.bmoc, p.lon, p.lat) ORDER BY p.i) FILTER (WHERE s.id = {id}),
+                array_agg(mgx_is_in_moc(s.moc, p.lon, p.lat) ORDER BY p.i) FILTER (WHERE s.id = {id})
+         FROM pts p CROSS JOIN shapes s"
+      )).unwrap();
+      assert_eq!(per_row, (Some(expected_bmoc), Some(expected_moc)));
+    }
+  }
+
+  #[pg_test]
+  fn test_moc_uniq_round_trip() {
+    use crate::moc::{mgx_moc_from_uniq, mgx_moc_from_cells, mgx_moc_to_uniq};
+    // Cell 0 at depth 0, its child (1, 2) is merged with it, and cell 5000 at depth 5
+    let uniqs = vec![
+      crate::mgx_to_uniq(5, 5000),
+      crate::mgx_to_uniq(0, 0),
+      crate::mgx_to_uniq(1, 2),
+    ];
+    let moc = mgx_moc_from_uniq(uniqs);
+    assert_eq!(moc.depth_max, 5);
+    assert_eq!(moc.ranges.len(), 2);
+    assert_eq!(mgx_moc_to_uniq(moc), vec![crate::mgx_to_uniq(0, 0), crate::mgx_to_uniq(5, 5000)]);
+
+    // 4 siblings are returned as their parent
+    let moc = mgx_moc_from_cells(vec![3, 3, 3, 3], vec![7, 4, 6, 5]);
+    assert_eq!(mgx_moc_to_uniq(moc), vec![crate::mgx_to_uniq(2, 1)]);
+  }
+
+  #[pg_test]
+  fn test_moc_cells() {
+    use crate::moc::{mgx_moc_from_cells, mgx_moc_cells, mgx_moc_cells_at};
+    let cells = || mgx_moc_from_cells(vec![0, 2, 2], vec![1, 0, 1]);
+    let exploded: Vec<(i32, i64)> = mgx_moc_cells(cells()).collect();
+    assert_eq!(exploded, vec![(2, 0), (2, 1), (0, 1)]);
+    let at_1: Vec<i64> = mgx_moc_cells_at(cells(), 1).collect();
+    assert_eq!(at_1, vec![0, 4, 5, 6, 7]);
+    let at_0: Vec<i64> = mgx_moc_cells_at(cells(), 0).collect();
+    assert_eq!(at_0, vec![0, 1]);
+    // Empty and unsorted ranges given to the MOC
+    let n = cdshealpix::nested::n_hash(29) as i64;
+    let unnormalized = || RangeMOCPSQL { depth_max: 0, ranges: vec![n / 12..n / 6, 0..0, 0..n / 12] };
+    let at_0: Vec<i64> = mgx_moc_cells_at(unnormalized(), 0).collect();
+    assert_eq!(at_0, vec![0, 1]);
+    let exploded: Vec<(i32, i64)> = mgx_moc_cells(unnormalized()).collect();
+    assert_eq!(exploded, vec![(0, 0), (0, 1)]);
+
+    let bmoc = crate::bmoc::mgx_bmoc_cone_coverage_approx(6, 13.158329, -72.80028, 5.64323);
+    let n_entries = bmoc.entries.len();
+    let bmoc_cells: Vec<(i32, i64, bool)> = crate::bmoc::mgx_bmoc_cells(bmoc).collect();
+    assert_eq!(bmoc_cells.len(), n_entries);
+    assert!(bmoc_cells.iter().all(|(depth, _, _)| *depth <= 6));
+  }
+
+  #[pg_test]
+  fn test_bmoc_coverage_modes() {
+    use crate::bmoc::{mgx_bmoc_cone_coverage_mode, mgx_bmoc_ring_coverage_mode, CoverageModePSQL};
+    // Area of the BMOC in number of depth 29 cells
+    let area = |bmoc: BMOCpsql| -> u64 { BMOC::from(bmoc).to_ranges().iter().map(|r| r.end - r.start).sum() };
+    let cone = |mode| area(mgx_bmoc_cone_coverage_mode(8, 13.158329, -72.80028, 5.64323, mode, 2));
+    let (approx, custom) = (cone(CoverageModePSQL::Approx), cone(CoverageModePSQL::Custom));
+    let (fullin, centers) = (cone(CoverageModePSQL::FullIn), cone(CoverageModePSQL::Centers));
+    assert!(0 < fullin && fullin < centers && centers < custom && custom <= approx);
+
+    let ring = |mode| area(mgx_bmoc_ring_coverage_mode(8, 13.158329, -72.80028, 2.0, 5.64323, mode, 2));
+    let (approx, fullin, centers) = (ring(CoverageModePSQL::Approx), ring(CoverageModePSQL::FullIn), ring(CoverageModePSQL::Centers));
+    assert!(0 < fullin && fullin < centers && centers < approx);
+
+    // No FullIn cell of the ring intersects the internal cone
+    let fullin = BMOC::from(mgx_bmoc_ring_coverage_mode(8, 13.158329, -72.80028, 2.0, 5.64323, CoverageModePSQL::FullIn, 2));
+    let internal = cdshealpix::nested::cone_coverage_approx(8, 13.158329f64.to_radians(), (-72.80028f64).to_radians(), 2f64.to_radians());
+    assert!(fullin.entries.iter().all(|raw| fullin.from_raw_value(*raw).is_full));
+    assert!(fullin.and(&internal).entries.is_empty());
+  }
+
+  #[pg_test]
+  fn test_moc_from_cones() {
+    use crate::multi::mgx_moc_from_cones;
+    let (lons, lats, radii): (Vec<f64>, Vec<f64>, Vec<f64>) = (0..1000)
+      .map(|i| ((i as f64 * 0.36) % 360.0, ((i as f64 * 0.17) % 160.0) - 80.0, 0.05))
+      .fold((vec![], vec![], vec![]), |(mut lons, mut lats, mut radii), (lon, lat, r)| {
+        lons.push(lon); lats.push(lat); radii.push(r);
+        (lons, lats, radii)
+      });
+    let union = mgx_moc_from_cones(lons.clone(), lats.clone(), radii.clone(), 10, 2, CellSelectionPSQL::All);
+    // Same as the union of the cones built one by one
+    let expected = (0..lons.len())
+      .map(|i| mgx_moc_from_cone(lons[i], lats[i], radii[i], 10, 2, CellSelectionPSQL::All))
+      .fold(RangeMOCPSQL::from(RangeMOC::<u64, Hpx<u64>>::new_empty(10)), mgx_moc_or);
+    assert_eq!((union.depth_max, &union.ranges), (expected.depth_max, &expected.ranges));
+    assert_eq!(mgx_moc_from_cones(vec![], vec![], vec![], 10, 2, CellSelectionPSQL::All).ranges.len(), 0);
+    // Same MOCs built in the backend only
+    Spi::run("SET mogipix.max_threads = 1").unwrap();
+    assert_eq!(mgx_moc_from_cones(lons.clone(), lats.clone(), radii.clone(), 10, 2, CellSelectionPSQL::All).ranges, union.ranges);
+    let inside = mgx_moc_from_cones(lons.clone(), lats.clone(), radii.clone(), 10, 2, CellSelectionPSQL::Inside);
+    Spi::run("RESET mogipix.max_threads").unwrap();
+    assert_eq!(mgx_moc_from_cones(lons, lats, radii, 10, 2, CellSelectionPSQL::Inside).ranges, inside.ranges);
+  }
+
+  #[pg_test]
+  fn test_moc_from_ellipses_and_boxes() {
+    use crate::multi::{mgx_moc_from_elliptical_cones, mgx_moc_from_boxes};
+    let (lons, lats) = (vec![10.0, 10.2, 100.0], vec![20.0, 20.1, -20.0]);
+    let (a, b, pa) = (vec![0.5, 0.4, 1.0], vec![0.2, 0.1, 0.5], vec![30.0, 45.0, 0.0]);
+    let ellipses = mgx_moc_from_elliptical_cones(lons.clone(), lats.clone(), a.clone(), b.clone(), pa.clone(), 10, 2, CellSelectionPSQL::All);
+    let expected = (1..3).fold(
+      mgx_moc_from_elliptical_cone(lons[0], lats[0], a[0], b[0], pa[0], 10, 2, CellSelectionPSQL::All),
+      |moc, i| mgx_moc_or(moc, mgx_moc_from_elliptical_cone(lons[i], lats[i], a[i], b[i], pa[i], 10, 2, CellSelectionPSQL::All))
+    );
+    assert_eq!(ellipses.ranges, expected.ranges);
+
+    let boxes = mgx_moc_from_boxes(lons.clone(), lats.clone(), a.clone(), b.clone(), pa.clone(), 10, CellSelectionPSQL::All);
+    let expected = (1..3).fold(
+      mgx_moc_from_box(lons[0], lats[0], a[0], b[0], pa[0], 10, CellSelectionPSQL::All),
+      |moc, i| mgx_moc_or(moc, mgx_moc_from_box(lons[i], lats[i], a[i], b[i], pa[i], 10, CellSelectionPSQL::All))
+    );
+    assert_eq!(boxes.ranges, expected.ranges);
+
+    // Aggregate
+    Spi::run("CREATE TABLE detections (ra float8, dec float8, a float8, b float8, pa float8)").unwrap();
+    Spi::run("INSERT INTO detections VALUES (10, 20, 0.5, 0.2, 30), (10.2, 20.1, 0.4, 0.1, 45), (100, -20, 1, 0.5, 0)").unwrap();
+    let agg = Spi::get_one::<RangeMOCPSQL>("SELECT mgx_moc_from_ellipse_agg(ra, dec, a, b, pa, 10, 2, 'All') FROM detections").unwrap().unwrap();
+    assert_eq!(agg.ranges, ellipses.ranges);
+    let agg_inside = Spi::get_one::<RangeMOCPSQL>("SELECT mgx_moc_from_ellipse_agg(ra, dec, a, b, pa, 10, 3, 'Inside') FROM detections").unwrap().unwrap();
+    let inside = mgx_moc_from_elliptical_cones(lons, lats, a, b, pa, 10, 3, CellSelectionPSQL::Inside);
+    assert_eq!(agg_inside.ranges, inside.ranges);
+    assert!(Spi::get_one::<RangeMOCPSQL>("SELECT mgx_moc_from_ellipse_agg(ra, dec, a, b, pa, 10, 2, 'All') FROM detections WHERE false").unwrap().is_none());
+  }
+
+  #[pg_test]
+  fn test_moc_normalize() {
+    use crate::moc::{mgx_moc_normalize, mgx_moc_set_depth_max};
+    let shift_0 = 2 * 29;
+    let cell_0 = |hash: i64| (hash << shift_0)..((hash + 1) << shift_0);
+    // Unsorted, overlapping and not aligned on the depth 0
+    let moc = RangeMOCPSQL { depth_max: 0, ranges: vec![cell_0(3), 5..10, cell_0(2), (cell_0(2).start + 1)..cell_0(3).end] };
+    let normalized = mgx_moc_normalize(moc);
+    assert_eq!(normalized.ranges, vec![cell_0(0), cell_0(2).start..cell_0(3).end]);
+
+    // Deeper depth : same coverage, lower depth : partial cells are enlarged
+    let refined = mgx_moc_set_depth_max(mgx_moc_normalize(RangeMOCPSQL { depth_max: 3, ranges: vec![0..1] }), 10);
+    assert_eq!(refined.depth_max, 10);
+    assert_eq!(refined.ranges, vec![0..(1 << (2 * 26))]);
+    let degraded = mgx_moc_set_depth_max(refined, 0);
+    assert_eq!(degraded.ranges, vec![cell_0(0)]);
+
+    assert!(RangeMOCPSQL { depth_max: 30, ranges: vec![] }.normalized().is_err());
+    assert!(RangeMOCPSQL { depth_max: 3, ranges: vec![-1..5] }.normalized().is_err());
+
+    // The text input is normalized too
+    let input = Spi::get_one::<RangeMOCPSQL>(&format!(
+      r#"SELECT '{{"depth_max": 0, "ranges": [{{"start": {}, "end": {}}}, {{"start": 5, "end": 10}}, {{"start": 0, "end": 0}}]}}'::RangeMOCPSQL"#,
+      cell_0(3).start, cell_0(3).end
+    )).unwrap().unwrap();
+    assert_eq!(input.ranges, vec![cell_0(0), cell_0(3)]);
+  }
+
+  #[pg_test]
+  fn test_moc_morphology() {
+    let cone = |radius: f64| mgx_moc_from_cone(13.158329, -72.80028, radius, 8, 2, CellSelectionPSQL::All);
+    let to_moc = |moc: RangeMOCPSQL| -> RangeMOC<u64, Hpx<u64>> { moc.into() };
+    let is_in = |moc: &RangeMOC<u64, Hpx<u64>>, lat: f64| moc.is_in(13.158329f64.to_radians(), lat.to_radians());
+
+    let expanded = to_moc(mgx_moc_expand_by_angle(cone(1.0), 2.0));
+    assert!(is_in(&expanded, -72.80028 + 2.5));
+    assert!(!is_in(&expanded, -72.80028 + 5.0));
+
+    let annulus = mgx_moc_minus(cone(5.0), cone(2.0));
+    assert!(!is_in(&to_moc(mgx_moc_fill_holes(annulus.clone())), -72.80028 + 10.0));
+    assert!(is_in(&to_moc(mgx_moc_fill_holes(annulus.clone())), -72.80028));
+    // MOC covering more than half of the sky with 2 holes : the largest one is taken as the outside
+    // unless a point of the outside is given
+    let holed = mgx_moc_not(mgx_moc_or(cone(5.0), mgx_moc_from_cone(13.158329, 0.0, 2.0, 8, 2, CellSelectionPSQL::All)));
+    let filled = to_moc(mgx_moc_fill_holes(holed.clone()));
+    assert!(is_in(&filled, 0.0) && !is_in(&filled, -72.80028));
+    let filled = to_moc(mgx_moc_fill_holes_around(holed.clone(), 13.158329, 0.0));
+    assert!(is_in(&filled, -72.80028) && !is_in(&filled, 0.0));
+
+    // The internal border is in the MOC, the external one isn't
+    let inner = to_moc(mgx_moc_internal_border(annulus.clone()));
+    let outer = to_moc(mgx_moc_external_border(annulus.clone()));
+    assert!(RangeMOCPSQL::from(inner.minus(&to_moc(annulus.clone()))).ranges.is_empty());
+    assert!(RangeMOCPSQL::from(outer.and(&to_moc(annulus.clone()))).ranges.is_empty());
+    assert!(mgx_moc_contracted(annulus.clone()).sky_fraction() < annulus.sky_fraction());
+  }
+
+  #[pg_test]
+  fn test_moc_outlines() {
+    // A single cell : one outline made of its 4 vertices
+    let cell = mgx_moc_from_cells(vec![3], vec![42]);
+    let outlines = moc_outlines(cell);
+    assert_eq!(outlines.len(), 1);
+    assert_eq!(outlines[0].len(), 4);
+
+    // The same cell given by its 4 children : the sides are traced at the depth of the cell, not at the MOC depth
+    let children = mgx_moc_from_cells(vec![4, 4, 4, 4], vec![168, 169, 170, 171]);
+    let outlines = moc_outlines(children);
+    assert_eq!(outlines.len(), 1);
+    assert_eq!(outlines[0].len(), 4);
+
+    // A neighbour of depth 5 on the SE side of the cell : only this side is split, down to depth 5
+    let se = cdshealpix::nested::neighbours(3, 42, false).get(cdshealpix::compass_point::MainWind::SE).copied().unwrap();
+    let outlines = moc_outlines(mgx_moc_from_cells(vec![3, 5], vec![42, (16 * se + 10) as i64]));
+    assert_eq!(outlines.len(), 1);
+    assert_eq!(outlines[0].len(), 4 + 2 + 2);
+
+    // The 4 cells around the north pole and the north polar cap : the sides of the polar triangles are matched
+    let outlines = moc_outlines(mgx_moc_from_cells(vec![1, 1, 1, 1], vec![3, 7, 11, 15]));
+    assert_eq!(outlines.len(), 1);
+    assert_eq!(outlines[0].len(), 8);
+    let outlines = moc_outlines(mgx_moc_from_cells(vec![0, 0, 0, 0], vec![0, 1, 2, 3]));
+    assert_eq!(outlines.len(), 1);
+    assert_eq!(outlines[0].len(), 8);
+
+    // Annulus : an outer outline and a hole
+    let cone = |radius: f64| mgx_moc_from_cone(13.158329, 40.0, radius, 7, 2, CellSelectionPSQL::All);
+    assert_eq!(moc_outlines(mgx_moc_minus(cone(5.0), cone(2.0))).len(), 2);
+  }
+
+  #[pg_test]
+  fn test_geojson_wkt_export() {
+    use crate::export::*;
+    let geojson = mgx_cell_to_geojson(0, 4).0;
+    let ring = geojson["features"][0]["geometry"]["coordinates"][0].as_array().unwrap().clone();
+    assert_eq!(ring.first(), ring.last());
+    // Base cell 4 is centred on lon = 0 : the ring mustn't jump between 0 and 360
+    let lons: Vec<f64> = ring.iter().map(|v| v[0].as_f64().unwrap()).collect();
+    assert!(lons.windows(2).all(|w| (w[1] - w[0]).abs() < 180.0));
+
+    let moc = mgx_moc_from_cells(vec![0, 2], vec![1, 0]);
+    assert_eq!(mgx_moc_to_geojson(moc.clone()).0["features"].as_array().unwrap().len(), 2);
+    assert!(mgx_moc_to_wkt(moc).starts_with("MULTIPOLYGON((("));
+    assert!(mgx_cell_to_wkt(3, 42).starts_with("POLYGON(("));
+    assert_eq!(mgx_moc_to_wkt(RangeMOCPSQL { depth_max: 3, ranges: vec![] }), "MULTIPOLYGON EMPTY");
+
+    // The longitudes of a ring are unwrapped and the ring is closed
+    let coordinates = unwrapped_coordinates(&[(359f64.to_radians(), 0.0), (1f64.to_radians(), 0.0)], true);
+    assert!((coordinates[1][0] - 361.0).abs() < 1e-9);
+    assert_eq!(coordinates.len(), 3);
+  }
+
+  #[pg_test]
+  fn test_export_antimeridian() {
+    use crate::export::*;
+    use crate::region::RegionPSQL;
+    let in_range = |polygons: &serde_json::Value| polygons.as_array().unwrap().iter()
+      .flat_map(|polygon| polygon.as_array().unwrap().clone())
+      .flat_map(|ring| ring.as_array().unwrap().clone())
+      .all(|v| (-180.0..=180.0).contains(&v[0].as_f64().unwrap()));
+
+    // Base cell 6 is centred on lon = 180 : it is cut in two polygons
+    let geometry = mgx_cell_to_geojson(0, 6).0["features"][0]["geometry"].clone();
+    assert_eq!(geometry["type"], "MultiPolygon");
+    assert_eq!(geometry["coordinates"].as_array().unwrap().len(), 2);
+    assert!(in_range(&geometry["coordinates"]));
+    assert!(mgx_cell_to_wkt(0, 6).starts_with("MULTIPOLYGON((("));
+    // Base cell 4, centred on lon = 0, is kept in one piece with negative longitudes
+    let geometry = mgx_cell_to_geojson(0, 4).0["features"][0]["geometry"].clone();
+    assert_eq!(geometry["type"], "Polygon");
+    assert!(in_range(&serde_json::json!([geometry["coordinates"]])));
+
+    let outline = mgx_moc_outline_geojson(mgx_moc_from_cone(180.0, 0.0, 5.0, 6, 2, CellSelectionPSQL::All)).0;
+    assert!(outline["geometry"]["coordinates"].as_array().unwrap().len() >= 2);
+    assert!(in_range(&serde_json::json!([outline["geometry"]["coordinates"]])));
+
+    // Regions
+    let cone = |lon: f64, lat: f64, radius: f64| mgx_region_to_geojson(RegionPSQL::Cone { lon, lat, radius }).0["geometry"].clone();
+    assert_eq!(cone(10.0, 20.0, 5.0)["type"], "Polygon");
+    let geometry = cone(180.0, 0.0, 5.0);
+    assert_eq!(geometry["type"], "MultiPolygon");
+    assert!(in_range(&geometry["coordinates"]));
+    // A cone around the pole goes through the pole along the antimeridian
+    let geometry = cone(0.0, 88.0, 5.0);
+    assert_eq!(geometry["type"], "Polygon");
+    assert!(geometry["coordinates"][0].as_array().unwrap().iter().any(|v| v[1].as_f64().unwrap() == 90.0));
+    // A cone containing both poles is the whole sky minus a hole
+    let geometry = cone(180.0, 0.0, 120.0);
+    assert_eq!(geometry["type"], "Polygon");
+    assert_eq!(geometry["coordinates"].as_array().unwrap().len(), 2);
+
+    let ring = mgx_region_to_geojson(RegionPSQL::Ring { lon: 10.0, lat: 20.0, r_min: 2.0, r_max: 5.0 }).0;
+    assert_eq!(ring["geometry"]["coordinates"].as_array().unwrap().len(), 2);
+    let ring = mgx_region_to_geojson(RegionPSQL::Ring { lon: 0.0, lat: 90.0, r_min: 2.0, r_max: 5.0 }).0;
+    assert_eq!(ring["geometry"]["type"], "Polygon");
+    assert_eq!(ring["geometry"]["coordinates"].as_array().unwrap().len(), 1);
+
+    let zone = RegionPSQL::Zone { lon_min: 170.0, lat_min: -10.0, lon_max: 190.0, lat_max: 10.0 };
+    assert!(mgx_region_to_wkt(zone).starts_with("MULTIPOLYGON((("));
+    assert!(mgx_region_to_wkt(RegionPSQL::Box { lon: 10.0, lat: 20.0, a: 2.0, b: 1.0, pa: 30.0 }).starts_with("POLYGON(("));
+  }
+
+  #[cfg(feature = "postgis")]
+  #[pg_test]
+  fn test_moc_from_wkt() {
+    use crate::postgis::{parse_wkt_polygons, moc_from_wkt};
+    let polygons = parse_wkt_polygons("SRID=4326;MULTIPOLYGON(((10 20, 12 20, 11 22, 10 20)), ((100 -20, 104 -20, 102 -16, 100 -20), (101.5 -19, 102.5 -19, 102 -18, 101.5 -19)))").unwrap();
+    assert_eq!(polygons.len(), 2);
+    assert_eq!(polygons[0], vec![vec![(10.0, 20.0), (12.0, 20.0), (11.0, 22.0)]]);
+    assert_eq!(polygons[1].len(), 2);
+    assert!(parse_wkt_polygons("POLYGON EMPTY").unwrap().is_empty());
+    assert!(parse_wkt_polygons("LINESTRING(0 0, 1 1)").is_err());
+
+    let moc = moc_from_wkt("POLYGON((10 20, 12 20, 11 22, 10 20))", 10).unwrap();
+    assert!(moc.is_in(11f64.to_radians(), 20.5f64.to_radians()));
+    assert!(!moc.is_in(50f64.to_radians(), 20.5f64.to_radians()));
+  }
+
+  #[cfg(feature = "pgsphere")]
+  #[pg_test]
+  fn test_pgsphere_text() {
+    use crate::pgsphere::*;
+    use crate::region::RegionPSQL;
+    let coo = mgx_coo_from_spoint_text("(10.5d , -20d 30m 0s)");
+    assert!((coo.lon_rad.to_degrees() - 10.5).abs() < 1e-12);
+    assert!((coo.lat_rad.to_degrees() + 20.5).abs() < 1e-12);
+    let coo = mgx_coo_from_spoint_text("(1h 0m 0s , 0.1)");
+    assert!((coo.lon_rad.to_degrees() - 15.0).abs() < 1e-12);
+    assert!((coo.lat_rad - 0.1).abs() < 1e-15);
+
+    let circle = mgx_region_from_scircle_text("<(0.1 , 0.2) , 0.05>");
+    let round_trip = mgx_region_from_scircle_text(&mgx_region_to_pgsphere_text(circle.clone()));
+    match (circle, round_trip) {
+      (RegionPSQL::Cone { lon, radius, .. }, RegionPSQL::Cone { lon: lon2, radius: radius2, .. }) => {
+        assert!((lon - 0.1f64.to_degrees()).abs() < 1e-12);
+        assert!((lon - lon2).abs() < 1e-12 && (radius - radius2).abs() < 1e-12);
+      }
+      _ => panic!("Cone expected"),
+    }
+
+    let polygon = mgx_region_from_spoly_text("{(0.1 , 0.2),(0.15 , 0.2),(0.12 , 0.25)}");
+    assert_eq!(mgx_region_to_pgsphere_text(polygon), "{(0.1 , 0.2),(0.15 , 0.2),(0.12 , 0.25)}");
+  }
+
+  #[pg_test]
+  fn test_healpix_index_table() {
+    Spi::run("CREATE TABLE stars (id bigint, ra double precision, dec double precision)").unwrap();
+    Spi::run("INSERT INTO stars VALUES (1, 10.68, 41.27), (2, 83.82, -5.39)").unwrap();
+    Spi::run("SELECT mgx_healpix_index_table('stars', 'ra', 'dec', 12, cluster => true)").unwrap();
+
+    let hash = Spi::get_one::<i64>("SELECT healpix_hash FROM stars WHERE id = 1").unwrap();
+    assert_eq!(hash, Some(crate::mgx_hash(12, 10.68, 41.27)));
+    let depth = Spi::get_one::<i32>("SELECT depth FROM mgx_indexed_tables WHERE table_name = 'stars'::regclass").unwrap();
+    assert_eq!(depth, Some(12));
+    let n_indexes = Spi::get_one::<i64>("SELECT count(*) FROM pg_indexes WHERE tablename = 'stars'").unwrap();
+    assert_eq!(n_indexes, Some(2));
+    // The recorded index names are the ones generated by Postgres
+    let registered = Spi::get_one::<i64>(
+      "SELECT count(*) FROM mgx_indexed_tables t JOIN pg_indexes i ON i.indexname IN (t.hash_index, t.range_index)
+       WHERE t.table_name = 'stars'::regclass AND i.tablename = 'stars'"
+    ).unwrap();
+    assert_eq!(registered, Some(2));
+
+    // Table with a name of 63 bytes in another schema : no name truncation or collision
+    let long_name = "s".repeat(63);
+    Spi::run("CREATE SCHEMA other").unwrap();
+    Spi::run(&format!("CREATE TABLE other.{long_name} (ra double precision, dec double precision)")).unwrap();
+    Spi::run(&format!("SELECT mgx_healpix_index_table('other.{long_name}', 'ra', 'dec', 12)")).unwrap();
+    let n_indexes = Spi::get_one::<i64>(&format!("SELECT count(*) FROM pg_indexes WHERE schemaname = 'other' AND tablename = '{long_name}'")).unwrap();
+    assert_eq!(n_indexes, Some(2));
+  }
+
+  #[pg_test]
+  fn test_indexed_tables_registration() {
+    use crate::catalog::*;
+    Spi::run("CREATE TABLE gaia (source_id bigint, ra double precision, dec double precision)").unwrap();
+    assert_eq!(lookup_indexed_table("gaia"), None);
+
+    mgx_register_indexed_table("gaia", "ra", "dec", 29, None, "icrs", Some("gaia_hash_idx"), None);
+    let table = lookup_indexed_table("gaia").unwrap();
+    assert_eq!(table.frame, "ICRS");
+    assert_eq!(table.hash_index.as_deref(), Some("gaia_hash_idx"));
+    assert_eq!(table.hash_expression(), "mgx_hash(29, \"ra\", \"dec\")");
+
+    assert!(mgx_unregister_indexed_table("gaia"));
+    assert!(!mgx_unregister_indexed_table("gaia"));
+  }
+
+  #[pg_test]
+  fn test_cone_search() {
+    Spi::run("CREATE TABLE targets (id bigint, ra double precision, dec double precision)").unwrap();
+    Spi::run("INSERT INTO targets SELECT i, (i * 7.3) % 360, ((i * 3.1) % 180) - 90 FROM generate_series(1, 2000) i").unwrap();
+    Spi::run("SELECT mgx_healpix_index_table('targets', 'ra', 'dec', 12)").unwrap();
+
+    let (lon, lat, radius) = (100.0, 10.0, 15.0);
+    let expected = Spi::get_one::<i64>(&format!(
+      "SELECT count(*) FROM targets WHERE mgx_adql_distance({lon}, {lat}, ra, dec) <= {radius}"
+    )).unwrap();
+    let found = Spi::get_one::<i64>(&format!(
+      "SELECT count(*) FROM mgx_cone_search_typed(NULL::targets, {lon}, {lat}, {radius})"
+    )).unwrap();
+    assert!(expected.unwrap() > 0);
+    assert_eq!(found, expected);
+
+    // A coverage forced at the depth of the table is degraded to bound the number of range scans
+    Spi::run("SET mogipix.coverage_depth = 12").unwrap();
+    let query = crate::search::mgx_cone_search_query("targets", lon, lat, radius, "icrs");
+    assert!(query.matches("UNION ALL").count() < crate::search::MAX_RANGE_SCANS);
+    let found = Spi::get_one::<i64>(&format!(
+      "SELECT count(*) FROM mgx_cone_search_typed(NULL::targets, {lon}, {lat}, {radius})"
+    )).unwrap();
+    assert_eq!(found, expected);
+  }
+
+  #[pg_test]
+  fn test_region_search_and_crossmatch() {
+    Spi::run("CREATE TABLE targets (id bigint, ra double precision, dec double precision)").unwrap();
+    Spi::run("INSERT INTO targets SELECT i, (i * 7.3) % 360, ((i * 3.1) % 180) - 90 FROM generate_series(1, 2000) i").unwrap();
+    Spi::run("SELECT mgx_healpix_index_table('targets', 'ra', 'dec', 12)").unwrap();
+    Spi::run("CREATE TABLE sources (id bigint, ra double precision, dec double precision)").unwrap();
+    Spi::run("INSERT INTO sources SELECT i, ((i * 7.3) % 360) + 0.001, ((i * 3.1) % 180) - 90 FROM generate_series(1, 2000, 3) i").unwrap();
+    Spi::run("SELECT mgx_register_indexed_table('sources', 'ra', 'dec', 10)").unwrap();
+
+    let region = "mgx_region_box(100, 10, 20, 10, 30)";
+    let expected = Spi::get_one::<i64>(&format!("SELECT count(*) FROM targets WHERE mgx_region_contains({region}, ra, dec)")).unwrap();
+    let found = Spi::get_one::<i64>(&format!("SELECT count(*) FROM mgx_region_search_typed(NULL::targets, {region})")).unwrap();
+    assert!(expected.unwrap() > 0);
+    assert_eq!(found, expected);
+
+    let expected = Spi::get_one::<i64>(
+      "SELECT count(*) FROM targets a, sources b WHERE mgx_adql_distance(a.ra, a.dec, b.ra, b.dec) <= 0.01"
+    ).unwrap();
+    let found = Spi::get_one::<i64>(
+      "SELECT count(*) FROM mgx_crossmatch('targets', 'sources', 0.01)
+       AS t(id_a bigint, ra_a float8, dec_a float8, hash_a bigint, id_b bigint, ra_b float8, dec_b float8, distance float8)"
+    ).unwrap();
+    assert!(expected.unwrap() > 0);
+    assert_eq!(found, expected);
+
+    // The ranges of the crossmatch are at the depth of the table
+    let ranges: Vec<(i64, i64)> = crate::search::mgx_crossmatch_ranges(100.0, 10.0, 0.01, 10).collect();
+    let hash = crate::mgx_hash(10, 100.0, 10.0);
+    assert!(!ranges.is_empty());
+    assert!(ranges.iter().all(|(start, end)| start < end && *end <= cdshealpix::nested::n_hash(10) as i64));
+    assert!(ranges.iter().any(|(start, end)| (*start..*end).contains(&hash)));
+
+    // The frames are checked
+    let table = crate::catalog::registered_table("targets");
+    assert_eq!(table.frame, "ICRS");
+    assert!(crate::search::mgx_cone_search_query("targets", 100.0, 10.0, 1.0, "icrs").contains("SELECT t.*"));
+  }
+
+  #[pg_test]
+  fn test_optimal_coverage_depth() {
+    use crate::region::mgx_region_cone;
+    let cone = mgx_region_cone(13.158329, -72.80028, 0.01);
+    let start = cone.characteristic_depth() as i32;
+    // More rows to recheck -> deeper coverage
+    let few = crate::region::mgx_optimal_coverage_depth(cone.clone(), 1_000, 29);
+    let many = crate::region::mgx_optimal_coverage_depth(cone.clone(), 1_000_000_000, 29);
+    assert!(start <= few && few <= many && many <= 29);
+    assert!(crate::region::mgx_optimal_coverage_depth(cone.clone(), 1_000_000_000, 8) <= 8);
+
+    Spi::run("SET mogipix.coverage_depth = 11").unwrap();
+    assert_eq!(crate::region::mgx_optimal_coverage_depth(cone.clone(), 1_000, 29), 11);
+    assert_eq!(crate::region::mgx_optimal_coverage_depth(cone, 1_000, 8), 8);
+    Spi::run("RESET mogipix.coverage_depth").unwrap();
+  }
+
+  #[pg_test]
+  fn test_gucs() {
+    let hash_deg = crate::mgx_hash(12, 10.68, 41.27);
+    let hash_unit = |depth: i32, lon: f64, lat: f64| Spi::get_one::<i64>(
+      &format!("SELECT mgx_hash_unit({depth}, {lon}, {lat})")
+    ).unwrap().unwrap();
+    Spi::run("SET mogipix.angle_unit = 'arcsec'").unwrap();
+    assert_eq!(hash_unit(12, 10.68 * 3600.0, 41.27 * 3600.0), hash_deg);
+    // The immutable functions keep taking degrees
+    assert_eq!(crate::mgx_hash(12, 10.68, 41.27), hash_deg);
+    assert_eq!(
+      Spi::get_one::<crate::region::RegionPSQL>("SELECT mgx_region_cone_unit(36, 72, 18)").unwrap(),
+      Some(crate::region::RegionPSQL::Cone { lon: 0.01, lat: 0.02, radius: 0.005 })
+    );
+    Spi::run("SET mogipix.angle_unit = 'rad'").unwrap();
+    assert_eq!(hash_unit(12, 10.68f64.to_radians(), 41.27f64.to_radians()), hash_deg);
+    Spi::run("RESET mogipix.angle_unit").unwrap();
+
+    Spi::run("SET mogipix.default_depth = 12").unwrap();
+    assert_eq!(hash_unit(-1, 10.68, 41.27), hash_deg);
+    Spi::run("RESET mogipix.default_depth").unwrap();
+    assert_eq!(hash_unit(-1, 10.68, 41.27), crate::mgx_hash(29, 10.68, 41.27));
+
+    let cone = crate::region::mgx_region_cone(13.158329, -72.80028, 0.5);
+    let start = cone.characteristic_depth() as i32;
+    Spi::run("SET mogipix.coverage_delta_depth = 3").unwrap();
+    assert_eq!(crate::region::mgx_optimal_coverage_depth(cone.clone(), 1_000, 29), start + 3);
+    // mogipix.coverage_depth takes precedence
+    Spi::run("SET mogipix.coverage_depth = 5").unwrap();
+    assert_eq!(crate::region::mgx_optimal_coverage_depth(cone, 1_000, 29), 5);
+    Spi::run("RESET mogipix.coverage_depth").unwrap();
+    Spi::run("RESET mogipix.coverage_delta_depth").unwrap();
+
+    // Without the exact recheck, the searches accept all the points of the partial cells
+    Spi::run("CREATE TABLE grid AS SELECT 8.5 + i * 0.03 AS ra, 18.5 + j * 0.03 AS dec FROM generate_series(0, 99) i, generate_series(0, 99) j").unwrap();
+    Spi::run("SELECT mgx_healpix_index_table('grid', 'ra', 'dec', 12)").unwrap();
+    let count = || Spi::get_one::<i64>("SELECT count(*) FROM mgx_cone_search_typed(NULL::grid, 10, 20, 1)").unwrap().unwrap();
+    let exact = count();
+    Spi::run("SET mogipix.exact_recheck = off").unwrap();
+    assert!(count() > exact);
+    Spi::run("RESET mogipix.exact_recheck").unwrap();
+  }
+
+  #[pg_test]
+  fn test_moc_split() {
+    let cone = |lon: f64, lat: f64| mgx_moc_from_cone(lon, lat, 2.0, 8, 2, CellSelectionPSQL::All);
+    let two_cones = mgx_moc_or(cone(10.0, 20.0), cone(100.0, -20.0));
+    assert_eq!(mgx_moc_n_components(two_cones.clone(), false), 2);
+    assert_eq!(mgx_moc_n_components(cone(10.0, 20.0), true), 1);
+
+    let components: Vec<RangeMOCPSQL> = mgx_moc_split(two_cones.clone(), false).collect();
+    let total: f64 = components.iter().map(|c| c.sky_fraction()).sum();
+    assert!((total - two_cones.sky_fraction()).abs() < 1e-12);
+  }
+
+  #[pg_test]
+  fn test_coverage_recheck() {
+    let coverage = crate::predicates::cone_coverage(13.158329, -72.80028, 5.64323).unwrap();
+    assert!(coverage.contains(13.158329f64.to_radians(), -72.80028f64.to_radians()));
+    assert!(!coverage.contains(193.158329f64.to_radians(), 72.80028f64.to_radians()));
+    // Just outside the cone but in a partial cell of its coverage : rejected by the exact recheck
+    assert!(!coverage.contains(13.158329f64.to_radians(), (-72.80028f64 + 5.65).to_radians()));
+    assert!(!coverage.ranges().is_empty());
+  }
+
+  #[pg_test]
+  fn test_polygon_validation() {
+    use crate::polygon::PolygonPSQL;
+    use skyregion::SkyRegion;
+    let triangle_deg = vec![(10.0, 20.0), (12.0, 20.0), (11.0, 22.0)];
+    let from_deg = PolygonPSQL::from_deg(triangle_deg.clone(), false).unwrap();
+    let from_rad = PolygonPSQL::from_rad(triangle_deg.iter().map(|(lon, lat)| (lon.to_radians(), lat.to_radians())).collect(), false).unwrap();
+    assert_eq!(from_deg, from_rad);
+
+    // Same coverage and same containment whatever the unit used to build the polygon
+    let (lon, lat) = (11.0f64, 20.5f64);
+    assert!(from_deg.to_sky_region().unwrap().contains(lon.to_radians(), lat.to_radians()));
+    assert!(!matches!(from_deg.to_bmoc(10, false).test_coo(lon.to_radians(), lat.to_radians()), cdshealpix::nested::bmoc::Status::OUT));
+    assert!(crate::polygon::mgx_polygon_contains(std::ptr::null_mut(), from_deg.into(), lon, lat));
+
+    assert!(PolygonPSQL::from_deg(vec![(10.0, 20.0), (12.0, 20.0)], false).is_err());
+    assert!(PolygonPSQL::from_deg(vec![(10.0, 20.0), (12.0, 95.0), (11.0, 22.0)], false).is_err());
+    // Bow tie
+    assert!(PolygonPSQL::from_deg(vec![(10.0, 20.0), (12.0, 22.0), (12.0, 20.0), (10.0, 22.0)], false).is_err());
+    // Vertices spread over the whole equator
+    assert!(PolygonPSQL::from_deg(vec![(0.0, 0.0), (120.0, 0.0), (240.0, 0.0)], false).is_err());
+
+    // The text input and the stored datums are validated too
+    let input = Spi::get_one::<PolygonPSQL>(&format!(
+      r#"SELECT '{}'::PolygonPSQL"#, serde_json::to_string(&from_deg).unwrap()
+    )).unwrap().unwrap();
+    assert_eq!(input, from_deg);
+    assert!(serde_json::from_str::<PolygonPSQL>(r#"{"vertices_rad": [[0.1, 0.2], [0.2, 0.2]], "complement": false}"#).is_err());
+  }
+
+  #[pg_test]
+  fn test_region_from_stcs() {
+    use crate::region::{region_from_stcs, RegionPSQL};
+    assert_eq!(region_from_stcs("Circle ICRS 10.68 41.27 0.5"), Ok(RegionPSQL::Cone { lon: 10.68, lat: 41.27, radius: 0.5 }));
+    assert_eq!(
+      region_from_stcs("Polygon ICRS 10 20 12 20 11 22"),
+      Ok(RegionPSQL::Polygon(crate::polygon::mgx_polygon_from_deg(vec![10.0, 12.0, 11.0], vec![20.0, 20.0, 22.0], false)))
+    );
+    assert_eq!(
+      region_from_stcs("Box ICRS 10 20 4 2"),
+      Ok(RegionPSQL::Box { lon: 10.0, lat: 20.0, a: 2.0, b: 1.0, pa: 90.0 })
+    );
+    assert!(region_from_stcs("Circle FK5 10.68 41.27 0.5").is_ok());
+    assert!(region_from_stcs("Circle GALACTIC 10.68 41.27 0.5").is_err());
+    assert!(region_from_stcs("Circle ECLIPTIC 10.68 41.27 0.5").is_err());
+    assert!(region_from_stcs("Circle ICRS 10.68 41.27").is_err());
+    assert!(region_from_stcs("Spiral ICRS 10.68 41.27 0.5").is_err());
+
+    let cone = crate::region::mgx_region_cone(10.68, 41.27, 0.5);
+    assert!(cone.to_sky_region().is_ok());
+    assert!(cone.coverage().unwrap().contains(10.68f64.to_radians(), 41.27f64.to_radians()));
+
+    // Compound shapes
+    let cone = |lon: f64, lat: f64, radius: f64| RegionPSQL::Cone { lon, lat, radius };
+    assert_eq!(
+      region_from_stcs("Union ICRS (Circle 10 20 1 Circle 30 20 1)"),
+      Ok(RegionPSQL::Union(vec![cone(10.0, 20.0, 1.0), cone(30.0, 20.0, 1.0)]))
+    );
+    assert_eq!(
+      region_from_stcs("Intersection ICRS (Circle 10 20 5 Not (Circle 10 20 1))"),
+      Ok(RegionPSQL::Intersection(vec![cone(10.0, 20.0, 5.0), RegionPSQL::Not(Box::new(cone(10.0, 20.0, 1.0)))]))
+    );
+    assert!(region_from_stcs("Union GALACTIC (Circle 10 20 1 Circle 30 20 1)").is_err());
+
+    let annulus = region_from_stcs("Intersection ICRS (Circle 10 20 5 Not (Circle 10 20 1))").unwrap();
+    let coverage = annulus.coverage().unwrap();
+    assert!(coverage.contains(10.0f64.to_radians(), 23.0f64.to_radians()));
+    assert!(!coverage.contains(10.0f64.to_radians(), 20.0f64.to_radians()));
+    assert!(!coverage.contains(10.0f64.to_radians(), 26.0f64.to_radians()));
+    let moc = annulus.to_moc(10);
+    assert!(moc.is_in(10.0f64.to_radians(), 23.0f64.to_radians()));
+    assert!(!moc.is_in(10.0f64.to_radians(), 20.0f64.to_radians()));
+  }
+
+  #[pg_test]
+  fn test_compound_regions() {
+    use crate::adql::*;
+    use crate::export::mgx_region_to_geojson;
+    use crate::region::RegionPSQL;
+    let cone = |lon: f64, lat: f64, radius: f64| RegionPSQL::Cone { lon, lat, radius };
+    let not = |region: RegionPSQL| RegionPSQL::Not(Box::new(region));
+    // Same region as a ring
+    let annulus = RegionPSQL::Intersection(vec![cone(10.0, 20.0, 3.0), not(cone(10.0, 20.0, 0.5))]);
+    let ring = RegionPSQL::Ring { lon: 10.0, lat: 20.0, r_min: 0.5, r_max: 3.0 };
+    assert!((mgx_adql_area(annulus.clone()) / mgx_adql_area(ring) - 1.0).abs() < 1e-3);
+    assert_eq!(mgx_adql_intersects(mgx_adql_box(10.0, 20.0, 0.4, 0.4), annulus.clone()), 0);
+    assert_eq!(mgx_adql_contains_region(mgx_adql_box(10.0, 21.5, 0.4, 0.4), annulus.clone()), 1);
+    assert_eq!(mgx_adql_contains_region(mgx_adql_circle(10.0, 20.0, 1.0), annulus.clone()), 0);
+    let geojson = mgx_region_to_geojson(annulus).0;
+    assert_eq!(geojson["geometry"]["type"], "Polygon");
+    assert_eq!(geojson["geometry"]["coordinates"].as_array().unwrap().len(), 2);
+
+    // Two overlapping cones : the parts of the circles inside the other cone aren't boundaries
+    let union = RegionPSQL::Union(vec![cone(10.0, 0.0, 2.0), cone(12.0, 0.0, 2.0)]);
+    assert_eq!(mgx_adql_contains_region(mgx_adql_circle(11.0, 0.0, 1.5), union.clone()), 1);
+    assert_eq!(mgx_adql_contains_region(mgx_adql_circle(11.0, 0.0, 3.5), union.clone()), 0);
+    assert_eq!(mgx_adql_intersects(mgx_adql_circle(15.0, 0.0, 0.5), union.clone()), 0);
+    assert_eq!(mgx_adql_intersects(mgx_adql_circle(14.5, 0.0, 0.6), union.clone()), 1);
+    let geojson = mgx_region_to_geojson(union).0;
+    assert_eq!(geojson["geometry"]["type"], "Polygon");
+    assert_eq!(geojson["geometry"]["coordinates"].as_array().unwrap().len(), 1);
+    // Disjoint cones : empty intersection, two polygons for the union
+    let disjoint = vec![cone(10.0, 0.0, 1.0), cone(30.0, 0.0, 1.0)];
+    assert_eq!(mgx_adql_intersects(RegionPSQL::Intersection(disjoint.clone()), mgx_adql_circle(0.0, 0.0, 90.0)), 0);
+    assert_eq!(mgx_region_to_geojson(RegionPSQL::Union(disjoint)).0["geometry"]["type"], "MultiPolygon");
+
+    // Complement of a cone : whole sky but the cone
+    let outside = not(cone(10.0, 20.0, 1.0));
+    assert!((mgx_adql_area(outside.clone()) + mgx_adql_area(cone(10.0, 20.0, 1.0)) - 41252.96).abs() < 0.01);
+    assert_eq!(mgx_adql_contains_region(mgx_adql_circle(40.0, 20.0, 1.0), outside.clone()), 1);
+    assert_eq!(mgx_adql_intersects(mgx_adql_circle(10.0, 20.0, 0.5), outside), 0);
+  }
+
+  #[pg_test]
+  fn test_moc_from_stcs() {
+    let circle = crate::moc::moc_from_stcs("Circle ICRS 10.68 41.27 0.5", 10).unwrap();
+    assert!(circle.is_in(10.68f64.to_radians(), 41.27f64.to_radians()));
+    assert!(!circle.is_in(190.68f64.to_radians(), -41.27f64.to_radians()));
+
+    let union = crate::moc::moc_from_stcs("Union ICRS (Circle 10.68 41.27 0.5 Circle 190.68 -41.27 0.5)", 10).unwrap();
+    assert!(union.is_in(190.68f64.to_radians(), -41.27f64.to_radians()));
+
+    assert!(crate::moc::moc_from_stcs("Circle GALACTIC 10.68 41.27 0.5", 10).is_err());
+    assert!(crate::moc::moc_from_stcs("Circle ICRS 10.68", 10).is_err());
+  }
+
+  #[pg_test]
+  fn test_adql_functions() {
+    use crate::adql::*;
+    assert!((mgx_adql_distance_deg(10.0, 0.0, 20.0, 0.0) - 10.0).abs() < 1e-12);
+    assert!((mgx_adql_distance(mgx_adql_point(0.0, 90.0), mgx_adql_point(123.0, 0.0)) - 90.0).abs() < 1e-12);
+
+    // Full sky = 4 PI sr ~ 41252.96 deg2, so an hemisphere is ~ 20626.48 deg2
+    assert!((mgx_adql_area(mgx_adql_circle(0.0, 90.0, 90.0)) - 20626.48).abs() < 0.01);
+
+    let small = mgx_adql_circle(10.0, 20.0, 1.0);
+    let big = mgx_adql_circle(10.5, 20.0, 2.0);
+    let far = mgx_adql_circle(100.0, -20.0, 1.0);
+    assert_eq!(mgx_adql_contains_region(small.clone(), big.clone()), 1);
+    assert_eq!(mgx_adql_contains_region(big.clone(), small.clone()), 0);
+    assert_eq!(mgx_adql_intersects(small.clone(), far.clone()), 0);
+    assert_eq!(mgx_adql_intersects(mgx_adql_box(10.0, 20.0, 4.0, 2.0), small), 1);
+    assert_eq!(mgx_adql_contains(std::ptr::null_mut(), mgx_adql_point(100.0, -20.0), far.into()), 1);
+  }
+
+  #[pg_test]
+  fn test_adql_exact_geometry() {
+    use crate::adql::*;
+    use crate::region::RegionPSQL;
+    // Octant : 1/8 of the sky whatever the order of the vertices
+    let octant = mgx_adql_area(mgx_adql_polygon(vec![0.0, 0.0, 90.0, 0.0, 0.0, 90.0]));
+    assert!((octant - 41252.96 / 8.0).abs() < 0.01);
+    assert!((mgx_adql_area(mgx_adql_polygon(vec![0.0, 90.0, 90.0, 0.0, 0.0, 0.0])) - octant).abs() < 1e-6);
+    // Small box at the equator ~ its planar area, elliptical cone of equal axes = cone
+    assert!((mgx_adql_area(mgx_adql_box(10.0, 0.0, 2.0, 1.0)) - 2.0).abs() < 1e-3);
+    let ellipse = RegionPSQL::EllipticalCone { lon: 10.0, lat: 20.0, a: 5.0, b: 5.0, pa: 30.0 };
+    assert!((mgx_adql_area(ellipse) - mgx_adql_area(mgx_adql_circle(10.0, 20.0, 5.0))).abs() < 1e-6);
+
+    // A circle 0.1 deg away from a polygon, closer than a cell of the coverage depth
+    let square = mgx_adql_polygon(vec![10.0, 0.0, 11.0, 0.0, 11.0, 1.0, 10.0, 1.0]);
+    assert_eq!(mgx_adql_intersects(square.clone(), mgx_adql_circle(11.6, 0.5, 0.5)), 0);
+    assert_eq!(mgx_adql_intersects(square.clone(), mgx_adql_circle(11.6, 0.5, 0.65)), 1);
+    assert_eq!(mgx_adql_contains_region(mgx_adql_circle(10.5, 0.5, 0.45), square.clone()), 1);
+    assert_eq!(mgx_adql_contains_region(mgx_adql_circle(10.5, 0.5, 0.55), square.clone()), 0);
+    assert_eq!(mgx_adql_contains_region(square.clone(), mgx_adql_circle(10.5, 0.5, 0.75)), 1);
+
+    // Box in the hole of a ring, then in the ring
+    let ring = RegionPSQL::Ring { lon: 10.0, lat: 20.0, r_min: 0.5, r_max: 3.0 };
+    assert_eq!(mgx_adql_intersects(mgx_adql_box(10.0, 20.0, 0.4, 0.4), ring.clone()), 0);
+    assert_eq!(mgx_adql_contains_region(mgx_adql_box(10.0, 20.0, 0.4, 0.4), ring.clone()), 0);
+    assert_eq!(mgx_adql_contains_region(mgx_adql_box(10.0, 21.5, 0.4, 0.4), ring.clone()), 1);
+    // A cone containing the hole isn't in the ring
+    assert_eq!(mgx_adql_contains_region(mgx_adql_circle(10.0, 20.0, 1.0), ring.clone()), 0);
+
+    // Zone crossing lon = 0
+    let zone = RegionPSQL::Zone { lon_min: 350.0, lat_min: -5.0, lon_max: 10.0, lat_max: 5.0 };
+    assert_eq!(mgx_adql_contains_region(mgx_adql_circle(0.0, 0.0, 4.0), zone.clone()), 1);
+    assert_eq!(mgx_adql_contains_region(mgx_adql_circle(0.0, 0.0, 6.0), zone.clone()), 0);
+    assert_eq!(mgx_adql_intersects(mgx_adql_circle(180.0, 0.0, 6.0), zone), 0);
+  }
 }
\ No newline at end of file